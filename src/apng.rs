@@ -0,0 +1,847 @@
+use std::{ops::Range, time::Duration};
+use crate::chunk::Chunk;
+use crate::decoder::decode_image;
+use crate::encoder::{encode_image_data, split_data, EncodeOptions, FilterStrategy};
+use crate::image::Image;
+use crate::known_chunk::{Actl, BlendOp, ColorType, DisposeOp, Fctl, Ihdr, Plte, Trns};
+use crate::png::Png;
+use crate::Result;
+
+/// The chunks making up one animation frame: its fcTL and the IDAT or fdAT
+/// chunks holding its image data, as indexes into `Png::chunks()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameChunks {
+    pub control: Fctl,
+    pub control_index: usize,
+    pub data_indices: Vec<usize>,
+}
+
+/// A validated view of the animation chunks in a `Png`.
+pub struct Apng<'a> {
+    png: &'a Png,
+    header: Ihdr,
+    control: Actl,
+    frames: Vec<FrameChunks>,
+    default_image_is_frame: bool,
+}
+
+impl<'a> Apng<'a> {
+    pub fn from_png(png: &'a Png) -> Result<Apng<'a>> {
+        let header = png.ihdr()?;
+        let control = match png.chunk_by_type("acTL") {
+            Some(chunk) => Actl::parse(chunk.data())?,
+            None => return Err("Not an animated PNG: missing acTL chunk".into())
+        };
+
+        let mut frames: Vec<FrameChunks> = Vec::new();
+        let mut next_sequence = 0;
+        let mut seen_idat = false;
+        let mut default_image_is_frame = false;
+
+        for (indx, chunk) in png.chunks().iter().enumerate() {
+            match &chunk.chunk_type().bytes() {
+                b"acTL" if seen_idat => return Err("acTL must come before the first IDAT".into()),
+                b"fcTL" => {
+                    let fctl = Fctl::parse(chunk.data())?;
+                    check_sequence(fctl.sequence_number, &mut next_sequence)?;
+                    if fctl.x_offset as u64 + fctl.width as u64 > header.width as u64
+                        || fctl.y_offset as u64 + fctl.height as u64 > header.height as u64 {
+                        return Err(format!("Frame {} extends outside the {}x{} canvas", frames.len(), header.width, header.height).into());
+                    }
+                    if frames.is_empty() {
+                        let covers_canvas = fctl.x_offset == 0 && fctl.y_offset == 0
+                            && fctl.width == header.width && fctl.height == header.height;
+                        if !covers_canvas {
+                            return Err("The first frame must cover the whole canvas".into());
+                        }
+                        default_image_is_frame = !seen_idat;
+                    } else if !seen_idat {
+                        return Err("Only one fcTL may come before the first IDAT".into());
+                    }
+                    frames.push(FrameChunks { control: fctl, control_index: indx, data_indices: Vec::new() });
+                },
+                b"IDAT" => {
+                    seen_idat = true;
+                    if default_image_is_frame && frames.len() == 1 {
+                        frames[0].data_indices.push(indx);
+                    }
+                },
+                b"fdAT" => {
+                    if chunk.data().len() < 4 {
+                        return Err("fdAT is missing its sequence number".into());
+                    }
+                    let sequence = u32::from_be_bytes(chunk.data()[0..4].try_into().unwrap());
+                    check_sequence(sequence, &mut next_sequence)?;
+                    let owns_fdat = frames.len() > 1 || (frames.len() == 1 && !default_image_is_frame);
+                    if !owns_fdat || !seen_idat {
+                        return Err(format!("fdAT with sequence {} does not follow an fcTL", sequence).into());
+                    }
+                    frames.last_mut().unwrap().data_indices.push(indx);
+                },
+                _ => {}
+            }
+        }
+
+        if frames.len() != control.num_frames as usize {
+            return Err(format!("acTL declares {} frames but {} were found", control.num_frames, frames.len()).into());
+        }
+        if let Some(empty) = frames.iter().position(|frame| frame.data_indices.is_empty()) {
+            return Err(format!("Frame {} has no image data", empty).into());
+        }
+
+        Ok(Apng { png, header, control, frames, default_image_is_frame })
+    }
+
+    pub fn control(&self) -> &Actl {
+        &self.control
+    }
+
+    pub fn frame_chunks(&self) -> &[FrameChunks] {
+        &self.frames
+    }
+
+    /// False when the default image is hidden from the animation.
+    pub fn default_image_is_frame(&self) -> bool {
+        self.default_image_is_frame
+    }
+
+    /// The compressed image data of frame `index`, with fdAT sequence numbers stripped.
+    pub fn frame_data(&self, index: usize) -> Vec<u8> {
+        self.frames[index].data_indices.iter()
+            .flat_map(|&i| {
+                let chunk = &self.png.chunks()[i];
+                let skip = if chunk.chunk_type().bytes() == *b"fdAT" { 4 } else { 0 };
+                chunk.data()[skip..].to_vec()
+            })
+            .collect()
+    }
+
+    /// Decodes and composites each frame onto the canvas in turn.
+    pub fn frames(&self) -> Result<Frames<'_>> {
+        Ok(Frames {
+            apng: self,
+            palette: self.png.palette()?,
+            transparency: self.png.transparency()?,
+            // Allocated once the first frame, which covers the whole
+            // canvas, has decoded, so a bogus IHDR size can't force it.
+            canvas: Image::blank(0, 0),
+            index: 0,
+        })
+    }
+}
+
+fn check_sequence(sequence: u32, next_sequence: &mut u32) -> Result<()> {
+    if sequence != *next_sequence {
+        return Err(format!("Expected sequence number {}, found {}", next_sequence, sequence).into());
+    }
+    *next_sequence += 1;
+    Ok(())
+}
+
+/// A fully composited animation frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    image: Image,
+    delay_num: u16,
+    delay_den: u16,
+}
+
+impl Frame {
+    pub fn new(image: Image, delay_num: u16, delay_den: u16) -> Frame {
+        Frame { image, delay_num, delay_den }
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn delay_num(&self) -> u16 {
+        self.delay_num
+    }
+
+    pub fn delay_den(&self) -> u16 {
+        self.delay_den
+    }
+
+    pub fn delay(&self) -> Duration {
+        let den = if self.delay_den == 0 { 100 } else { self.delay_den };
+        Duration::from_secs_f64(self.delay_num as f64 / den as f64)
+    }
+}
+
+pub struct Frames<'a> {
+    apng: &'a Apng<'a>,
+    palette: Option<Plte>,
+    transparency: Option<Trns>,
+    canvas: Image,
+    index: usize,
+}
+
+impl Frames<'_> {
+    fn render(&mut self) -> Result<Frame> {
+        let control = &self.apng.frames[self.index].control;
+        let mut header = self.apng.header.clone();
+        header.width = control.width;
+        header.height = control.height;
+        let image = decode_image(&header, self.palette.as_ref(), self.transparency.as_ref(), &self.apng.frame_data(self.index))?;
+        if self.index == 0 {
+            Image::check_size(self.apng.header.width, self.apng.header.height)?;
+            self.canvas = Image::blank(self.apng.header.width, self.apng.header.height);
+        }
+
+        let (x0, y0) = (control.x_offset, control.y_offset);
+        let saved = match control.dispose_op {
+            DisposeOp::Previous => Some(self.canvas.crop(x0, y0, control.width, control.height)?),
+            _ => None
+        };
+
+        for y in 0..control.height {
+            for x in 0..control.width {
+                let src = image.pixel(x, y);
+                let pixel = match control.blend_op {
+                    BlendOp::Source => src,
+                    BlendOp::Over => blend_over(self.canvas.pixel(x0 + x, y0 + y), src),
+                };
+                self.canvas.set_pixel(x0 + x, y0 + y, pixel);
+            }
+        }
+        let frame = Frame::new(self.canvas.clone(), control.delay_num, control.delay_den);
+
+        // A first frame disposed to "previous" is cleared instead, as there is no previous canvas
+        match (control.dispose_op, saved) {
+            (DisposeOp::Previous, Some(saved)) if self.index > 0 => {
+                for y in 0..control.height {
+                    for x in 0..control.width {
+                        self.canvas.set_pixel(x0 + x, y0 + y, saved.pixel(x, y));
+                    }
+                }
+            },
+            (DisposeOp::None, _) => {},
+            _ => for y in 0..control.height {
+                for x in 0..control.width {
+                    self.canvas.set_pixel(x0 + x, y0 + y, [0; 4]);
+                }
+            }
+        }
+        Ok(frame)
+    }
+}
+
+impl Iterator for Frames<'_> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.apng.frames.len() {
+            return None;
+        }
+        let frame = self.render();
+        // Stop after an error, as the canvas no longer reflects the animation
+        self.index = if frame.is_ok() { self.index + 1 } else { self.apng.frames.len() };
+        Some(frame)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnimationOptions {
+    /// Zero loops forever.
+    pub num_plays: u32,
+    /// Store each frame after the first as only the region that changed.
+    pub crop_to_changes: bool,
+    /// A default image shown by viewers without APNG support, kept out of the animation.
+    pub hidden_default: Option<Image>,
+}
+
+/// Assembles full-canvas frames into an animated PNG.
+pub fn assemble(frames: &[Frame], options: &AnimationOptions) -> Result<Png> {
+    let first = match frames.first() {
+        Some(first) => first.image(),
+        None => return Err("An animation needs at least one frame".into())
+    };
+    let (width, height) = (first.width(), first.height());
+    let all_images = frames.iter().map(|frame| frame.image()).chain(options.hidden_default.as_ref());
+    if let Some(image) = all_images.clone().find(|image| (image.width(), image.height()) != (width, height)) {
+        return Err(format!("Frame size {}x{} does not match the {}x{} canvas", image.width(), image.height(), width, height).into());
+    }
+    let mut encode_options = EncodeOptions::for_image(first);
+    if all_images.clone().any(|image| !image.fits_in_8_bits()) {
+        encode_options.bit_depth = 16;
+    }
+    let header = encode_options.header(width, height);
+
+    let mut chunks = vec![
+        Chunk::from_type_str("IHDR", header.to_bytes())?,
+        Chunk::from_type_str("acTL", Actl { num_frames: frames.len() as u32, num_plays: options.num_plays }.to_bytes())?,
+    ];
+    if let Some(hidden) = &options.hidden_default {
+        for data in split_data(&encode_image_data(hidden, &encode_options)?) {
+            chunks.push(Chunk::from_type_str("IDAT", data)?);
+        }
+    }
+
+    let mut sequence_number = 0;
+    for (indx, frame) in frames.iter().enumerate() {
+        let (x_offset, y_offset, region) = if options.crop_to_changes && indx > 0 {
+            let (x, y, w, h) = changed_region(frames[indx - 1].image(), frame.image());
+            (x, y, frame.image().crop(x, y, w, h)?)
+        } else {
+            (0, 0, frame.image().clone())
+        };
+        let control = Fctl {
+            sequence_number,
+            width: region.width(),
+            height: region.height(),
+            x_offset,
+            y_offset,
+            delay_num: frame.delay_num(),
+            delay_den: frame.delay_den(),
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        chunks.push(Chunk::from_type_str("fcTL", control.to_bytes())?);
+        sequence_number += 1;
+
+        let data = encode_image_data(&region, &encode_options)?;
+        if indx == 0 && options.hidden_default.is_none() {
+            for data in split_data(&data) {
+                chunks.push(Chunk::from_type_str("IDAT", data)?);
+            }
+        } else {
+            for piece in split_data(&data) {
+                chunks.push(Chunk::from_type_str("fdAT", sequence_number.to_be_bytes().into_iter().chain(piece).collect())?);
+                sequence_number += 1;
+            }
+        }
+    }
+    chunks.push(Chunk::from_type_str("IEND", Vec::new())?);
+    Ok(Png::from_chunks(chunks))
+}
+
+/// The bounding box (x, y, width, height) of the pixels that differ between
+/// two same-sized images, or a single pixel when nothing changed.
+pub fn changed_region(previous: &Image, next: &Image) -> (u32, u32, u32, u32) {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for y in 0..next.height() {
+        for x in 0..next.width() {
+            if previous.pixel(x, y) != next.pixel(x, y) {
+                bounds = Some(match bounds {
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y)
+                });
+            }
+        }
+    }
+    match bounds {
+        Some((x0, y0, x1, y1)) => (x0, y0, x1 - x0 + 1, y1 - y0 + 1),
+        None => (0, 0, 1, 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EditFrame {
+    original: usize,
+    control: Fctl,
+    data: Vec<u8>,
+}
+
+/// Edits the frame list of an animation in terms of its chunks. The
+/// compressed data of a frame is reused as-is unless the edit changed what
+/// it would be composited onto, in which case only that frame is re-encoded
+/// from its original appearance. Redrawn frames are RGBA, so an animation
+/// stored any other way, or interlaced, has every frame re-encoded as RGBA.
+pub struct AnimationEditor<'a> {
+    png: &'a Png,
+    header: Ihdr,
+    num_plays: u32,
+    default_image_is_frame: bool,
+    head: Vec<Chunk>,
+    tail: Vec<Chunk>,
+    original: Vec<Fctl>,
+    frames: Vec<EditFrame>,
+}
+
+impl<'a> AnimationEditor<'a> {
+    const ANIMATION_TYPES: [&'static [u8; 4]; 4] = [b"acTL", b"fcTL", b"fdAT", b"IDAT"];
+    /// Chunks describing how the pixels were stored, dropped when the
+    /// animation is re-encoded as RGBA.
+    const LAYOUT_TYPES: [&'static [u8; 4]; 5] = [b"PLTE", b"tRNS", b"sBIT", b"bKGD", b"hIST"];
+
+    pub fn new(png: &'a Png) -> Result<AnimationEditor<'a>> {
+        let apng = Apng::from_png(png)?;
+        let first_idat = png.chunks().iter()
+            .position(|chunk| chunk.chunk_type().bytes() == *b"IDAT")
+            .unwrap_or(png.chunks().len());
+        let other_chunks = |chunks: &[Chunk]| chunks.iter()
+            .filter(|chunk| !AnimationEditor::ANIMATION_TYPES.contains(&&chunk.chunk_type().bytes()))
+            .cloned()
+            .collect::<Vec<Chunk>>();
+        let frames = apng.frame_chunks().iter().enumerate()
+            .map(|(indx, frame)| EditFrame { original: indx, control: frame.control.clone(), data: apng.frame_data(indx) })
+            .collect::<Vec<EditFrame>>();
+
+        Ok(AnimationEditor {
+            png,
+            header: apng.header.clone(),
+            num_plays: apng.control.num_plays,
+            default_image_is_frame: apng.default_image_is_frame,
+            head: other_chunks(&png.chunks()[..first_idat]),
+            tail: other_chunks(&png.chunks()[first_idat..]),
+            original: frames.iter().map(|frame| frame.control.clone()).collect(),
+            frames,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn set_num_plays(&mut self, num_plays: u32) {
+        self.num_plays = num_plays;
+    }
+
+    pub fn set_delay(&mut self, frames: Range<usize>, delay_num: u16, delay_den: u16) -> Result<()> {
+        self.check_range(&frames)?;
+        for frame in &mut self.frames[frames] {
+            frame.control.delay_num = delay_num;
+            frame.control.delay_den = delay_den;
+        }
+        Ok(())
+    }
+
+    pub fn remove_frames(&mut self, frames: Range<usize>) -> Result<()> {
+        self.check_range(&frames)?;
+        if frames.len() == self.frames.len() {
+            return Err("Cannot remove every frame of an animation".into());
+        }
+        self.frames.drain(frames);
+        Ok(())
+    }
+
+    pub fn reverse(&mut self) {
+        self.frames.reverse();
+    }
+
+    fn check_range(&self, frames: &Range<usize>) -> Result<()> {
+        if frames.start >= frames.end || frames.end > self.frames.len() {
+            return Err(format!("Invalid frame range {}..{} for {} frames", frames.start, frames.end, self.frames.len()).into());
+        }
+        Ok(())
+    }
+
+    /// Whether each frame, in its new position, still renders exactly as it did.
+    fn reusable(&self) -> Vec<bool> {
+        let mut reusable: Vec<bool> = Vec::with_capacity(self.frames.len());
+        for (indx, frame) in self.frames.iter().enumerate() {
+            let control = &self.original[frame.original];
+            let independent = control.blend_op == BlendOp::Source
+                && control.x_offset == 0 && control.y_offset == 0
+                && control.width == self.header.width && control.height == self.header.height;
+            let same_start = indx == 0 && frame.original == 0;
+            let same_predecessor = indx > 0 && frame.original > 0
+                && self.frames[indx - 1].original == frame.original - 1 && reusable[indx - 1];
+            reusable.push(independent || same_start || same_predecessor);
+        }
+        reusable
+    }
+
+    pub fn to_png(&self) -> Result<Png> {
+        let mut reusable = self.reusable();
+        // Compositing can leave pixels, such as areas cleared to transparent,
+        // that a palette, gray or RGB image can't hold, and redrawn frames
+        // aren't interlaced. All frames share the header, so redrawing any of
+        // them in such an animation means redrawing all of them.
+        let to_rgba = reusable.contains(&false)
+            && (self.header.color_type != ColorType::Rgba || self.header.interlace_method != 0);
+        if to_rgba {
+            reusable.fill(false);
+        }
+        let redraw: Vec<usize> = self.frames.iter().zip(&reusable)
+            .filter(|(_, reusable)| !**reusable)
+            .map(|(frame, _)| frame.original)
+            .collect();
+        let mut redrawn: Vec<Option<Image>> = vec![None; self.original.len()];
+        if !redraw.is_empty() {
+            let apng = Apng::from_png(self.png)?;
+            for (indx, frame) in apng.frames()?.enumerate() {
+                let frame = frame?;
+                if redraw.contains(&indx) {
+                    redrawn[indx] = Some(frame.image);
+                }
+                if indx >= *redraw.iter().max().unwrap() {
+                    break;
+                }
+            }
+        }
+        let options = EncodeOptions {
+            color_type: ColorType::Rgba,
+            bit_depth: if self.header.bit_depth == 16 { 16 } else { 8 },
+            filter: FilterStrategy::Adaptive,
+            compression_level: 6,
+            palette: None,
+        };
+
+        let mut chunks = if to_rgba { self.rgba_chunks(&self.head, &options)? } else { self.head.clone() };
+        chunks.push(Chunk::from_type_str("acTL", Actl { num_frames: self.frames.len() as u32, num_plays: self.num_plays }.to_bytes())?);
+        if !self.default_image_is_frame {
+            let data = if to_rgba { encode_image_data(&self.png.decode()?, &options)? } else { self.png.image_data() };
+            for data in split_data(&data) {
+                chunks.push(Chunk::from_type_str("IDAT", data)?);
+            }
+        }
+        let mut sequence_number = 0;
+        for (indx, frame) in self.frames.iter().enumerate() {
+            let (mut control, data) = match &redrawn[frame.original] {
+                Some(image) if !reusable[indx] => {
+                    let control = Fctl {
+                        width: self.header.width,
+                        height: self.header.height,
+                        x_offset: 0,
+                        y_offset: 0,
+                        dispose_op: DisposeOp::None,
+                        blend_op: BlendOp::Source,
+                        ..frame.control.clone()
+                    };
+                    (control, encode_image_data(image, &options)?)
+                },
+                _ => (frame.control.clone(), frame.data.clone())
+            };
+            control.sequence_number = sequence_number;
+            chunks.push(Chunk::from_type_str("fcTL", control.to_bytes())?);
+            sequence_number += 1;
+
+            if indx == 0 && self.default_image_is_frame {
+                for data in split_data(&data) {
+                    chunks.push(Chunk::from_type_str("IDAT", data)?);
+                }
+            } else {
+                for piece in split_data(&data) {
+                    chunks.push(Chunk::from_type_str("fdAT", sequence_number.to_be_bytes().into_iter().chain(piece).collect())?);
+                    sequence_number += 1;
+                }
+            }
+        }
+        chunks.extend(if to_rgba { self.rgba_chunks(&self.tail, &options)? } else { self.tail.clone() });
+        Ok(Png::from_chunks(chunks))
+    }
+
+    /// `chunks` with IHDR rewritten for `options` and the chunks that only
+    /// make sense for the original color type removed. A gray ICC profile
+    /// can't describe an RGB image, so iCCP goes too for gray animations.
+    fn rgba_chunks(&self, chunks: &[Chunk], options: &EncodeOptions) -> Result<Vec<Chunk>> {
+        let gray = matches!(self.header.color_type, ColorType::Grayscale | ColorType::GrayscaleAlpha);
+        let mut rgba = Vec::new();
+        for chunk in chunks {
+            match &chunk.chunk_type().bytes() {
+                b"IHDR" => rgba.push(Chunk::from_type_str("IHDR", options.header(self.header.width, self.header.height).to_bytes())?),
+                b"iCCP" if gray => {},
+                chunk_type if AnimationEditor::LAYOUT_TYPES.contains(&chunk_type) => {},
+                _ => rgba.push(chunk.clone())
+            }
+        }
+        Ok(rgba)
+    }
+}
+
+/// Composites `src` over `dst` with straight (non-premultiplied) alpha.
+pub fn blend_over(dst: [u16; 4], src: [u16; 4]) -> [u16; 4] {
+    const MAX: u64 = u16::MAX as u64;
+    let src_alpha = src[3] as u64;
+    if src_alpha == MAX {
+        return src;
+    }
+    if src_alpha == 0 {
+        return dst;
+    }
+    let dst_weight = dst[3] as u64 * (MAX - src_alpha);
+    let out_alpha = src_alpha * MAX + dst_weight;
+    let mut out = [0u16; 4];
+    for c in 0..3 {
+        out[c] = ((src[c] as u64 * src_alpha * MAX + dst[c] as u64 * dst_weight) / out_alpha) as u16;
+    }
+    out[3] = (out_alpha / MAX) as u16;
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+
+    fn rgba_data(width: usize, rgba: &[u8]) -> Vec<u8> {
+        let raw: Vec<u8> = rgba.chunks(width * 4).flat_map(|row| [0].into_iter().chain(row.iter().copied())).collect();
+        compress_to_vec_zlib(&raw, 6)
+    }
+
+    fn fctl(sequence_number: u32, width: u32, height: u32, x_offset: u32, y_offset: u32, dispose_op: DisposeOp, blend_op: BlendOp) -> Chunk {
+        let fctl = Fctl { sequence_number, width, height, x_offset, y_offset, delay_num: 1, delay_den: 10, dispose_op, blend_op };
+        Chunk::from_type_str("fcTL", fctl.to_bytes()).unwrap()
+    }
+
+    fn fdat(sequence_number: u32, frame_data: Vec<u8>) -> Chunk {
+        Chunk::from_type_str("fdAT", sequence_number.to_be_bytes().into_iter().chain(frame_data).collect()).unwrap()
+    }
+
+    fn header(num_frames: u32) -> Vec<Chunk> {
+        vec![
+            Chunk::from_type_str("IHDR", vec![0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]).unwrap(),
+            Chunk::from_type_str("acTL", Actl { num_frames, num_plays: 0 }.to_bytes()).unwrap(),
+        ]
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const HALF_BLUE: [u8; 4] = [0, 0, 255, 128];
+
+    fn testing_apng() -> Png {
+        let mut chunks = header(3);
+        chunks.push(fctl(0, 2, 2, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(Chunk::from_type_str("IDAT", rgba_data(2, &RED.repeat(4))).unwrap());
+        chunks.push(fctl(1, 1, 1, 1, 1, DisposeOp::Background, BlendOp::Over));
+        chunks.push(fdat(2, rgba_data(1, &HALF_BLUE)));
+        chunks.push(fctl(3, 1, 1, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(fdat(4, rgba_data(1, &[0, 0, 0, 0])));
+        chunks.push(Chunk::from_type_str("IEND", vec![]).unwrap());
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_apng_structure() {
+        let png = testing_apng();
+        let apng = Apng::from_png(&png).unwrap();
+        assert_eq!(apng.control().num_frames, 3);
+        assert!(apng.default_image_is_frame());
+        assert_eq!(apng.frame_chunks()[1].data_indices, vec![5]);
+    }
+
+    #[test]
+    fn test_frame_composition() {
+        let png = testing_apng();
+        let apng = Apng::from_png(&png).unwrap();
+        let frames: Vec<Frame> = apng.frames().unwrap().map(|f| f.unwrap()).collect();
+        assert_eq!(frames.len(), 3);
+
+        assert_eq!(frames[0].image().to_rgba8(), RED.repeat(4));
+        assert_eq!(frames[0].delay(), Duration::from_millis(100));
+
+        let blended = frames[1].image().pixel(1, 1);
+        assert_eq!(blended[3], u16::MAX);
+        assert!(blended[0] > 0 && blended[2] > 0);
+        assert_eq!(frames[1].image().pixel(0, 0), frames[0].image().pixel(0, 0));
+
+        // Frame 1 was disposed to the background, frame 2 replaced (0, 0) with transparency
+        assert_eq!(frames[2].image().pixel(1, 1), [0; 4]);
+        assert_eq!(frames[2].image().pixel(0, 0), [0; 4]);
+        assert_eq!(frames[2].image().to_rgba8()[4..8], RED);
+    }
+
+    #[test]
+    fn test_hidden_default_image() {
+        let mut chunks = header(1);
+        chunks.push(Chunk::from_type_str("IDAT", rgba_data(2, &RED.repeat(4))).unwrap());
+        chunks.push(fctl(0, 2, 2, 0, 0, DisposeOp::Previous, BlendOp::Source));
+        chunks.push(fdat(1, rgba_data(2, &HALF_BLUE.repeat(4))));
+        let png = Png::from_chunks(chunks);
+        let apng = Apng::from_png(&png).unwrap();
+        assert!(!apng.default_image_is_frame());
+        let frames: Vec<Frame> = apng.frames().unwrap().map(|f| f.unwrap()).collect();
+        assert_eq!(frames[0].image().to_rgba8(), HALF_BLUE.repeat(4));
+    }
+
+    #[test]
+    fn test_sequence_numbers_must_be_consecutive() {
+        let mut chunks = header(2);
+        chunks.push(fctl(0, 2, 2, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(Chunk::from_type_str("IDAT", rgba_data(2, &RED.repeat(4))).unwrap());
+        chunks.push(fctl(1, 1, 1, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(fdat(3, rgba_data(1, &RED)));
+        let png = Png::from_chunks(chunks);
+        assert!(Apng::from_png(&png).is_err());
+    }
+
+    #[test]
+    fn test_frame_count_must_match() {
+        let mut chunks = header(2);
+        chunks.push(fctl(0, 2, 2, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(Chunk::from_type_str("IDAT", rgba_data(2, &RED.repeat(4))).unwrap());
+        let png = Png::from_chunks(chunks);
+        assert!(Apng::from_png(&png).is_err());
+    }
+
+    #[test]
+    fn test_frame_outside_canvas() {
+        let mut chunks = header(2);
+        chunks.push(fctl(0, 2, 2, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(Chunk::from_type_str("IDAT", rgba_data(2, &RED.repeat(4))).unwrap());
+        chunks.push(fctl(1, 2, 2, 1, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(fdat(2, rgba_data(2, &RED.repeat(4))));
+        let png = Png::from_chunks(chunks);
+        assert!(Apng::from_png(&png).is_err());
+    }
+
+    fn solid(rgba: [u8; 4]) -> Image {
+        Image::from_rgba8(2, 2, &rgba.repeat(4)).unwrap()
+    }
+
+    #[test]
+    fn test_assemble_round_trip() {
+        let mut second = solid(RED);
+        second.set_pixel(1, 0, [0, 0, 65535, 65535]);
+        let frames = vec![Frame::new(solid(RED), 1, 10), Frame::new(second, 25, 100)];
+        let options = AnimationOptions { num_plays: 2, crop_to_changes: true, hidden_default: None };
+        let png = assemble(&frames, &options).unwrap();
+
+        let apng = Apng::from_png(&png).unwrap();
+        assert_eq!(apng.control().num_plays, 2);
+        assert!(apng.default_image_is_frame());
+        let control = &apng.frame_chunks()[1].control;
+        assert_eq!((control.x_offset, control.y_offset, control.width, control.height), (1, 0, 1, 1));
+        assert_eq!(png.decode().unwrap(), *frames[0].image());
+
+        let decoded: Vec<Frame> = apng.frames().unwrap().map(|f| f.unwrap()).collect();
+        assert_eq!(decoded, frames);
+    }
+
+    #[test]
+    fn test_assemble_hidden_default() {
+        let frames = vec![Frame::new(solid(RED), 1, 10), Frame::new(solid(HALF_BLUE), 1, 10)];
+        let options = AnimationOptions { hidden_default: Some(solid([0, 255, 0, 255])), ..Default::default() };
+        let png = assemble(&frames, &options).unwrap();
+        let apng = Apng::from_png(&png).unwrap();
+        assert!(!apng.default_image_is_frame());
+        assert_eq!(png.decode().unwrap().to_rgba8()[..4], [0, 255, 0, 255]);
+        let decoded: Vec<Frame> = apng.frames().unwrap().map(|f| f.unwrap()).collect();
+        assert_eq!(decoded, frames);
+    }
+
+    #[test]
+    fn test_assemble_rejects_mismatched_sizes() {
+        let frames = vec![Frame::new(solid(RED), 1, 10), Frame::new(Image::blank(3, 3), 1, 10)];
+        assert!(assemble(&frames, &AnimationOptions::default()).is_err());
+        assert!(assemble(&[], &AnimationOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_changed_region() {
+        let mut next = solid(RED);
+        assert_eq!(changed_region(&solid(RED), &next), (0, 0, 1, 1));
+        next.set_pixel(0, 1, [0; 4]);
+        next.set_pixel(1, 0, [0; 4]);
+        assert_eq!(changed_region(&solid(RED), &next), (0, 0, 2, 2));
+    }
+
+    fn rendered(png: &Png) -> Vec<Frame> {
+        Apng::from_png(png).unwrap().frames().unwrap().map(|f| f.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_edit_delays_and_loops() {
+        let png = testing_apng();
+        let mut editor = AnimationEditor::new(&png).unwrap();
+        editor.set_num_plays(5);
+        editor.set_delay(1..3, 3, 4).unwrap();
+        assert!(editor.set_delay(2..4, 1, 1).is_err());
+        let edited = editor.to_png().unwrap();
+
+        let apng = Apng::from_png(&edited).unwrap();
+        assert_eq!(apng.control().num_plays, 5);
+        let frames = rendered(&edited);
+        assert_eq!(frames[0].delay_num(), 1);
+        assert_eq!((frames[2].delay_num(), frames[2].delay_den()), (3, 4));
+        // Untouched frames keep their compressed data
+        assert_eq!(apng.frame_data(1), Apng::from_png(&png).unwrap().frame_data(1));
+    }
+
+    #[test]
+    fn test_remove_frames_keeps_appearance() {
+        let png = testing_apng();
+        let original = rendered(&png);
+        let mut editor = AnimationEditor::new(&png).unwrap();
+        editor.remove_frames(0..1).unwrap();
+        let edited = editor.to_png().unwrap();
+
+        let frames = rendered(&edited);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].image(), original[1].image());
+        assert_eq!(frames[1].image(), original[2].image());
+        assert_eq!(edited.decode().unwrap(), *original[1].image());
+        assert!(editor.remove_frames(0..2).is_err());
+    }
+
+    #[test]
+    fn test_reverse_keeps_appearance() {
+        let png = testing_apng();
+        let original = rendered(&png);
+        let mut editor = AnimationEditor::new(&png).unwrap();
+        editor.reverse();
+        let frames = rendered(&editor.to_png().unwrap());
+        let images: Vec<&Image> = frames.iter().map(|frame| frame.image()).collect();
+        assert_eq!(images, original.iter().rev().map(|frame| frame.image()).collect::<Vec<&Image>>());
+    }
+
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    /// A red canvas with a blue pixel drawn at (1, 1) and then cleared, and
+    /// another drawn at (0, 0), stored as `color_type` with `sample` giving
+    /// the bytes of each pixel.
+    fn opaque_apng(color_type: ColorType, extra: Vec<Chunk>, sample: impl Fn([u8; 4]) -> Vec<u8>) -> Png {
+        let data = |width: usize, pixels: &[[u8; 4]]| {
+            let raw: Vec<u8> = pixels.chunks(width)
+                .flat_map(|row| std::iter::once(0).chain(row.iter().flat_map(|&pixel| sample(pixel))))
+                .collect();
+            compress_to_vec_zlib(&raw, 6)
+        };
+        let ihdr = Ihdr { width: 2, height: 2, bit_depth: 8, color_type, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        let mut chunks = vec![Chunk::from_type_str("IHDR", ihdr.to_bytes()).unwrap()];
+        chunks.extend(extra);
+        chunks.push(Chunk::from_type_str("acTL", Actl { num_frames: 3, num_plays: 0 }.to_bytes()).unwrap());
+        chunks.push(fctl(0, 2, 2, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(Chunk::from_type_str("IDAT", data(2, &[RED; 4])).unwrap());
+        chunks.push(fctl(1, 1, 1, 1, 1, DisposeOp::Background, BlendOp::Source));
+        chunks.push(fdat(2, data(1, &[BLUE])));
+        chunks.push(fctl(3, 1, 1, 0, 0, DisposeOp::None, BlendOp::Source));
+        chunks.push(fdat(4, data(1, &[BLUE])));
+        chunks.push(Chunk::from_type_str("IEND", vec![]).unwrap());
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_redrawn_frames_become_rgba() {
+        let fixtures = [
+            opaque_apng(ColorType::Indexed, vec![
+                Chunk::from_type_str("PLTE", vec![255, 0, 0, 0, 0, 255]).unwrap(),
+                Chunk::from_type_str("bKGD", vec![1]).unwrap(),
+            ], |pixel| vec![if pixel == RED { 0 } else { 1 }]),
+            opaque_apng(ColorType::Rgb, vec![], |pixel| pixel[..3].to_vec()),
+            opaque_apng(ColorType::Grayscale, vec![Chunk::from_type_str("iCCP", b"gray\0\0".to_vec()).unwrap()], |pixel| vec![pixel[0]]),
+        ];
+        for png in fixtures {
+            let original = rendered(&png);
+            let mut editor = AnimationEditor::new(&png).unwrap();
+            editor.remove_frames(0..1).unwrap();
+            let edited = editor.to_png().unwrap();
+
+            assert_eq!(edited.ihdr().unwrap().color_type, ColorType::Rgba);
+            assert!(["PLTE", "bKGD", "iCCP"].iter().all(|chunk_type| edited.chunk_by_type(chunk_type).is_none()));
+            let frames = rendered(&edited);
+            assert_eq!(frames[1].image().pixel(1, 1), [0; 4]);
+            assert_eq!(frames.iter().map(Frame::image).collect::<Vec<_>>(), original[1..].iter().map(Frame::image).collect::<Vec<_>>());
+
+            // Edits that redraw nothing keep the original encoding.
+            let mut editor = AnimationEditor::new(&png).unwrap();
+            editor.set_delay(0..3, 1, 2).unwrap();
+            assert_eq!(editor.to_png().unwrap().ihdr().unwrap(), png.ihdr().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_blend_over() {
+        assert_eq!(blend_over([1, 2, 3, 65535], [9, 9, 9, 0]), [1, 2, 3, 65535]);
+        assert_eq!(blend_over([0, 0, 0, 0], [9, 9, 9, 100]), [9, 9, 9, 100]);
+        let half = blend_over([0, 0, 0, 65535], [65535, 65535, 65535, 32768]);
+        assert_eq!(half[3], 65535);
+        assert_eq!(half[0], 32768);
+    }
+}
@@ -1,18 +1,51 @@
 use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io::{BufReader, BufWriter};
 use std::io::Write;
+use std::ops::Range;
+use std::str::FromStr;
 
-use crate::png::Png;
-use crate::Result;
-use crate::Error;
-use crate::chunk::Chunk;
+use pngme::apng::{self, AnimationEditor, AnimationOptions, Apng, Frame};
+use pngme::carve;
+use pngme::convert::{self, Format};
+use pngme::diff;
+use pngme::encoder::{self, EncodeOptions};
+use pngme::hexdump::{self, HexdumpOptions};
+use pngme::ico::{Ico, IconImage};
+use pngme::png::Png;
+use pngme::Result;
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::chunk_reader::ChunkReader;
+use pngme::chunk_writer::{self, ChunkWriter};
+use pngme::image::Image;
+use pngme::known_chunk::KnownChunk;
+use pngme::keys;
+use pngme::mapped::{self, MappedPng};
+use pngme::mng::{Kind, Mng};
+use pngme::optimize;
+use pngme::payload::{self, Decoded, PayloadOptions};
+use pngme::repair;
+use pngme::seal::{self, Identity};
+use pngme::sign::{self, Signer25519, Verification};
+use pngme::strip::{self, StripProfile};
+use sha2::{Digest, Sha256};
 
 pub enum PngArgs {
-    Encode(String, String),
-    Decode(String),
-    Delete(String),
-    Print()
+    Print(),
+    Explode(String),
+    DropFrames(Range<usize>),
+    Retime(u16, Option<Range<usize>>),
+    SetLoops(u32),
+    Reverse(),
+    Strip(StripProfile),
+    /// Whether to keep ancillary chunks.
+    Optimize(bool),
+    /// Key file, and the ancillary chunk types to sign besides the critical ones.
+    Sign(String, Vec<String>),
+    /// Public key file.
+    VerifySignature(String)
 }
 
 pub struct Args {
@@ -23,91 +56,622 @@ pub struct Args {
 
 impl Args {
     pub fn new(file_path: &str, func: PngArgs) -> Args{
-        let bytes = fs::read(file_path).unwrap();
-        let bytes: &[u8] = &bytes;
-        let png = Png::try_from(bytes).unwrap();
+        let file = BufReader::new(File::open(file_path).unwrap());
+        let png = Png::from_reader(file).unwrap_or_else(|e| panic!("{}", e));
         println!("Successfully created args"); 
         Args {
-            png: png, 
-            func: func,
+            png, 
+            func,
             file_path: file_path.to_string()
         }
     }
     
-    pub fn encode(&mut self)-> Result<()> {
+    /// Writes every composited frame to `<prefix>_<index>.png` and returns the frame count.
+    pub fn explode(&self) -> Result<usize> {
         match &self.func {
-            PngArgs::Encode(chunk_type, message) => {
-                let bytes = u32::to_be_bytes(message.len() as u32).iter().map(|&x| x)
-                    .chain(chunk_type.bytes().into_iter())
-                    .chain(message.bytes().into_iter())
-                    .collect::<Vec<u8>>();
-                let bytes: &[u8] = &bytes;
-                // println!("{:?}", bytes);
-                match Chunk::try_from(bytes) {
-                    Ok(chunk) => {
-                        let bytes = u32::to_be_bytes(chunk.length()).iter()
-                            .map(|&x| x)
-                            .chain(chunk.chunk_type().bytes().iter().map(|&x| x))
-                            .chain(chunk.as_bytes().iter().map(|&x| x))
-                            .chain(u32::to_be_bytes(chunk.crc()).iter().map(|&x| x))
-                            .collect::<Vec<u8>>();
-                        let bytes: &[u8] = &bytes;
-                        let mut file = OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .open(self.file_path.clone())
-                            .unwrap();
-                            
-                        file.write_all(bytes).unwrap_or_else(|e| {
-                            panic!("Unable to write to file because of {e}");
-                        });  
-                        self.png.append_chunk(chunk);
-                        return Ok(())
-                    },
-                    Err(e) => return Err(format!("Unable to create chunk because of {}", e).into())
+            PngArgs::Explode(prefix) => {
+                let apng = Apng::from_png(&self.png)?;
+                let mut count = 0;
+                for (indx, frame) in apng.frames()?.enumerate() {
+                    let frame = frame?;
+                    let png = encoder::encode(frame.image(), &EncodeOptions::for_image(frame.image()))?;
+                    let path = format!("{}_{:03}.png", prefix, indx);
+                    fs::write(&path, png.as_bytes())?;
+                    println!("{} ({} ms)", path, frame.delay().as_millis());
+                    count += 1;
                 }
+                Ok(count)
             },
             _ => Err("Incorrect function call".into())
         }
     }
     
-    pub fn decode(&self)-> Option<String> {
-        match &self.func  {
-            PngArgs::Decode(chunk_type) => {
-                match self.png.chunk_by_type(chunk_type) {
-                    Some(chunk) => {
-                        return Some(String::from_utf8(chunk.as_bytes().to_vec()).unwrap());
-                    }
-                    None => return None
+    pub fn edit_frames(&mut self) -> Result<()> {
+        let mut editor = AnimationEditor::new(&self.png)?;
+        match &self.func {
+            PngArgs::DropFrames(frames) => editor.remove_frames(frames.clone())?,
+            PngArgs::Retime(delay_ms, frames) => {
+                let frames = frames.clone().unwrap_or(0..editor.len());
+                editor.set_delay(frames, *delay_ms, 1000)?
+            },
+            PngArgs::SetLoops(num_plays) => editor.set_num_plays(*num_plays),
+            PngArgs::Reverse() => editor.reverse(),
+            _ => return Err("Incorrect function call".into())
+        }
+        self.png = editor.to_png()?;
+        fs::write(&self.file_path, self.png.as_bytes())?;
+        Ok(())
+    }
+    
+    /// Rewrites the file without the chunks the profile removes, returning
+    /// the number of chunks and bytes removed.
+    pub fn strip(&mut self) -> Result<(usize, usize)> {
+        match &self.func {
+            PngArgs::Strip(profile) => {
+                let (png, report) = strip::strip(&self.png, profile)?;
+                for chunk in &report.removed {
+                    println!("Removed {} ({} bytes)", chunk.chunk_type(), chunk.length() + 12);
                 }
-                    
-            }, 
-            _ => None
+                self.png = png;
+                fs::write(&self.file_path, self.png.as_bytes())?;
+                Ok((report.removed.len(), report.bytes_saved()))
+            },
+            _ => Err("Incorrect function call".into())
         }
     }
     
-    pub fn delete(&mut self) -> Result<()>{
-        match &self.func  {
-            PngArgs::Delete(chunk_type) => {
-                match self.png.remove_first_chunk(chunk_type) {
-                    Ok(_) => {
-                        let mut file = File::create(self.file_path.clone()).unwrap();
-                        file.write_all(&self.png.as_bytes()).unwrap_or_else(|e| {
-                            panic!("Unable to write to file because of {e}");
-                        });
-                        return Ok(())
-                    },
-                    Err(e) => return Err(format!("Unable to find chunk due to {}", e).into())
-                }  
-            }, 
+    /// Rewrites the file with the smallest lossless encoding found, returning
+    /// the old and new file sizes.
+    pub fn optimize(&mut self) -> Result<(usize, usize)> {
+        match &self.func {
+            PngArgs::Optimize(keep_ancillary) => {
+                let (png, report) = optimize::optimize(&self.png, *keep_ancillary)?;
+                match &report.options {
+                    Some(options) => println!("Encoded as {:?} at bit depth {}, filter {:?}, compression level {}",
+                        options.color_type, options.bit_depth, options.filter, options.compression_level),
+                    None => println!("Original image data is already the smallest")
+                }
+                for name in &report.dropped {
+                    println!("Removed {}", name);
+                }
+                self.png = png;
+                fs::write(&self.file_path, self.png.as_bytes())?;
+                Ok((report.original_size, report.optimized_size))
+            },
             _ => Err("Incorrect function call".into())
         }
     }
     
+    /// Rewrites the file with a signature chunk, replacing any earlier one,
+    /// and returns the number of chunks signed.
+    pub fn sign(&mut self) -> Result<usize> {
+        match &self.func {
+            PngArgs::Sign(key_file, types) => {
+                let signer = Signer25519::from_key_file(&fs::read_to_string(key_file)?)?;
+                let png = sign::sign(&self.png, &signer, types)?;
+                let verification = sign::verify(&png, &signer.verifying_key())?;
+                self.png = png;
+                fs::write(&self.file_path, self.png.as_bytes())?;
+                Ok(verification.covered.len())
+            },
+            _ => Err("Incorrect function call".into())
+        }
+    }
+
+    pub fn verify_signature(&self) -> Result<Verification> {
+        match &self.func {
+            PngArgs::VerifySignature(key_file) => {
+                let key = sign::verifying_key_from_file(&fs::read_to_string(key_file)?)?;
+                sign::verify(&self.png, &key)
+            },
+            _ => Err("Incorrect function call".into())
+        }
+    }
+
     pub fn print(&self) {
         println!("{:?}", self.png.header());
+        let header = self.png.ihdr().ok();
         for chunk in self.png.chunks() {
-            println!("{}", chunk);
+            if !KnownChunk::is_known(&chunk.chunk_type()) {
+                println!("{}", chunk);
+                continue;
+            }
+            match KnownChunk::from_chunk(chunk, header.as_ref()) {
+                Ok(known) => println!("{}", known),
+                Err(e) => println!("{} (invalid: {})", chunk, e)
+            }
+        }
+    }
+}
+
+pub struct AnimateArgs {
+    pub output: String,
+    /// Input file paths with their delays in milliseconds.
+    pub frames: Vec<(String, u16)>,
+    /// When set, inputs are raw RGBA8 files of this size instead of PNGs.
+    pub raw_size: Option<(u32, u32)>,
+    pub num_plays: u32,
+    pub crop_to_changes: bool,
+    pub hidden_default: Option<String>,
+}
+
+impl AnimateArgs {
+    pub fn run(&self) -> Result<()> {
+        let mut frames = Vec::new();
+        for (path, delay_ms) in &self.frames {
+            frames.push(Frame::new(self.load_image(path)?, *delay_ms, 1000));
+        }
+        let hidden_default = match &self.hidden_default {
+            Some(path) => Some(self.load_image(path)?),
+            None => None
+        };
+        let options = AnimationOptions {
+            num_plays: self.num_plays,
+            crop_to_changes: self.crop_to_changes,
+            hidden_default,
+        };
+        let png = apng::assemble(&frames, &options)?;
+        fs::write(&self.output, png.as_bytes())?;
+        Ok(())
+    }
+
+    fn load_image(&self, path: &str) -> Result<Image> {
+        let bytes = fs::read(path)?;
+        match self.raw_size {
+            Some((width, height)) => Image::from_rgba8(width, height, &bytes),
+            None => Png::try_from(bytes.as_slice())?.decode()
+        }
+    }
+}
+
+/// The message commands, which never load the whole file: encode appends
+/// its chunk, decode and padding go through a memory map, and delete
+/// streams the file through once into its replacement.
+pub struct MessageArgs {
+    file_path: String,
+}
+
+impl MessageArgs {
+    pub fn new(file_path: &str) -> MessageArgs {
+        MessageArgs { file_path: file_path.to_string() }
+    }
+
+    pub fn encode(&self, chunk_type: &str, message: &str, options: &PayloadOptions) -> Result<()> {
+        let chunk_type = ChunkType::from_str(chunk_type)
+            .map_err(|e| format!("Unable to create chunk because of {}", e))?;
+        let chunk = Chunk::new(chunk_type, payload::encode(message.as_bytes(), options)?);
+        // Walking the chunk headers is enough to know it's a PNG to append to.
+        MappedPng::open(&self.file_path)?;
+        let file = OpenOptions::new().append(true).open(&self.file_path)?;
+        let mut writer = ChunkWriter::without_signature(file);
+        writer.write_chunk(&chunk)?;
+        writer.flush()
+    }
+
+    pub fn decode(&self, chunk_type: &str, key_file: Option<&str>) -> Result<Option<Decoded>> {
+        let identity = load_identity(key_file)?;
+        let data = match MappedPng::open(&self.file_path).and_then(|png| Ok(png.chunk_by_type(chunk_type)?.map(|chunk| chunk.data().to_vec()))) {
+            Ok(data) => data,
+            // A message with error correction can still be read from a chunk
+            // whose CRC no longer matches.
+            Err(_) => repair::repair(&fs::read(&self.file_path)?)?.0.chunk_by_type(chunk_type).map(|chunk| chunk.as_bytes())
+        };
+        match data {
+            Some(data) => Ok(Some(payload::decode(&data, identity.as_ref())?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Removes the first chunk of `chunk_type`, or with `pad` overwrites it
+    /// with a padding chunk of the same size instead of rewriting the file.
+    pub fn delete(&self, chunk_type: &str, pad: bool) -> Result<()> {
+        if pad {
+            let mut png = MappedPng::open(&self.file_path)?;
+            let indx = png.index().iter()
+                .position(|header| header.chunk_type.to_string() == chunk_type)
+                .ok_or("Unable to find chunk due to Chunktype not found")?;
+            let mut file = OpenOptions::new().read(true).write(true).open(&self.file_path)?;
+            return png.pad_chunk_in_place(&mut file, indx);
+        }
+
+        let temp_path = format!("{}.tmp", self.file_path);
+        let mut reader = ChunkReader::new(BufReader::new(File::open(&self.file_path)?))?;
+        let mut writer = ChunkWriter::new(BufWriter::new(File::create(&temp_path)?))?;
+        match chunk_writer::remove_chunk(&mut reader, &mut writer, chunk_type) {
+            Ok(true) => {
+                drop(writer);
+                fs::rename(&temp_path, &self.file_path)?;
+                Ok(())
+            },
+            Ok(false) => {
+                fs::remove_file(&temp_path)?;
+                Err("Unable to find chunk due to Chunktype not found".into())
+            },
+            Err(e) => {
+                fs::remove_file(&temp_path)?;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The identity in `key_file` for opening sealed messages, if one is given.
+fn load_identity(key_file: Option<&str>) -> Result<Option<Identity>> {
+    match key_file {
+        Some(path) => Ok(Some(Identity::from_key_file(&fs::read_to_string(path)?)?)),
+        None => Ok(None)
+    }
+}
+
+/// Read-only inspection through a memory map, so that only the chunks being
+/// shown are ever read from disk.
+pub struct InspectArgs {
+    png: MappedPng,
+}
+
+impl InspectArgs {
+    pub fn new(file_path: &str) -> Result<InspectArgs> {
+        Ok(InspectArgs { png: MappedPng::open(file_path)? })
+    }
+
+    pub fn list(&self) {
+        println!("{:>12}  {:4}  {:>10}", "offset", "type", "length");
+        for header in self.png.index() {
+            println!("{:>12}  {}  {:>10}", header.offset, header.chunk_type, header.length);
+        }
+        println!("{} chunks, {} bytes", self.png.len(), self.png.file_size());
+    }
+
+    /// Like `Args::print`, but chunks without a parsed form (IDAT, fdAT and
+    /// unknown types) are listed by offset rather than dumped.
+    pub fn print(&self) -> Result<()> {
+        let header = self.png.ihdr().ok();
+        for (indx, entry) in self.png.index().iter().enumerate() {
+            if !KnownChunk::is_known(&entry.chunk_type) || entry.chunk_type.to_string() == "fdAT" {
+                println!("{} at offset {}, length {}", entry.chunk_type, entry.offset, entry.length);
+                continue;
+            }
+            let chunk = self.png.chunk(indx)?.to_chunk();
+            match KnownChunk::from_chunk(&chunk, header.as_ref()) {
+                Ok(known) => println!("{}", known),
+                Err(e) => println!("{} (invalid: {})", chunk, e)
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct RepairArgs {
+    pub input: String,
+    pub output: String,
+    /// Where to also write the list of changes, besides printing it.
+    pub report: Option<String>,
+}
+
+impl RepairArgs {
+    /// Writes the repaired file and returns how many changes were made.
+    pub fn run(&self) -> Result<usize> {
+        let (png, repairs) = repair::repair(&fs::read(&self.input)?)?;
+        fs::write(&self.output, png.as_bytes())?;
+        let report: Vec<String> = repairs.iter().map(|repair| repair.to_string()).collect();
+        for line in &report {
+            println!("{}", line);
+        }
+        if let Some(path) = &self.report {
+            fs::write(path, report.join("\n") + "\n")?;
+        }
+        Ok(repairs.len())
+    }
+}
+
+pub struct FixDimensionsArgs {
+    pub input: String,
+    /// Where to write the file with the best candidate, if anywhere.
+    pub output: Option<String>,
+    pub limit: u32,
+}
+
+impl FixDimensionsArgs {
+    pub fn run(&self) -> Result<()> {
+        let bytes = fs::read(&self.input)?;
+        let candidates = repair::recover_dimensions(&bytes, self.limit)?;
+        if candidates.is_empty() {
+            return Err(format!("No width and height up to {} match the IHDR CRC", self.limit).into());
+        }
+        for candidate in &candidates {
+            let fits = match candidate.matches_data {
+                Some(true) => "matches image data",
+                Some(false) => "does not match image data",
+                None => "image data unreadable"
+            };
+            println!("{}x{} ({})", candidate.width, candidate.height, fits);
+        }
+        if let Some(output) = &self.output {
+            let best = candidates[0];
+            fs::write(output, repair::set_dimensions(&bytes, best.width, best.height)?)?;
+            println!("Wrote {}x{} to {}", best.width, best.height, output);
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+pub struct CarveArgs {
+    pub input: String,
+    pub prefix: String,
+    /// Also write the readable part of partial images.
+    pub keep_partial: bool,
+}
+
+impl CarveArgs {
+    /// Writes every complete PNG to `<prefix>_<index>_at_<offset>.png` and
+    /// returns how many were written.
+    pub fn run(&self) -> Result<usize> {
+        let blob = mapped::map_file(&self.input)?;
+        let mut written = 0;
+        for (indx, carved) in carve::carve(&blob).iter().enumerate() {
+            let path = match &carved.error {
+                None => format!("{}_{:03}_at_{}.png", self.prefix, indx, carved.offset),
+                Some(e) => {
+                    println!("Partial PNG at offset {} ({} bytes readable): {}", carved.offset, carved.bytes.len(), e);
+                    if !self.keep_partial {
+                        continue;
+                    }
+                    format!("{}_{:03}_at_{}.partial.png", self.prefix, indx, carved.offset)
+                }
+            };
+            fs::write(&path, carved.bytes)?;
+            println!("{} ({} bytes from offset {})", path, carved.bytes.len(), carved.offset);
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+pub struct DiffArgs {
+    pub old: String,
+    pub new: String,
+    pub compare_pixels: bool,
+    /// Where to write an image marking the differing pixels.
+    pub diff_image: Option<String>,
+}
+
+impl DiffArgs {
+    pub fn run(&self) -> Result<()> {
+        let old = Png::from_reader(BufReader::new(File::open(&self.old)?))?;
+        let new = Png::from_reader(BufReader::new(File::open(&self.new)?))?;
+        let changes = diff::diff_chunks(&old, &new);
+        if changes.is_empty() {
+            println!("Chunks are identical");
+        }
+        for change in &changes {
+            println!("{}", change);
+        }
+
+        if self.compare_pixels || self.diff_image.is_some() {
+            let pixels = diff::diff_pixels(&old.decode()?, &new.decode()?)?;
+            if pixels.identical() {
+                println!("Pixels are identical");
+            } else {
+                println!("{} pixels differ, max channel delta {} of 65535", pixels.differing, pixels.max_delta);
+            }
+            if let Some(path) = &self.diff_image {
+                let png = encoder::encode(&pixels.image, &EncodeOptions::for_image(&pixels.image))?;
+                fs::write(path, png.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct HexdumpArgs {
+    pub input: String,
+    pub options: HexdumpOptions,
+}
+
+impl HexdumpArgs {
+    pub fn run(&self) -> Result<()> {
+        let bytes = mapped::map_file(&self.input)?;
+        print!("{}", hexdump::hexdump(&bytes, &self.options)?);
+        Ok(())
+    }
+}
+pub struct ConvertArgs {
+    pub input: String,
+    pub output: String,
+    pub format: Format,
+}
+
+impl ConvertArgs {
+    pub fn run(&self) -> Result<()> {
+        let png = Png::from_reader(BufReader::new(File::open(&self.input)?))?;
+        fs::write(&self.output, convert::export(&png.decode()?, self.format)?)?;
+        Ok(())
+    }
+}
+
+pub struct ImportArgs {
+    pub input: String,
+    pub output: String,
+    /// Chunk type and message to hide in the new PNG.
+    pub message: Option<(String, String)>,
+    pub options: PayloadOptions,
+}
+
+impl ImportArgs {
+    pub fn run(&self) -> Result<()> {
+        let image = convert::import(&fs::read(&self.input)?)?;
+        let mut png = convert::to_png(&image)?;
+        if let Some((chunk_type, message)) = &self.message {
+            let chunk_type = ChunkType::from_str(chunk_type)
+                .map_err(|e| format!("Unable to create chunk because of {}", e))?;
+            png.append_chunk(Chunk::new(chunk_type, payload::encode(message.as_bytes(), &self.options)?));
+        }
+        fs::write(&self.output, png.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Works on the PNG entries of an ICO or CUR file. Edits rewrite the whole
+/// file so the directory offsets and sizes stay right.
+pub struct IcoArgs {
+    file_path: String,
+    ico: Ico,
+}
+
+impl IcoArgs {
+    pub fn new(file_path: &str) -> Result<IcoArgs> {
+        let ico = Ico::try_from(fs::read(file_path)?.as_slice())?;
+        Ok(IcoArgs { file_path: file_path.to_string(), ico })
+    }
+
+    pub fn list(&self) {
+        println!("{:?} with {} entries", self.ico.kind(), self.ico.entries().len());
+        for (indx, entry) in self.ico.entries().iter().enumerate() {
+            let format = match entry.image() {
+                IconImage::Png(png) => {
+                    let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+                    format!("PNG [{}]", types.join(", "))
+                },
+                IconImage::Bitmap(bytes) => format!("BMP, {} bytes", bytes.len())
+            };
+            println!("{:>3}  {}x{}  offset {}  {}", indx, entry.width(), entry.height(), entry.offset(), format);
+        }
+    }
+
+    /// Writes every PNG entry to `<prefix>_<index>_<width>x<height>.png` and
+    /// returns how many were written.
+    pub fn extract(&self, prefix: &str) -> Result<usize> {
+        let mut written = 0;
+        for (indx, entry) in self.ico.entries().iter().enumerate() {
+            if let Some(png) = entry.png() {
+                let path = format!("{}_{:02}_{}x{}.png", prefix, indx, entry.width(), entry.height());
+                fs::write(&path, png.as_bytes())?;
+                println!("{}", path);
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    pub fn encode(&mut self, indx: usize, chunk_type: &str, message: &str, options: &PayloadOptions) -> Result<()> {
+        let chunk_type = ChunkType::from_str(chunk_type)
+            .map_err(|e| format!("Unable to create chunk because of {}", e))?;
+        let chunk = Chunk::new(chunk_type, payload::encode(message.as_bytes(), options)?);
+        self.png_mut(indx)?.append_chunk(chunk);
+        self.save()
+    }
+
+    pub fn decode(&self, indx: usize, chunk_type: &str, key_file: Option<&str>) -> Result<Option<Decoded>> {
+        let identity = load_identity(key_file)?;
+        let png = self.ico.entries().get(indx).and_then(|entry| entry.png())
+            .ok_or(format!("Entry {} is not a PNG", indx))?;
+        match png.chunk_by_type(chunk_type) {
+            Some(chunk) => Ok(Some(payload::decode(chunk.data(), identity.as_ref())?)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn delete(&mut self, indx: usize, chunk_type: &str) -> Result<()> {
+        self.png_mut(indx)?.remove_first_chunk(chunk_type)?;
+        self.save()
+    }
+
+    fn png_mut(&mut self, indx: usize) -> Result<&mut Png> {
+        self.ico.entry_mut(indx)?.png_mut().ok_or(format!("Entry {} is a BMP, not a PNG", indx).into())
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::write(&self.file_path, self.ico.as_bytes())?;
+        Ok(())
+    }
+}
+
+pub struct MngArgs {
+    mng: Mng,
+}
+
+impl MngArgs {
+    pub fn new(file_path: &str) -> Result<MngArgs> {
+        Ok(MngArgs { mng: Mng::try_from(fs::read(file_path)?.as_slice())? })
+    }
+
+    pub fn list(&self) {
+        match self.mng.kind() {
+            Kind::Jng => match self.mng.jhdr() {
+                Ok(jhdr) => println!("{}", jhdr),
+                Err(e) => println!("JNG with invalid header: {}", e)
+            },
+            _ => match self.mng.mhdr() {
+                Ok(mhdr) => println!("{}", mhdr),
+                Err(e) => println!("MNG with invalid header: {}", e)
+            }
+        }
+        println!("{} chunks", self.mng.chunks().len());
+        for (indx, subimage) in self.mng.subimages().iter().enumerate() {
+            let types: Vec<String> = subimage.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+            println!("{:>3}  {:?} at chunk {}  [{}]", indx, subimage.kind(), subimage.first_chunk(), types.join(", "));
+        }
+    }
+
+    /// Writes every subimage to `<prefix>_<index>.png` or `.jng` and returns
+    /// how many were written.
+    pub fn extract(&self, prefix: &str) -> Result<usize> {
+        let subimages = self.mng.subimages();
+        for (indx, subimage) in subimages.iter().enumerate() {
+            let path = format!("{}_{:03}.{}", prefix, indx, subimage.kind().extension());
+            fs::write(&path, subimage.as_bytes())?;
+            println!("{}", path);
+        }
+        Ok(subimages.len())
+    }
+}
+
+pub struct HashArgs {
+    pub files: Vec<String>,
+}
+
+impl HashArgs {
+    /// Prints the SHA-256 of each file, of its critical chunks and of its
+    /// decoded pixels. Files showing the same image share the pixel hash
+    /// however they were encoded.
+    pub fn run(&self) -> Result<()> {
+        println!("{:<64}  {:<64}  {:<64}  path", "file", "critical chunks", "pixels");
+        for path in &self.files {
+            let bytes = fs::read(path)?;
+            let png = Png::try_from(bytes.as_slice()).map_err(|e| format!("{}: {}", path, e))?;
+            let pixels = match png.pixel_hash() {
+                Ok(hash) => keys::hex(&hash),
+                Err(e) => {
+                    eprintln!("{}: unable to decode pixels because of {}", path, e);
+                    "-".to_string()
+                }
+            };
+            println!("{}  {}  {:<64}  {}", keys::hex(&Sha256::digest(&bytes)), keys::hex(&png.critical_chunk_hash()), pixels, path);
+        }
+        Ok(())
+    }
+}
+
+/// Generates an X25519 key pair for sealed messages and an Ed25519 key pair
+/// for signatures.
+pub struct KeygenArgs {
+    /// Written to `<name>.key` (secret) and `<name>.pub`.
+    pub name: String,
+}
+
+impl KeygenArgs {
+    pub fn run(&self) -> Result<()> {
+        let identity = Identity::generate();
+        let signer = Signer25519::generate();
+        let (secret_path, public_path) = (format!("{}.key", self.name), format!("{}.pub", self.name));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&secret_path)?.write_all((identity.to_key_file() + &signer.to_key_file()).as_bytes())?;
+        fs::write(&public_path, seal::public_key_file(&identity.public_key()) + &sign::verifying_key_file(&signer.verifying_key()))?;
+        println!("Secret keys written to {}, keep them private", secret_path);
+        println!("Public keys written to {}, share them with senders and verifiers", public_path);
+        Ok(())
+    }
+}
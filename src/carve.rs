@@ -0,0 +1,96 @@
+use crate::chunk_ref::ChunkRef;
+use crate::png::Png;
+
+/// A PNG found inside a larger blob.
+pub struct Carved<'a> {
+    /// Where the signature starts in the blob.
+    pub offset: usize,
+    /// The signature and every chunk that could be read, up to and
+    /// including IEND for a complete image.
+    pub bytes: &'a [u8],
+    /// Why the image stops short of IEND, for partial images.
+    pub error: Option<String>,
+}
+
+impl Carved<'_> {
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Finds every PNG signature in `blob` and walks the chunks after it to
+/// IEND. Images that break off early are still returned, with the reason.
+/// Scanning resumes after each complete image, or just past the signature
+/// of a partial one.
+pub fn carve(blob: &[u8]) -> Vec<Carved<'_>> {
+    let mut found = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = find_signature(blob, pos) {
+        let carved = walk(blob, offset);
+        pos = if carved.is_complete() { offset + carved.bytes.len() } else { offset + 1 };
+        found.push(carved);
+    }
+    found
+}
+
+fn find_signature(blob: &[u8], from: usize) -> Option<usize> {
+    blob.get(from..)?
+        .windows(Png::STANDARD_HEADER.len())
+        .position(|window| window == Png::STANDARD_HEADER)
+        .map(|indx| from + indx)
+}
+
+fn walk(blob: &[u8], offset: usize) -> Carved<'_> {
+    let mut end = offset + Png::STANDARD_HEADER.len();
+    loop {
+        if end == blob.len() {
+            return Carved { offset, bytes: &blob[offset..end], error: Some("Data ends before IEND".to_string()) };
+        }
+        match ChunkRef::parse(&blob[end..]) {
+            Ok((chunk, _)) => {
+                end += chunk.length() as usize + 12;
+                if chunk.type_bytes() == b"IEND" {
+                    return Carved { offset, bytes: &blob[offset..end], error: None };
+                }
+            },
+            Err(e) => return Carved { offset, bytes: &blob[offset..end], error: Some(e.to_string()) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    /// image.png up to and including IEND, without the chunk hidden after it.
+    fn complete_image() -> &'static [u8] {
+        &IMAGE[..41427]
+    }
+
+    #[test]
+    fn test_carve_from_blob() {
+        let mut blob = b"some header bytes".to_vec();
+        blob.extend_from_slice(complete_image());
+        blob.extend_from_slice(&[0; 100]);
+        blob.extend_from_slice(complete_image());
+        let carved = carve(&blob);
+        assert_eq!(carved.len(), 2);
+        assert_eq!(carved[0].offset, 17);
+        assert_eq!(carved[1].offset, 17 + complete_image().len() + 100);
+        assert!(carved.iter().all(|carved| carved.is_complete() && carved.bytes == complete_image()));
+    }
+
+    #[test]
+    fn test_truncated_image_is_reported() {
+        let mut blob = complete_image()[..1000].to_vec();
+        blob.extend_from_slice(complete_image());
+        let carved = carve(&blob);
+        assert_eq!(carved.len(), 2);
+        assert!(!carved[0].is_complete());
+        assert_eq!(carved[0].bytes.len(), 33);
+        assert!(carved[1].is_complete());
+        assert!(carve(b"no images in here").is_empty());
+    }
+}
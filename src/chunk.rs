@@ -1,9 +1,8 @@
-use std::{convert::TryFrom, fmt::{Display, Formatter}, panic, str::FromStr};
-use crate::{chunk, Error, Result};
+use std::{convert::TryFrom, fmt::Formatter, str::FromStr};
+use crate::{Error, Result};
 use crc::{Crc, CRC_32_ISO_HDLC};
 use crate::chunk_type::ChunkType;
 
-
 pub const CRC_PNG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
 #[derive(PartialEq, Clone)]
@@ -18,36 +17,36 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
     
     fn try_from(chunk_data: &[u8]) -> Result<Self> {
-        let c_type_bytes = chunk_data[4..8].try_into();
-        if c_type_bytes.is_err() {
+        if chunk_data.len() < 12 {
             return Err("Too small".into());
         }
-        let c_type_bytes:[u8; 4] = c_type_bytes.unwrap();
-        
         let length: u32 = u32::from_be_bytes(chunk_data[0..4].try_into().unwrap());
+        if chunk_data.len() - 12 < length as usize {
+            return Err(format!("Truncated chunk: length {} needs {} bytes, found {}", length, 12 + length as u64, chunk_data.len()).into());
+        }
+        let c_type_bytes: [u8; 4] = chunk_data[4..8].try_into().unwrap();
 
         match ChunkType::try_from(c_type_bytes) {
             Ok(chunk_type) => {
-                let crc = if (12 + length as usize) > chunk_data.len() {
-                    let val = chunk_type.bytes().iter()
-                        .map(|&x| x)
-                        .chain(chunk_data[8..8 + length as usize].iter().map(|&x| x))
-                        .collect::<Vec<u8>>();
-                    CRC_PNG.checksum(&val)
-                } else {
-
-                    u32::from_be_bytes(chunk_data[8 + length as usize..12 + length as usize].try_into().unwrap())
-                };
+                let val = chunk_type.bytes().iter().copied()
+                    .chain(chunk_data[8..8 + length as usize].iter().copied())
+                    .collect::<Vec<u8>>();
+                let expected_crc = CRC_PNG.checksum(&val);
+                let crc = u32::from_be_bytes(chunk_data[8 + length as usize..12 + length as usize].try_into().unwrap());
                 
-                return Ok(Chunk{
-                    chunk_type: chunk_type,
-                    length: length,
-                    chunk_data: chunk_data[8..8 + length as usize].iter().map(|&x| x).collect::<Vec<u8>>(),
-                    crc: crc
+                if crc != expected_crc {
+                    return Err(format!("CRC mismatch for {}: expected {}, found {}", chunk_type, expected_crc, crc).into());
+                }
+                
+                Ok(Chunk{
+                    chunk_type,
+                    length,
+                    chunk_data: chunk_data[8..8 + length as usize].to_vec(),
+                    crc
                 })
             },
             
-            Err(e) => return Err(format!("Unable to chunktype due to {}", e).into())
+            Err(e) => Err(format!("Unable to chunktype due to {}", e).into())
         }
         
     }
@@ -61,19 +60,23 @@ impl std::fmt::Display for Chunk {
 
 impl Chunk {
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let val: Vec<u8>  = chunk_type.bytes().iter()
-            .map(|&x| x)
-            .chain(data.iter().map(|&x| x))
+        let val: Vec<u8>  = chunk_type.bytes().iter().copied()
+            .chain(data.iter().copied())
             .collect();
         
         Chunk {
             length: data.len() as u32,
-            chunk_type: chunk_type,
+            chunk_type,
             crc: CRC_PNG.checksum(&val),
             chunk_data: data
         }
     }
     
+    /// A chunk of the type named by `chunk_type`, such as "IDAT".
+    pub fn from_type_str(chunk_type: &str, data: Vec<u8>) -> Result<Chunk> {
+        Ok(Chunk::new(ChunkType::from_str(chunk_type)?, data))
+    }
+    
     pub fn length(&self) -> u32 {
         self.length
     }
@@ -92,8 +95,8 @@ impl Chunk {
     
     pub fn data_as_string(&self) -> Result<String> {
         match String::from_utf8(self.chunk_data.clone()) {
-            Ok(str) => return Ok(str), 
-            Err(e) => return Err(format!("Unable to convert to String because of {}", e).into())
+            Ok(str) => Ok(str), 
+            Err(e) => Err(format!("Unable to convert to String because of {}", e).into())
         }
     }
     
@@ -219,6 +222,18 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let bytes = testing_chunk().length().to_be_bytes().into_iter()
+            .chain(*b"RuSt")
+            .chain(testing_chunk().as_bytes())
+            .collect::<Vec<u8>>();
+        // Missing CRC, then data cut short, then not even a header.
+        assert!(Chunk::try_from(bytes.as_ref()).is_err());
+        assert!(Chunk::try_from(&bytes[..20]).is_err());
+        assert!(Chunk::try_from(&bytes[..6]).is_err());
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -0,0 +1,263 @@
+use std::io::{self, Read};
+use crc::{Crc, Digest};
+use crate::chunk::{Chunk, CRC_PNG};
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// The largest chunk length the PNG specification allows.
+pub const MAX_CHUNK_LENGTH: u32 = i32::MAX as u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkHeader {
+    pub length: u32,
+    pub chunk_type: ChunkType,
+    /// Byte offset of the chunk's length field from the start of the stream.
+    pub offset: u64,
+}
+
+struct PendingData {
+    remaining: u32,
+    digest: Digest<'static, u32>,
+    chunk_type: ChunkType,
+}
+
+/// Reads chunks one at a time from any `Read`, checking the signature up
+/// front and each CRC as the chunk's data goes past. Memory use is bounded
+/// by the largest chunk when reading whole chunks, and by the caller's
+/// buffer when streaming chunk data through `data()`.
+pub struct ChunkReader<R: Read> {
+    reader: R,
+    pending: Option<PendingData>,
+    position: u64,
+    failed: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(reader: R) -> Result<ChunkReader<R>> {
+        ChunkReader::with_signature(reader, &Png::STANDARD_HEADER)
+    }
+
+    /// A reader for a stream with the same chunk layout as PNG but another
+    /// signature, such as MNG or JNG.
+    pub fn with_signature(reader: R, expected: &[u8; 8]) -> Result<ChunkReader<R>> {
+        let mut reader = reader;
+        let mut signature = [0u8; 8];
+        if let Err(e) = reader.read_exact(&mut signature) {
+            return Err(format!("Unable to read signature because of {}", e).into());
+        }
+        if signature != *expected {
+            return Err("Incorrect initial bytes".into());
+        }
+        Ok(ChunkReader { reader, pending: None, position: 8, failed: false })
+    }
+
+    /// Bytes consumed from the underlying reader so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Reads the next chunk's length and type, skipping (and CRC-checking)
+    /// any data left unread from the previous chunk. Returns `None` at a
+    /// clean end of stream.
+    pub fn next_header(&mut self) -> Result<Option<ChunkHeader>> {
+        self.finish_pending()?;
+
+        let mut bytes = [0u8; 8];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(format!("Truncated chunk header at offset {}", self.position).into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.into())
+            }
+        }
+        let offset = self.position;
+        self.position += 8;
+
+        let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
+        if length > MAX_CHUNK_LENGTH {
+            return Err(format!("Chunk length {} at offset {} is too large", length, offset).into());
+        }
+        let chunk_type = match ChunkType::try_from(<[u8; 4]>::try_from(&bytes[4..8]).unwrap()) {
+            Ok(chunk_type) => chunk_type,
+            Err(e) => return Err(format!("Invalid chunk type at offset {} because of {}", offset, e).into())
+        };
+
+        let crc: &'static Crc<u32> = &CRC_PNG;
+        let mut digest = crc.digest();
+        digest.update(&chunk_type.bytes());
+        self.pending = Some(PendingData { remaining: length, digest, chunk_type: chunk_type.clone() });
+        Ok(Some(ChunkHeader { length, chunk_type, offset }))
+    }
+
+    /// A reader over the data of the chunk whose header was just read. The
+    /// CRC is checked once the data is exhausted, failing the final read with
+    /// `InvalidData` on a mismatch.
+    pub fn data(&mut self) -> ChunkData<'_, R> {
+        ChunkData { chunks: self }
+    }
+
+    /// Reads the next chunk in full.
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk>> {
+        let header = match self.next_header()? {
+            Some(header) => header,
+            None => return Ok(None)
+        };
+        // The length is only a claim until the data has been read, so the
+        // buffer grows with what actually arrives.
+        let mut data = Vec::new();
+        self.data().read_to_end(&mut data)?;
+        Ok(Some(Chunk::new(header.chunk_type, data)))
+    }
+
+    fn read_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pending = match &mut self.pending {
+            Some(pending) => pending,
+            None => return Ok(0)
+        };
+        if pending.remaining == 0 {
+            self.verify_crc()?;
+            return Ok(0);
+        }
+        let len = buf.len().min(pending.remaining as usize);
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Truncated {} chunk data", pending.chunk_type)));
+        }
+        pending.digest.update(&buf[..n]);
+        pending.remaining -= n as u32;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn verify_crc(&mut self) -> io::Result<()> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(())
+        };
+        let mut crc = [0u8; 4];
+        self.reader.read_exact(&mut crc)?;
+        self.position += 4;
+        let expected = pending.digest.finalize();
+        let found = u32::from_be_bytes(crc);
+        if expected != found {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("CRC mismatch for {}: expected {}, found {}", pending.chunk_type, expected, found)));
+        }
+        Ok(())
+    }
+
+    fn finish_pending(&mut self) -> Result<()> {
+        let mut buf = [0u8; 8192];
+        while self.pending.is_some() {
+            self.read_data(&mut buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let chunk = self.next_chunk().transpose();
+        self.failed = matches!(chunk, Some(Err(_)));
+        chunk
+    }
+}
+
+pub struct ChunkData<'a, R: Read> {
+    chunks: &'a mut ChunkReader<R>,
+}
+
+impl<R: Read> Read for ChunkData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.chunks.read_data(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::from_type_str("FrSt", b"I am the first chunk".to_vec()).unwrap(),
+            Chunk::from_type_str("miDl", b"I am another chunk".to_vec()).unwrap(),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_reads_same_chunks_as_png() {
+        let chunks: Vec<Chunk> = ChunkReader::new(IMAGE).unwrap().map(|chunk| chunk.unwrap()).collect();
+        let png = Png::try_from(IMAGE).unwrap();
+        assert!(chunks == png.chunks());
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let mut bytes = testing_bytes();
+        bytes[0] = 13;
+        assert!(ChunkReader::new(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = testing_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.next_chunk().unwrap().is_some());
+        assert!(reader.next_chunk().is_err());
+    }
+
+    #[test]
+    fn test_streamed_data_and_skipping() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let header = reader.next_header().unwrap().unwrap();
+        assert_eq!(header.chunk_type.to_string(), "FrSt");
+        assert_eq!((header.length, header.offset), (20, 8));
+
+        let mut start = [0u8; 4];
+        reader.data().read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"I am");
+
+        // The rest of the first chunk is skipped and its CRC still checked
+        let header = reader.next_header().unwrap().unwrap();
+        assert_eq!(header.chunk_type.to_string(), "miDl");
+        assert_eq!(header.offset, 8 + 12 + 20);
+        let mut data = String::new();
+        reader.data().read_to_string(&mut data).unwrap();
+        assert_eq!(data, "I am another chunk");
+        assert!(reader.next_header().unwrap().is_none());
+        assert_eq!(reader.position(), bytes.len() as u64);
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(&bytes[..bytes.len() - 6]).unwrap();
+        assert!(reader.next_chunk().unwrap().is_some());
+        assert!(reader.next_chunk().is_err());
+        assert!(ChunkReader::new(&bytes[..bytes.len() - 6]).unwrap().nth(1).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_oversized_length_is_not_trusted() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&0x7fff_ffffu32.to_be_bytes());
+        bytes.extend_from_slice(b"huGe tiny");
+        assert!(ChunkReader::new(bytes.as_slice()).unwrap().next_chunk().is_err());
+        assert!(Png::from_reader(bytes.as_slice()).is_err());
+    }
+}
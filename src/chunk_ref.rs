@@ -0,0 +1,283 @@
+use std::{convert::TryFrom, fmt::Formatter};
+use crc::Crc;
+use crate::chunk::{Chunk, CRC_PNG};
+use crate::chunk_type::ChunkType;
+use crate::decoder;
+use crate::image::Image;
+use crate::known_chunk::{Ihdr, Plte, Trns};
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// A chunk borrowed from a byte slice. Parsing and every accessor are
+/// allocation free; `to_chunk` makes an owned copy when one is needed.
+#[derive(PartialEq, Clone, Copy)]
+pub struct ChunkRef<'a> {
+    length: u32,
+    chunk_type: [u8; 4],
+    chunk_data: &'a [u8],
+    crc: u32,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Splits the chunk at the start of `bytes` from the rest, without
+    /// checking its CRC.
+    fn parse_unchecked(bytes: &'a [u8]) -> Result<(ChunkRef<'a>, &'a [u8])> {
+        if bytes.len() < 12 {
+            return Err(format!("Truncated chunk: only {} bytes left", bytes.len()).into());
+        }
+        let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
+        let chunk_type: [u8; 4] = bytes[4..8].try_into().unwrap();
+        if let Err(e) = ChunkType::try_from(chunk_type) {
+            return Err(format!("Unable to chunktype due to {}", e).into());
+        }
+        let end = 12 + length as usize;
+        if bytes.len() < end {
+            return Err(format!("Truncated {} chunk: {} of {} data bytes present",
+                String::from_utf8_lossy(&chunk_type), bytes.len() - 12, length).into());
+        }
+        let chunk = ChunkRef {
+            length,
+            chunk_type,
+            chunk_data: &bytes[8..end - 4],
+            crc: u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap()),
+        };
+        Ok((chunk, &bytes[end..]))
+    }
+
+    /// Like `TryFrom<&[u8]>`, but also returns the bytes following the chunk.
+    pub fn parse(bytes: &'a [u8]) -> Result<(ChunkRef<'a>, &'a [u8])> {
+        let (chunk, rest) = ChunkRef::parse_unchecked(bytes)?;
+        let expected_crc = chunk.expected_crc();
+        if chunk.crc != expected_crc {
+            return Err(format!("CRC mismatch for {}: expected {}, found {}", chunk.chunk_type(), expected_crc, chunk.crc).into());
+        }
+        Ok((chunk, rest))
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn chunk_type(&self) -> ChunkType {
+        ChunkType::try_from(self.chunk_type).unwrap()
+    }
+
+    /// The raw type bytes, for comparisons that don't need a `ChunkType`.
+    pub fn type_bytes(&self) -> &[u8; 4] {
+        &self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.chunk_data
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    pub fn data_as_str(&self) -> Result<&'a str> {
+        match std::str::from_utf8(self.chunk_data) {
+            Ok(str) => Ok(str),
+            Err(e) => Err(format!("Unable to convert to String because of {}", e).into())
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.chunk_data
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(self.chunk_type(), self.chunk_data.to_vec())
+    }
+
+    fn expected_crc(&self) -> u32 {
+        let crc: &'static Crc<u32> = &CRC_PNG;
+        let mut digest = crc.digest();
+        digest.update(&self.chunk_type);
+        digest.update(self.chunk_data);
+        digest.finalize()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        ChunkRef::parse(bytes).map(|(chunk, _)| chunk)
+    }
+}
+
+impl<'a> From<&'a Chunk> for ChunkRef<'a> {
+    fn from(chunk: &'a Chunk) -> Self {
+        ChunkRef {
+            length: chunk.length(),
+            chunk_type: chunk.chunk_type().bytes(),
+            chunk_data: chunk.data(),
+            crc: chunk.crc(),
+        }
+    }
+}
+
+impl std::fmt::Display for ChunkRef<'_> {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "Length = {}, chunk_type = {:?}, chunk_data = {:?}, crc = {}", self.length, self.chunk_type(), self.chunk_data, self.crc)
+    }
+}
+
+/// A PNG borrowed from a byte slice. Every chunk is validated once up front;
+/// after that, walking the chunks re-reads them from the slice in place.
+#[derive(Clone, Copy)]
+pub struct PngRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PngRef<'a> {
+    /// Validates a file with the same chunk layout as PNG but another
+    /// signature, such as MNG or JNG.
+    pub fn with_signature(bytes: &'a [u8], signature: &[u8; 8]) -> Result<PngRef<'a>> {
+        if bytes.len() < 8 || bytes[0..8] != *signature {
+            return Err("Incorrect initial bytes".into());
+        }
+        let mut remaining = &bytes[8..];
+        while !remaining.is_empty() {
+            match ChunkRef::parse(remaining) {
+                Ok((_, rest)) => remaining = rest,
+                Err(e) => return Err(format!("Invalid chunk found because of {}", e).into())
+            }
+        }
+        Ok(PngRef { bytes })
+    }
+
+    pub fn header(&self) -> &'a [u8; 8] {
+        self.bytes[0..8].try_into().unwrap()
+    }
+
+    pub fn chunks(&self) -> ChunkRefs<'a> {
+        ChunkRefs { remaining: &self.bytes[8..] }
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<ChunkRef<'a>> {
+        self.chunks().find(|chunk| chunk.type_bytes() == chunk_type.as_bytes())
+    }
+
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        match self.chunk_by_type("IHDR") {
+            Some(chunk) => Ihdr::parse(chunk.data()),
+            None => Err("Missing IHDR chunk".into())
+        }
+    }
+
+    pub fn palette(&self) -> Result<Option<Plte>> {
+        match self.chunk_by_type("PLTE") {
+            Some(chunk) => Ok(Some(Plte::parse(chunk.data())?)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn transparency(&self) -> Result<Option<Trns>> {
+        match self.chunk_by_type("tRNS") {
+            Some(chunk) => Ok(Some(Trns::parse(chunk.data(), Some(&self.ihdr()?))?)),
+            None => Ok(None)
+        }
+    }
+
+    /// The concatenated contents of every IDAT chunk.
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks()
+            .filter(|chunk| chunk.type_bytes() == b"IDAT")
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect()
+    }
+
+    /// Decodes the default image to canonical RGBA16 pixels.
+    pub fn decode(&self) -> Result<Image> {
+        let header = self.ihdr()?;
+        decoder::decode_image(&header, self.palette()?.as_ref(), self.transparency()?.as_ref(), &self.image_data())
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn to_png(&self) -> Png {
+        Png::from_chunks(self.chunks().map(|chunk| chunk.to_chunk()).collect())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self> {
+        PngRef::with_signature(bytes, &Png::STANDARD_HEADER)
+    }
+}
+
+/// Iterator over the chunks of a `PngRef`.
+pub struct ChunkRefs<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for ChunkRefs<'a> {
+    type Item = ChunkRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+        // Already validated when the PngRef was created
+        let (chunk, rest) = ChunkRef::parse_unchecked(self.remaining).unwrap();
+        self.remaining = rest;
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    #[test]
+    fn test_same_chunks_as_png() {
+        let png = Png::try_from(IMAGE).unwrap();
+        let png_ref = PngRef::try_from(IMAGE).unwrap();
+        assert_eq!(png_ref.chunks().count(), png.chunks().len());
+        for (borrowed, owned) in png_ref.chunks().zip(png.chunks()) {
+            assert!(borrowed == ChunkRef::from(owned));
+            assert!(borrowed.to_chunk() == *owned);
+        }
+        assert_eq!(png_ref.to_png().as_bytes(), IMAGE);
+        assert_eq!(png_ref.as_bytes(), IMAGE);
+    }
+
+    #[test]
+    fn test_accessors() {
+        let png = PngRef::try_from(IMAGE).unwrap();
+        assert_eq!(png.header(), &Png::STANDARD_HEADER);
+        assert_eq!(png.ihdr().unwrap().width, 386);
+        let iend = png.chunk_by_type("IEND").unwrap();
+        assert_eq!(iend.length(), 0);
+        assert_eq!(iend.chunk_type().to_string(), "IEND");
+        assert!(png.chunk_by_type("zzZz").is_none());
+        assert!(png.decode().unwrap() == Png::try_from(IMAGE).unwrap().decode().unwrap());
+    }
+
+    #[test]
+    fn test_chunk_ref_from_bytes() {
+        let bytes = Png::from_chunks(vec![Chunk::from_type_str("RuSt", b"borrowed".to_vec()).unwrap()]).as_bytes();
+        let (chunk, rest) = ChunkRef::parse(&bytes[8..]).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(chunk.data_as_str().unwrap(), "borrowed");
+        assert_eq!(chunk.crc(), chunk.to_chunk().crc());
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let mut bytes = IMAGE.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(PngRef::try_from(bytes.as_slice()).is_err());
+        assert!(PngRef::try_from(&IMAGE[..IMAGE.len() - 3]).is_err());
+        assert!(PngRef::try_from(&IMAGE[..4]).is_err());
+    }
+}
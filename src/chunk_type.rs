@@ -13,39 +13,30 @@ impl ChunkType {
     
     pub fn is_valid(&self) -> bool {
         for i in self.bytes {
-            if (i > 90 || i < 65) && (i > 122 || i < 97) {
+            if !(65..=90).contains(&i) && !(97..=122).contains(&i) {
                 return false;
             }
         }
         
-        return self.bytes[2] & 0x20 == 0
+        self.bytes[2] & 0x20 == 0
     }
     
     pub fn is_critical(&self) -> bool {
-        return (self.bytes[0] & 0x20) == 0;
+        (self.bytes[0] & 0x20) == 0
     }
     
     pub fn is_public(&self) -> bool {
-        return (self.bytes[1] & 0x20) == 0;
+        (self.bytes[1] & 0x20) == 0
     }
     
     pub fn is_reserved_bit_valid(&self) -> bool {
-        return (self.bytes[2] & 0x20) == 0
+        (self.bytes[2] & 0x20) == 0
     }
     
     pub fn is_safe_to_copy(&self) -> bool {
-        return !((self.bytes[3] & 0x20) == 0)
+        (self.bytes[3] & 0x20) != 0
     }
     
-    pub fn to_string(&self) -> String {
-        let mut res: String = String::new();
-        
-        for i in self.bytes {
-            res.push(i as char);
-        }
-        
-        res
-    }
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
@@ -54,7 +45,7 @@ impl TryFrom<[u8; 4]> for ChunkType {
     fn try_from(bytes: [u8; 4]) -> Result<Self> {
         // println!("chunk type is {:?}", String::from_utf8(bytes.try_into().unwrap()));
         for b in bytes {
-            if !(b >= 65 && b <= 90) && !(b >= 97 && b <= 122) && b != 32{
+            if !(65..=90).contains(&b) && !(97..=122).contains(&b) && b != 32{
                 return Err(format!("Not a letter for {b}").into());
             }
         }
@@ -64,8 +55,8 @@ impl TryFrom<[u8; 4]> for ChunkType {
             return Err("Invalid bit placement".into())
         }
         
-        return Ok(ChunkType{
-            bytes: bytes
+        Ok(ChunkType{
+            bytes
         })
     }
 }
@@ -78,24 +69,25 @@ impl FromStr for ChunkType {
             return Err("Too long".into());
         }
         let mut bytes: [u8; 4] = [0; 4]; 
-        let mut indx = 0;
         
-        for i in s.as_bytes() {
-            if !(*i >= 65 && *i <= 90) && !(*i >= 97 && *i <= 122) {
+        for (indx, i) in s.as_bytes().iter().enumerate() {
+            if !i.is_ascii_alphabetic() {
                 return Err(format!("Not a letter for {i:?}").into());
             }
             bytes[indx] = *i;
-            indx += 1;
         }
         
-        return Ok(ChunkType { bytes: bytes })
+        Ok(ChunkType { bytes })
     }
 }
 
 
 impl Display for ChunkType {
     fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
-        write!(f, "{:?}", self.bytes)
+        for i in self.bytes {
+            write!(f, "{}", i as char)?;
+        }
+        Ok(())
     }
 }
 
@@ -0,0 +1,198 @@
+use std::io::{self, Read, Write};
+use crc::Crc;
+use crate::chunk::{Chunk, CRC_PNG};
+use crate::chunk_reader::{ChunkReader, MAX_CHUNK_LENGTH};
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// Writes the PNG signature and then chunks one at a time to any `Write`,
+/// computing each CRC as the data streams through.
+pub struct ChunkWriter<W: Write> {
+    writer: W,
+    position: u64,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    pub fn new(writer: W) -> Result<ChunkWriter<W>> {
+        ChunkWriter::with_signature(writer, &Png::STANDARD_HEADER)
+    }
+
+    /// A writer that starts the stream with another signature, such as MNG or JNG.
+    pub fn with_signature(writer: W, signature: &[u8; 8]) -> Result<ChunkWriter<W>> {
+        let mut writer = writer;
+        writer.write_all(signature)?;
+        Ok(ChunkWriter { writer, position: signature.len() as u64 })
+    }
+
+    /// A writer for appending chunks to a stream that already has its signature.
+    pub fn without_signature(writer: W) -> ChunkWriter<W> {
+        ChunkWriter { writer, position: 0 }
+    }
+
+    /// Bytes written so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        self.writer.write_all(&chunk.length().to_be_bytes())?;
+        self.writer.write_all(&chunk.chunk_type().bytes())?;
+        self.writer.write_all(chunk.data())?;
+        self.writer.write_all(&chunk.crc().to_be_bytes())?;
+        self.position += chunk.length() as u64 + 12;
+        Ok(())
+    }
+
+    /// Writes a chunk whose `length` bytes of data are streamed from `data`,
+    /// so the data never has to be held in memory.
+    pub fn write_chunk_from_reader<R: Read>(&mut self, chunk_type: &ChunkType, length: u32, data: R) -> Result<()> {
+        if length > MAX_CHUNK_LENGTH {
+            return Err(format!("Chunk length {} is too large", length).into());
+        }
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&chunk_type.bytes())?;
+
+        let crc: &'static Crc<u32> = &CRC_PNG;
+        let mut digest = crc.digest();
+        digest.update(&chunk_type.bytes());
+        let mut data = data.take(length as u64);
+        let mut buf = [0u8; 8192];
+        let mut written: u64 = 0;
+        loop {
+            let n = match data.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into())
+            };
+            digest.update(&buf[..n]);
+            self.writer.write_all(&buf[..n])?;
+            written += n as u64;
+        }
+        if written != length as u64 {
+            return Err(format!("{} chunk data ended after {} of {} bytes", chunk_type, written, length).into());
+        }
+        self.writer.write_all(&digest.finalize().to_be_bytes())?;
+        self.position += length as u64 + 12;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Streams every chunk from `reader` to `writer`, inserting `insert` before
+/// the first chunk of type `before`, or at the end if there is none. Chunk
+/// data is passed through without being buffered whole.
+pub fn splice_chunk<R: Read, W: Write>(reader: &mut ChunkReader<R>, writer: &mut ChunkWriter<W>, insert: &Chunk, before: &str) -> Result<()> {
+    let mut inserted = false;
+    while let Some(header) = reader.next_header()? {
+        if !inserted && header.chunk_type.bytes() == before.as_bytes() {
+            writer.write_chunk(insert)?;
+            inserted = true;
+        }
+        writer.write_chunk_from_reader(&header.chunk_type, header.length, reader.data())?;
+    }
+    if !inserted {
+        writer.write_chunk(insert)?;
+    }
+    writer.flush()
+}
+
+/// Streams every chunk from `reader` to `writer` except the first of type
+/// `chunk_type`, and returns whether there was one to leave out.
+pub fn remove_chunk<R: Read, W: Write>(reader: &mut ChunkReader<R>, writer: &mut ChunkWriter<W>, chunk_type: &str) -> Result<bool> {
+    let mut removed = false;
+    while let Some(header) = reader.next_header()? {
+        if !removed && header.chunk_type.bytes() == chunk_type.as_bytes() {
+            // Read through rather than skipped so the CRC is still checked.
+            io::copy(&mut reader.data(), &mut io::sink())?;
+            removed = true;
+            continue;
+        }
+        writer.write_chunk_from_reader(&header.chunk_type, header.length, reader.data())?;
+    }
+    writer.flush()?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_write_chunks() {
+        let chunks = vec![
+            Chunk::from_type_str("FrSt", b"I am the first chunk".to_vec()).unwrap(),
+            Chunk::from_type_str("LASt", b"I am the last chunk".to_vec()).unwrap(),
+        ];
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for chunk in &chunks {
+            writer.write_chunk(chunk).unwrap();
+        }
+        assert_eq!(writer.position(), 8 + 32 + 31);
+        let bytes = writer.into_inner().unwrap();
+        assert_eq!(bytes, Png::from_chunks(chunks).as_bytes());
+    }
+
+    #[test]
+    fn test_write_chunk_from_reader() {
+        let expected = Chunk::from_type_str("RuSt", b"This is where your secret message will be!".to_vec()).unwrap();
+        let mut writer = ChunkWriter::without_signature(Vec::new());
+        writer.write_chunk_from_reader(&expected.chunk_type(), expected.length(), expected.data()).unwrap();
+        let bytes = writer.into_inner().unwrap();
+        let actual = Chunk::try_from(bytes.as_slice()).unwrap();
+        assert!(actual == expected);
+        assert_eq!(actual.crc(), 2882656334);
+    }
+
+    #[test]
+    fn test_short_reader() {
+        let mut writer = ChunkWriter::without_signature(Vec::new());
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        assert!(writer.write_chunk_from_reader(&chunk_type, 10, &b"short"[..]).is_err());
+    }
+
+    #[test]
+    fn test_splice_chunk() {
+        let original = Png::from_chunks(vec![
+            Chunk::from_type_str("FrSt", b"first".to_vec()).unwrap(),
+            Chunk::from_type_str("IEND", Vec::new()).unwrap(),
+        ]).as_bytes();
+        let mut reader = ChunkReader::new(original.as_slice()).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        splice_chunk(&mut reader, &mut writer, &Chunk::from_type_str("ruSt", b"hidden".to_vec()).unwrap(), "IEND").unwrap();
+
+        let png = Png::try_from(writer.into_inner().unwrap().as_slice()).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_remove_chunk() {
+        let original = Png::from_chunks(vec![
+            Chunk::from_type_str("ruSt", b"first".to_vec()).unwrap(),
+            Chunk::from_type_str("ruSt", b"second".to_vec()).unwrap(),
+            Chunk::from_type_str("IEND", Vec::new()).unwrap(),
+        ]).as_bytes();
+        let mut reader = ChunkReader::new(original.as_slice()).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        assert!(remove_chunk(&mut reader, &mut writer, "ruSt").unwrap());
+
+        let png = Png::try_from(writer.into_inner().unwrap().as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"second");
+
+        let mut reader = ChunkReader::new(original.as_slice()).unwrap();
+        assert!(!remove_chunk(&mut reader, &mut ChunkWriter::new(Vec::new()).unwrap(), "teSt").unwrap());
+    }
+}
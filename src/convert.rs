@@ -0,0 +1,549 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use crate::encoder;
+use crate::image::Image;
+use crate::optimize;
+use crate::png::Png;
+use crate::Result;
+
+/// Uncompressed formats a decoded image can be written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// PGM for gray images, PPM otherwise. Can't hold transparency.
+    Pnm,
+    /// Netpbm PAM, with an alpha channel when the image has transparency.
+    Pam,
+    /// farbfeld: always RGBA with 16 bits per sample.
+    Farbfeld,
+    /// 24-bit BMP for opaque images, 32-bit with an alpha mask otherwise.
+    Bmp,
+}
+
+impl Format {
+    /// Picks the format from a file extension.
+    pub fn from_path(path: &str) -> Result<Format> {
+        let extension = Path::new(path).extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("");
+        Format::from_name(&extension.to_ascii_lowercase())
+    }
+
+    pub fn from_name(name: &str) -> Result<Format> {
+        match name {
+            "pnm" | "ppm" | "pgm" => Ok(Format::Pnm),
+            "pam" => Ok(Format::Pam),
+            "ff" | "farbfeld" => Ok(Format::Farbfeld),
+            "bmp" => Ok(Format::Bmp),
+            _ => Err(format!("Unknown image format {:?}", name).into())
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        let name = match self {
+            Format::Pnm => "PNM",
+            Format::Pam => "PAM",
+            Format::Farbfeld => "farbfeld",
+            Format::Bmp => "BMP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Writes `image` in `format`. Gray and 8-bit images use the smallest
+/// layout the format has for them; nothing is lost except where a format
+/// can't hold 16-bit samples (BMP), which keeps the high byte.
+pub fn export(image: &Image, format: Format) -> Result<Vec<u8>> {
+    match format {
+        Format::Pnm => to_pnm(image),
+        Format::Pam => Ok(to_pam(image)),
+        Format::Farbfeld => Ok(to_farbfeld(image)),
+        Format::Bmp => to_bmp(image),
+    }
+}
+
+/// Channels written per pixel for Netpbm formats: gray or RGB, plus alpha.
+fn netpbm_channels(image: &Image, alpha: bool) -> Vec<usize> {
+    let mut channels = if image.is_grayscale() { vec![0] } else { vec![0, 1, 2] };
+    if alpha {
+        channels.push(3);
+    }
+    channels
+}
+
+/// Samples of `channels` for every pixel, one byte each when `wide` is
+/// false, otherwise big-endian 16-bit.
+fn netpbm_samples(image: &Image, channels: &[usize], wide: bool) -> Vec<u8> {
+    let mut samples = Vec::with_capacity(image.pixels().len() * channels.len() * if wide { 2 } else { 1 });
+    for pixel in image.pixels() {
+        for &c in channels {
+            if wide {
+                samples.extend_from_slice(&pixel[c].to_be_bytes());
+            } else {
+                samples.push((pixel[c] >> 8) as u8);
+            }
+        }
+    }
+    samples
+}
+
+fn to_pnm(image: &Image) -> Result<Vec<u8>> {
+    if !image.is_opaque() {
+        return Err("PPM and PGM can't hold transparency, use PAM, farbfeld or BMP".into());
+    }
+    let wide = !image.fits_in_8_bits();
+    let magic = if image.is_grayscale() { "P5" } else { "P6" };
+    let mut bytes = format!("{}\n{} {}\n{}\n", magic, image.width(), image.height(), if wide { 65535 } else { 255 }).into_bytes();
+    bytes.extend(netpbm_samples(image, &netpbm_channels(image, false), wide));
+    Ok(bytes)
+}
+
+fn to_pam(image: &Image) -> Vec<u8> {
+    let alpha = !image.is_opaque();
+    let wide = !image.fits_in_8_bits();
+    let channels = netpbm_channels(image, alpha);
+    let tuple_type = match (channels.len(), alpha) {
+        (1, _) => "GRAYSCALE",
+        (2, _) => "GRAYSCALE_ALPHA",
+        (_, false) => "RGB",
+        (_, true) => "RGB_ALPHA",
+    };
+    let mut bytes = format!("P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+        image.width(), image.height(), channels.len(), if wide { 65535 } else { 255 }, tuple_type).into_bytes();
+    bytes.extend(netpbm_samples(image, &channels, wide));
+    bytes
+}
+
+fn to_farbfeld(image: &Image) -> Vec<u8> {
+    let mut bytes = b"farbfeld".to_vec();
+    bytes.extend_from_slice(&image.width().to_be_bytes());
+    bytes.extend_from_slice(&image.height().to_be_bytes());
+    bytes.extend(image.to_rgba16_bytes());
+    bytes
+}
+
+/// Size of BITMAPINFOHEADER.
+const BMP_INFO_HEADER: u32 = 40;
+/// Size of BITMAPV4HEADER, which adds channel masks and a color space.
+const BMP_V4_HEADER: u32 = 108;
+/// `LCS_sRGB`, stored little-endian like every other BMP field.
+const BMP_SRGB: u32 = 0x7352_4742;
+
+fn to_bmp(image: &Image) -> Result<Vec<u8>> {
+    let alpha = !image.is_opaque();
+    let bpp: u32 = if alpha { 4 } else { 3 };
+    let row_size = (image.width() as u64 * bpp as u64).next_multiple_of(4);
+    let header_size = if alpha { BMP_V4_HEADER } else { BMP_INFO_HEADER };
+    let data_offset = 14 + header_size;
+    let file_size = u32::try_from(data_offset as u64 + row_size * image.height() as u64)
+        .map_err(|_| format!("{}x{} is too large for BMP", image.width(), image.height()))?;
+
+    let mut bytes = Vec::with_capacity(file_size as usize);
+    bytes.extend_from_slice(b"BM");
+    bytes.extend_from_slice(&file_size.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&data_offset.to_le_bytes());
+
+    bytes.extend_from_slice(&header_size.to_le_bytes());
+    bytes.extend_from_slice(&(image.width() as i32).to_le_bytes());
+    // A positive height means rows are stored bottom-up.
+    bytes.extend_from_slice(&(image.height() as i32).to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&(bpp as u16 * 8).to_le_bytes());
+    // BI_BITFIELDS when masks follow, BI_RGB otherwise.
+    bytes.extend_from_slice(&(if alpha { 3u32 } else { 0 }).to_le_bytes());
+    bytes.extend_from_slice(&((row_size * image.height() as u64) as u32).to_le_bytes());
+    // 2835 pixels per meter is 72 DPI.
+    bytes.extend_from_slice(&2835u32.to_le_bytes());
+    bytes.extend_from_slice(&2835u32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 8]);
+    if alpha {
+        for mask in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000] {
+            bytes.extend_from_slice(&mask.to_le_bytes());
+        }
+        bytes.extend_from_slice(&BMP_SRGB.to_le_bytes());
+        // Endpoints and gamma are ignored for sRGB.
+        bytes.extend_from_slice(&[0; 48]);
+    }
+
+    let padding = row_size as usize - image.width() as usize * bpp as usize;
+    for y in (0..image.height()).rev() {
+        for x in 0..image.width() {
+            let [r, g, b, a] = image.pixel(x, y).map(|c| (c >> 8) as u8);
+            bytes.extend_from_slice(&[b, g, r]);
+            if alpha {
+                bytes.push(a);
+            }
+        }
+        bytes.extend(std::iter::repeat_n(0, padding));
+    }
+    Ok(bytes)
+}
+
+/// Reads a PPM/PGM, PAM, farbfeld, BMP or QOI file, recognised by its
+/// magic bytes. Samples keep their full precision and alpha is kept.
+pub fn import(bytes: &[u8]) -> Result<Image> {
+    match bytes.get(..2) {
+        Some(b"P5" | b"P6") => from_pnm(bytes),
+        Some(b"P7") => from_pam(bytes),
+        Some(b"BM") => from_bmp(bytes),
+        _ if bytes.starts_with(b"farbfeld") => from_farbfeld(bytes),
+        _ if bytes.starts_with(b"qoif") => from_qoi(bytes),
+        _ => Err("Not a PPM, PGM, PAM, farbfeld, BMP or QOI file".into())
+    }
+}
+
+/// Encodes `image` in whichever lossless color type and bit depth gives the
+/// smallest file.
+pub fn to_png(image: &Image) -> Result<Png> {
+    let mut best: Option<Png> = None;
+    for target in optimize::lossless_targets(image) {
+        let png = encoder::encode(image, &target)?;
+        if best.as_ref().is_none_or(|best| png.as_bytes().len() < best.as_bytes().len()) {
+            best = Some(png);
+        }
+    }
+    best.ok_or("No way to encode the image".into())
+}
+
+/// Scales a sample with maximum `max` to 16 bits, rounding to nearest.
+fn scale_sample(sample: u32, max: u32) -> u16 {
+    ((sample.min(max) as u64 * u16::MAX as u64 + max as u64 / 2) / max as u64) as u16
+}
+
+/// Expands gray, gray + alpha, RGB or RGBA samples to a pixel.
+fn to_pixel(samples: &[u16]) -> [u16; 4] {
+    match *samples {
+        [v] => [v, v, v, u16::MAX],
+        [v, a] => [v, v, v, a],
+        [r, g, b] => [r, g, b, u16::MAX],
+        [r, g, b, a] => [r, g, b, a],
+        _ => unreachable!("Netpbm depth is checked to be 1-4"),
+    }
+}
+
+/// Reads `depth` samples per pixel after a Netpbm header, one byte each up
+/// to a maxval of 255 and big-endian 16-bit above it.
+fn netpbm_pixels(data: &[u8], width: u32, height: u32, depth: usize, maxval: u32) -> Result<Image> {
+    if !(1..=4).contains(&depth) {
+        return Err(format!("Unsupported depth {}", depth).into());
+    }
+    if !(1..=65535).contains(&maxval) {
+        return Err(format!("Invalid maxval {}", maxval).into());
+    }
+    let sample_size = if maxval > 255 { 2 } else { 1 };
+    let needed = (width as usize).checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(depth * sample_size))
+        .ok_or_else(|| format!("A {}x{} image is too large", width, height))?;
+    if data.len() < needed {
+        return Err(format!("Expected {} bytes of samples, found {}", needed, data.len()).into());
+    }
+    let pixels = data[..needed].chunks_exact(depth * sample_size).map(|pixel| {
+        let samples: Vec<u16> = pixel.chunks_exact(sample_size).map(|sample| {
+            let value = sample.iter().fold(0u32, |value, &b| value << 8 | b as u32);
+            scale_sample(value, maxval)
+        }).collect();
+        to_pixel(&samples)
+    }).collect();
+    Image::new(width, height, pixels)
+}
+
+fn from_pnm(bytes: &[u8]) -> Result<Image> {
+    // Magic, width, height and maxval, separated by whitespace and comments,
+    // then a single whitespace byte before the samples.
+    let mut values = Vec::new();
+    let mut pos = 2;
+    while values.len() < 3 {
+        match bytes.get(pos) {
+            Some(b'#') => while bytes.get(pos).is_some_and(|&b| b != b'\n') { pos += 1 },
+            Some(b) if b.is_ascii_whitespace() => pos += 1,
+            Some(b) if b.is_ascii_digit() => {
+                let start = pos;
+                while bytes.get(pos).is_some_and(|b| b.is_ascii_digit()) {
+                    pos += 1;
+                }
+                values.push(std::str::from_utf8(&bytes[start..pos])?.parse::<u32>()?);
+            },
+            _ => return Err("Truncated or invalid PNM header".into())
+        }
+    }
+    let depth = if &bytes[..2] == b"P5" { 1 } else { 3 };
+    netpbm_pixels(bytes.get(pos + 1..).ok_or("Truncated PNM header")?, values[0], values[1], depth, values[2])
+}
+
+fn from_pam(bytes: &[u8]) -> Result<Image> {
+    let end = bytes.windows(7).position(|window| window == b"ENDHDR\n")
+        .ok_or("PAM header has no ENDHDR")?;
+    let header = std::str::from_utf8(&bytes[..end])?;
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    for line in header.lines().skip(1) {
+        let mut fields = line.split_whitespace();
+        let (key, value) = (fields.next(), fields.next());
+        match (key, value) {
+            (Some("WIDTH"), Some(value)) => width = Some(value.parse()?),
+            (Some("HEIGHT"), Some(value)) => height = Some(value.parse()?),
+            (Some("DEPTH"), Some(value)) => depth = Some(value.parse()?),
+            (Some("MAXVAL"), Some(value)) => maxval = Some(value.parse()?),
+            _ => {}
+        }
+    }
+    match (width, height, depth, maxval) {
+        (Some(width), Some(height), Some(depth), Some(maxval)) => netpbm_pixels(&bytes[end + 7..], width, height, depth, maxval),
+        _ => Err("PAM header is missing WIDTH, HEIGHT, DEPTH or MAXVAL".into())
+    }
+}
+
+fn from_farbfeld(bytes: &[u8]) -> Result<Image> {
+    if bytes.len() < 16 {
+        return Err("Truncated farbfeld header".into());
+    }
+    let width = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+    let height = u32::from_be_bytes(bytes[12..16].try_into().unwrap());
+    let pixels = bytes[16..].chunks_exact(8)
+        .map(|p| [0, 2, 4, 6].map(|c| u16::from_be_bytes([p[c], p[c + 1]])))
+        .collect();
+    Image::new(width, height, pixels)
+}
+
+fn le_u32(bytes: &[u8], pos: usize) -> Result<u32> {
+    bytes.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or("Truncated BMP header".into())
+}
+
+/// Reads uncompressed BMPs: 1, 4 and 8 bits with a palette, 24 bits, and
+/// 16 or 32 bits with or without channel masks.
+fn from_bmp(bytes: &[u8]) -> Result<Image> {
+    let data_offset = le_u32(bytes, 10)? as usize;
+    let header_size = le_u32(bytes, 14)? as usize;
+    if header_size < BMP_INFO_HEADER as usize {
+        return Err("Only BMPs with a BITMAPINFOHEADER or later are supported".into());
+    }
+    let width = le_u32(bytes, 18)? as i32;
+    let height = le_u32(bytes, 22)? as i32;
+    let bpp = le_u32(bytes, 28)? & 0xffff;
+    let compression = le_u32(bytes, 30)?;
+    if width <= 0 || height == 0 {
+        return Err(format!("Invalid BMP size {}x{}", width, height).into());
+    }
+    let (width, top_down) = (width as u32, height < 0);
+    let height = height.unsigned_abs();
+
+    let masks = match (compression, bpp) {
+        // Masks follow a plain BITMAPINFOHEADER, or are part of a larger one.
+        (3, 16 | 32) => {
+            let alpha = if header_size >= 56 { le_u32(bytes, 66)? } else { 0 };
+            Some([le_u32(bytes, 54)?, le_u32(bytes, 58)?, le_u32(bytes, 62)?, alpha])
+        },
+        (0, 16) => Some([0x7c00, 0x03e0, 0x001f, 0]),
+        (0, 32) => Some([0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000]),
+        (0, 1 | 4 | 8 | 24) => None,
+        _ => return Err(format!("Unsupported BMP: {} bits per pixel, compression {}", bpp, compression).into())
+    };
+    let palette: Vec<[u16; 4]> = if bpp <= 8 {
+        let colors = match le_u32(bytes, 46)? {
+            0 => 1 << bpp,
+            colors => colors as usize,
+        };
+        let start = 14 + header_size;
+        let table = bytes.get(start..start + colors * 4).ok_or("Truncated BMP palette")?;
+        table.chunks_exact(4).map(|c| [c[2], c[1], c[0]].map(|v| v as u16 * 257)).map(|[r, g, b]| [r, g, b, u16::MAX]).collect()
+    } else {
+        Vec::new()
+    };
+
+    let row_size = (width as usize * bpp as usize).div_ceil(32) * 4;
+    let data = bytes.get(data_offset..).ok_or("BMP data offset is past the end")?;
+    if data.len() < row_size * height as usize {
+        return Err("Truncated BMP pixel data".into());
+    }
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height as usize {
+        let stored = if top_down { y } else { height as usize - 1 - y };
+        let row = &data[stored * row_size..(stored + 1) * row_size];
+        for x in 0..width as usize {
+            let pixel = match (bpp, masks) {
+                (1 | 4 | 8, _) => {
+                    let bit = x * bpp as usize;
+                    let indx = (row[bit / 8] >> (8 - bpp as usize - bit % 8)) & ((1 << bpp) - 1);
+                    *palette.get(indx as usize).ok_or("BMP palette index out of range")?
+                },
+                (24, _) => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3]].map(|v| v as u16 * 257).into_iter()
+                    .chain([u16::MAX]).collect::<Vec<u16>>().try_into().unwrap(),
+                (_, Some(masks)) => {
+                    let size = bpp as usize / 8;
+                    let value = row[x * size..(x + 1) * size].iter().rev().fold(0u32, |value, &b| value << 8 | b as u32);
+                    masks.map(|mask| match mask {
+                        0 => u16::MAX,
+                        mask => scale_sample((value & mask) >> mask.trailing_zeros(), mask >> mask.trailing_zeros()),
+                    })
+                },
+                _ => unreachable!("Every other layout was rejected above"),
+            };
+            pixels.push(pixel);
+        }
+    }
+    // Plain 32-bit BMPs often leave the fourth byte at zero rather than
+    // storing alpha in it.
+    if compression == 0 && bpp == 32 && pixels.iter().all(|p| p[3] == 0) {
+        pixels.iter_mut().for_each(|p| p[3] = u16::MAX);
+    }
+    Image::new(width, height, pixels)
+}
+
+fn from_qoi(bytes: &[u8]) -> Result<Image> {
+    if bytes.len() < 14 {
+        return Err("Truncated QOI header".into());
+    }
+    let width = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+    let height = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+    let count = width as usize * height as usize;
+    let mut pixels = Vec::with_capacity(count.min(bytes.len() * 64));
+    let mut seen = [[0u8; 4]; 64];
+    let mut previous = [0, 0, 0, 255u8];
+    let mut pos = 14;
+    let byte = |pos: usize| bytes.get(pos).copied().ok_or("Truncated QOI data");
+    while pixels.len() < count {
+        let tag = byte(pos)?;
+        pos += 1;
+        let mut pixel = previous;
+        let mut run = 1;
+        match tag {
+            0xfe => {
+                pixel[..3].copy_from_slice(bytes.get(pos..pos + 3).ok_or("Truncated QOI data")?);
+                pos += 3;
+            },
+            0xff => {
+                pixel.copy_from_slice(bytes.get(pos..pos + 4).ok_or("Truncated QOI data")?);
+                pos += 4;
+            },
+            _ => match tag >> 6 {
+                0 => pixel = seen[tag as usize],
+                1 => {
+                    for (c, shift) in [4, 2, 0].into_iter().enumerate() {
+                        pixel[c] = pixel[c].wrapping_add((tag >> shift) & 3).wrapping_sub(2);
+                    }
+                },
+                2 => {
+                    let dg = (tag & 0x3f).wrapping_sub(32);
+                    let next = byte(pos)?;
+                    pos += 1;
+                    pixel[0] = pixel[0].wrapping_add(dg.wrapping_sub(8).wrapping_add(next >> 4));
+                    pixel[1] = pixel[1].wrapping_add(dg);
+                    pixel[2] = pixel[2].wrapping_add(dg.wrapping_sub(8).wrapping_add(next & 0xf));
+                },
+                _ => run = (tag & 0x3f) as usize + 1,
+            }
+        }
+        let [r, g, b, a] = pixel.map(|c| c as usize);
+        seen[(r * 3 + g * 5 + b * 7 + a * 11) % 64] = pixel;
+        previous = pixel;
+        for _ in 0..run.min(count - pixels.len()) {
+            pixels.push(pixel.map(|c| c as u16 * 257));
+        }
+    }
+    Image::new(width, height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb() -> Image {
+        Image::from_rgba8(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255]).unwrap()
+    }
+
+    fn gray_alpha() -> Image {
+        Image::from_rgba8(1, 2, &[10, 10, 10, 255, 20, 20, 20, 128]).unwrap()
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("out.PPM").unwrap(), Format::Pnm);
+        assert_eq!(Format::from_path("dir.d/out.ff").unwrap(), Format::Farbfeld);
+        assert!(Format::from_path("out").is_err());
+        assert!(Format::from_path("out.jpg").is_err());
+    }
+
+    #[test]
+    fn test_pnm() {
+        assert_eq!(export(&rgb(), Format::Pnm).unwrap(), b"P6\n2 1\n255\n\xff\x00\x00\x00\x00\xff");
+        let mut gray = Image::blank(1, 1);
+        gray.set_pixel(0, 0, [1000, 1000, 1000, u16::MAX]);
+        assert_eq!(export(&gray, Format::Pnm).unwrap(), b"P5\n1 1\n65535\n\x03\xe8");
+        assert!(export(&gray_alpha(), Format::Pnm).is_err());
+    }
+
+    #[test]
+    fn test_pam() {
+        let pam = export(&gray_alpha(), Format::Pam).unwrap();
+        let header = "P7\nWIDTH 1\nHEIGHT 2\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n";
+        assert_eq!(&pam[..header.len()], header.as_bytes());
+        assert_eq!(&pam[header.len()..], [10, 255, 20, 128]);
+    }
+
+    #[test]
+    fn test_farbfeld() {
+        let ff = export(&rgb(), Format::Farbfeld).unwrap();
+        assert_eq!(&ff[..16], b"farbfeld\x00\x00\x00\x02\x00\x00\x00\x01");
+        assert_eq!(&ff[16..], rgb().to_rgba16_bytes());
+    }
+
+    #[test]
+    fn test_bmp() {
+        let bmp = export(&rgb(), Format::Bmp).unwrap();
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(u32::from_le_bytes(bmp[2..6].try_into().unwrap()) as usize, bmp.len());
+        assert_eq!(u32::from_le_bytes(bmp[10..14].try_into().unwrap()), 54);
+        // One BGR row padded from 6 to 8 bytes.
+        assert_eq!(&bmp[54..], [0, 0, 255, 255, 0, 0, 0, 0]);
+
+        let bmp = export(&gray_alpha(), Format::Bmp).unwrap();
+        assert_eq!(u32::from_le_bytes(bmp[10..14].try_into().unwrap()), 122);
+        // Bottom row first.
+        assert_eq!(&bmp[122..], [20, 20, 20, 128, 10, 10, 10, 255]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut wide = gray_alpha();
+        wide.set_pixel(0, 0, [1000, 1000, 1000, u16::MAX]);
+        for image in [rgb(), gray_alpha(), wide] {
+            for format in [Format::Pam, Format::Farbfeld] {
+                assert_eq!(import(&export(&image, format).unwrap()).unwrap(), image, "{}", format);
+            }
+        }
+        assert_eq!(import(&export(&rgb(), Format::Pnm).unwrap()).unwrap(), rgb());
+        assert_eq!(import(&export(&rgb(), Format::Bmp).unwrap()).unwrap(), rgb());
+        assert_eq!(import(&export(&gray_alpha(), Format::Bmp).unwrap()).unwrap(), gray_alpha());
+    }
+
+    #[test]
+    fn test_pnm_header_and_maxval() {
+        let image = import(b"P6 # comment\n1 1\n15\n\x0f\x00\x05").unwrap();
+        assert_eq!(image.pixel(0, 0), [65535, 0, 21845, 65535]);
+        assert!(import(b"P6\n2 2\n255\n\x00").is_err());
+        assert!(import(b"P6\n1 1\n255").is_err());
+        assert!(import(b"P6\n4294967295 4294967295\n65535\n").is_err());
+        assert!(import(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_qoi() {
+        let mut qoi = b"qoif\x00\x00\x00\x04\x00\x00\x00\x01\x04\x00".to_vec();
+        // RGBA, a one pixel run, a small diff and an index back to the first pixel.
+        qoi.extend_from_slice(&[0xff, 10, 20, 30, 128, 0xc0, 0x7f, 20]);
+        qoi.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        let image = import(&qoi).unwrap();
+        let pixels: Vec<[u8; 4]> = image.to_rgba8().chunks(4).map(|p| p.try_into().unwrap()).collect();
+        assert_eq!(pixels, [[10, 20, 30, 128], [10, 20, 30, 128], [11, 21, 31, 128], [10, 20, 30, 128]]);
+    }
+
+    #[test]
+    fn test_to_png_picks_smallest() {
+        let png = to_png(&rgb()).unwrap();
+        assert_eq!(png.ihdr().unwrap().color_type, crate::known_chunk::ColorType::Rgb);
+        assert_eq!(png.decode().unwrap(), rgb());
+    }
+}
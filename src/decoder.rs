@@ -0,0 +1,282 @@
+use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, TINFLStatus};
+use crate::image::Image;
+use crate::known_chunk::{ColorType, Ihdr, Plte, Trns};
+use crate::Result;
+
+/// Adam7 passes as (x start, y start, x step, y step).
+pub const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Inflates, unfilters and de-interlaces `compressed` image data laid out as
+/// described by `header`, producing canonical RGBA16 pixels.
+///
+/// The dimensions in IHDR are untrusted, so images over `MAX_PIXELS` are
+/// refused outright, and nothing is allocated for the pixels until the
+/// image data has been inflated and found to be as long as the header
+/// requires. Data beyond that is ignored.
+pub fn decode_image(header: &Ihdr, palette: Option<&Plte>, transparency: Option<&Trns>, compressed: &[u8]) -> Result<Image> {
+    Image::check_size(header.width, header.height)?;
+    let expected = usize::try_from(raw_data_size(header))
+        .map_err(|_| format!("A {}x{} image is too large", header.width, header.height))?;
+    let data = match decompress_to_vec_zlib_with_limit(compressed, expected) {
+        Ok(data) => data,
+        Err(e) if e.status == TINFLStatus::HasMoreOutput => e.output,
+        Err(e) => return Err(format!("Unable to inflate image data because of {}", e).into())
+    };
+    if data.len() < expected {
+        return Err(format!("Image data is {} bytes, the header requires {}", data.len(), expected).into());
+    }
+    if header.color_type == ColorType::Indexed && palette.is_none() {
+        return Err("Indexed image has no PLTE chunk".into());
+    }
+
+    let passes: &[(u32, u32, u32, u32)] = if header.interlace_method == 1 { &ADAM7 } else { &[(0, 0, 1, 1)] };
+    let bits_per_pixel = header.color_type.channels() * header.bit_depth as usize;
+    let mut image = Image::blank(header.width, header.height);
+    let mut indx = 0;
+
+    for &(x0, y0, dx, dy) in passes {
+        let pass_width = header.width.saturating_sub(x0).div_ceil(dx);
+        let pass_height = header.height.saturating_sub(y0).div_ceil(dy);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let row_bytes = (pass_width as usize * bits_per_pixel).div_ceil(8);
+        let mut prev = vec![0u8; row_bytes];
+        for row in 0..pass_height {
+            if indx + 1 + row_bytes > data.len() {
+                return Err("Image data is shorter than the header requires".into());
+            }
+            let filter = data[indx];
+            let mut line = data[indx + 1..indx + 1 + row_bytes].to_vec();
+            indx += 1 + row_bytes;
+            unfilter(filter, &mut line, &prev, bits_per_pixel.div_ceil(8))?;
+
+            for col in 0..pass_width {
+                let pixel = read_pixel(header, palette, transparency, &line, col as usize)?;
+                image.set_pixel(x0 + col * dx, y0 + row * dy, pixel);
+            }
+            prev = line;
+        }
+    }
+    Ok(image)
+}
+
+/// The length of the inflated image data `header` describes: every scanline
+/// of every pass, each with its filter type byte. Saturates for headers too
+/// large to describe a real image.
+pub fn raw_data_size(header: &Ihdr) -> u64 {
+    let passes: &[(u32, u32, u32, u32)] = if header.interlace_method == 1 { &ADAM7 } else { &[(0, 0, 1, 1)] };
+    let bits_per_pixel = (header.color_type.channels() * header.bit_depth as usize) as u64;
+    passes.iter().map(|&(x0, y0, dx, dy)| {
+        let pass_width = header.width.saturating_sub(x0).div_ceil(dx) as u64;
+        let pass_height = header.height.saturating_sub(y0).div_ceil(dy) as u64;
+        if pass_width == 0 { 0 } else { pass_height.saturating_mul(1 + (pass_width * bits_per_pixel).div_ceil(8)) }
+    }).fold(0, u64::saturating_add)
+}
+
+/// Reverses the scanline filter in place. `prev` is the already unfiltered
+/// previous line of the same pass (all zeros for the first line).
+pub fn unfilter(filter: u8, line: &mut [u8], prev: &[u8], bpp: usize) -> Result<()> {
+    match filter {
+        0 => {},
+        1 => for i in bpp..line.len() {
+            line[i] = line[i].wrapping_add(line[i - bpp]);
+        },
+        2 => for i in 0..line.len() {
+            line[i] = line[i].wrapping_add(prev[i]);
+        },
+        3 => for i in 0..line.len() {
+            let left = if i >= bpp { line[i - bpp] as u16 } else { 0 };
+            line[i] = line[i].wrapping_add(((left + prev[i] as u16) / 2) as u8);
+        },
+        4 => for i in 0..line.len() {
+            let left = if i >= bpp { line[i - bpp] } else { 0 };
+            let upper_left = if i >= bpp { prev[i - bpp] } else { 0 };
+            line[i] = line[i].wrapping_add(paeth(left, prev[i], upper_left));
+        },
+        _ => return Err(format!("Unknown filter type {filter}").into())
+    }
+    Ok(())
+}
+
+pub fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reads the `index`-th sample of a packed scanline.
+fn read_sample(line: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([line[index * 2], line[index * 2 + 1]]),
+        8 => line[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            ((line[bit / 8] >> shift) & ((1 << bit_depth) - 1)) as u16
+        }
+    }
+}
+
+/// Scales a sample of `bit_depth` bits to the full 16-bit range.
+pub fn scale_to_16(sample: u16, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => sample,
+        8 => sample * 257,
+        _ => (sample as u32 * 65535 / ((1u32 << bit_depth) - 1)) as u16
+    }
+}
+
+fn read_pixel(header: &Ihdr, palette: Option<&Plte>, transparency: Option<&Trns>, line: &[u8], col: usize) -> Result<[u16; 4]> {
+    let depth = header.bit_depth;
+    let channels = header.color_type.channels();
+    let raw = |channel: usize| read_sample(line, col * channels + channel, depth);
+    let scaled = |channel: usize| scale_to_16(raw(channel), depth);
+
+    let pixel = match header.color_type {
+        ColorType::Grayscale => {
+            let alpha = match transparency {
+                Some(Trns::Gray(gray)) if *gray == raw(0) => 0,
+                _ => u16::MAX
+            };
+            [scaled(0), scaled(0), scaled(0), alpha]
+        },
+        ColorType::Rgb => {
+            let alpha = match transparency {
+                Some(Trns::Rgb(r, g, b)) if (*r, *g, *b) == (raw(0), raw(1), raw(2)) => 0,
+                _ => u16::MAX
+            };
+            [scaled(0), scaled(1), scaled(2), alpha]
+        },
+        ColorType::Indexed => {
+            let index = raw(0) as usize;
+            let entries = &palette.unwrap().entries;
+            if index >= entries.len() {
+                return Err(format!("Palette index {} is out of range", index).into());
+            }
+            let alpha = match transparency {
+                Some(Trns::Palette(alphas)) => alphas.get(index).copied().unwrap_or(255),
+                _ => 255
+            };
+            let [r, g, b] = entries[index];
+            [r as u16 * 257, g as u16 * 257, b as u16 * 257, alpha as u16 * 257]
+        },
+        ColorType::GrayscaleAlpha => [scaled(0), scaled(0), scaled(0), scaled(1)],
+        ColorType::Rgba => [scaled(0), scaled(1), scaled(2), scaled(3)],
+    };
+    Ok(pixel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: ColorType, interlace_method: u8) -> Ihdr {
+        Ihdr { width, height, bit_depth, color_type, compression_method: 0, filter_method: 0, interlace_method }
+    }
+
+    #[test]
+    fn test_decode_rgb_with_filters() {
+        // Two rows of 2 RGB pixels, the second row using the Up filter
+        let raw = [
+            1, 10, 20, 30, 5, 5, 5,
+            2, 1, 1, 1, 1, 1, 1,
+        ];
+        let image = decode_image(&header(2, 2, 8, ColorType::Rgb, 0), None, None, &compress_to_vec_zlib(&raw, 6)).unwrap();
+        assert_eq!(image.to_rgba8(), vec![
+            10, 20, 30, 255, 15, 25, 35, 255,
+            11, 21, 31, 255, 16, 26, 36, 255,
+        ]);
+    }
+
+    #[test]
+    fn test_decode_low_bit_depth_gray_with_transparency() {
+        let raw = [0, 0b00_01_10_11];
+        let trns = Trns::Gray(3);
+        let image = decode_image(&header(4, 1, 2, ColorType::Grayscale, 0), None, Some(&trns), &compress_to_vec_zlib(&raw, 6)).unwrap();
+        let pixels: Vec<[u16; 4]> = image.pixels().to_vec();
+        assert_eq!(pixels[0], [0, 0, 0, 65535]);
+        assert_eq!(pixels[1], [21845, 21845, 21845, 65535]);
+        assert_eq!(pixels[3], [65535, 65535, 65535, 0]);
+    }
+
+    #[test]
+    fn test_decode_indexed() {
+        let palette = Plte { entries: vec![[255, 0, 0], [0, 0, 255]] };
+        let trns = Trns::Palette(vec![128]);
+        let raw = [0, 0b0100_0000];
+        let image = decode_image(&header(2, 1, 1, ColorType::Indexed, 0), Some(&palette), Some(&trns), &compress_to_vec_zlib(&raw, 6)).unwrap();
+        assert_eq!(image.to_rgba8(), vec![255, 0, 0, 128, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        // A 2x2 gray image: pass 1 holds (0, 0), pass 6 holds (1, 0) and pass 7 holds row 1
+        let raw = [0, 10, 0, 20, 0, 30, 40];
+        let image = decode_image(&header(2, 2, 8, ColorType::Grayscale, 1), None, None, &compress_to_vec_zlib(&raw, 6)).unwrap();
+        let gray: Vec<u8> = image.to_rgba8().chunks(4).map(|p| p[0]).collect();
+        assert_eq!(gray, vec![10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let raw = [0, 1, 2];
+        assert!(decode_image(&header(2, 2, 8, ColorType::Grayscale, 0), None, None, &compress_to_vec_zlib(&raw, 6)).is_err());
+    }
+
+    #[test]
+    fn test_huge_header_is_rejected_before_allocating() {
+        let raw = [0, 1, 2];
+        let huge = header(0x7fff_ffff, 0x7fff_ffff, 16, ColorType::Rgba, 0);
+        assert!(decode_image(&huge, None, None, &compress_to_vec_zlib(&raw, 6)).is_err());
+    }
+
+    #[test]
+    fn test_pixel_count_is_capped() {
+        // A 1-bit image this size inflates from about a megabyte but would
+        // take gigabytes as RGBA16.
+        let raw = [0, 1, 2];
+        let wide = header(100_000, 100_000, 1, ColorType::Grayscale, 0);
+        let err = decode_image(&wide, None, None, &compress_to_vec_zlib(&raw, 6)).unwrap_err();
+        assert!(err.to_string().contains("pixels"));
+    }
+
+    #[test]
+    fn test_extra_data_is_ignored() {
+        let raw = [0, 1, 2, 0, 3, 4, 99, 99];
+        let image = decode_image(&header(2, 2, 8, ColorType::Grayscale, 0), None, None, &compress_to_vec_zlib(&raw, 6)).unwrap();
+        assert_eq!(image.to_rgba8().chunks(4).map(|p| p[0]).collect::<Vec<u8>>(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_raw_data_size() {
+        assert_eq!(raw_data_size(&header(3, 2, 8, ColorType::Rgb, 0)), 2 * (1 + 9));
+        assert_eq!(raw_data_size(&header(5, 1, 1, ColorType::Grayscale, 0)), 2);
+        // Passes 1, 4 and 6 of a 3x1 image each hold one pixel
+        assert_eq!(raw_data_size(&header(3, 1, 8, ColorType::Grayscale, 1)), 3 * 2);
+    }
+
+    #[test]
+    fn test_paeth() {
+        assert_eq!(paeth(10, 20, 15), 15);
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(10, 20, 20), 10);
+    }
+}
@@ -0,0 +1,416 @@
+use std::fmt::{Debug, Display, Formatter};
+use crate::chunk::Chunk;
+use crate::image::Image;
+use crate::known_chunk::{Bkgd, Ihdr, KnownChunk, Trns};
+use crate::png::Png;
+use crate::Result;
+
+/// Field differences listed per modified chunk before the rest are counted.
+const MAX_FIELD_DIFFS: usize = 10;
+
+/// How one chunk differs between two files. Indexes are positions in the
+/// chunk lists of the old and new file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkChange {
+    Added { chunk_type: String, index: usize },
+    Removed { chunk_type: String, index: usize },
+    /// Identical contents at a different position relative to the other chunks.
+    Moved { chunk_type: String, from: usize, to: usize },
+    Modified { chunk_type: String, from: usize, to: usize, fields: Vec<String> },
+}
+
+impl Display for ChunkChange {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ChunkChange::Added { chunk_type, index } => write!(f, "+ {} added at {}", chunk_type, index),
+            ChunkChange::Removed { chunk_type, index } => write!(f, "- {} removed from {}", chunk_type, index),
+            ChunkChange::Moved { chunk_type, from, to } => write!(f, "~ {} moved from {} to {}", chunk_type, from, to),
+            ChunkChange::Modified { chunk_type, from, to, fields } => {
+                write!(f, "* {} modified ({} -> {})", chunk_type, from, to)?;
+                for field in fields {
+                    write!(f, "\n    {}", field)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Aligns the chunk lists of `old` and `new`. Chunks in the longest common
+/// run of identical chunks are unchanged; other identical chunks have moved;
+/// the remaining chunks are paired up by type in order as modifications, and
+/// whatever is left was added or removed.
+pub fn diff_chunks(old: &Png, new: &Png) -> Vec<ChunkChange> {
+    let (old, new) = (old.chunks(), new.chunks());
+    let mut old_matched = vec![false; old.len()];
+    let mut new_matched = vec![false; new.len()];
+    for (i, j) in longest_common_subsequence(old, new) {
+        old_matched[i] = true;
+        new_matched[j] = true;
+    }
+
+    let (old_header, new_header) = (header(old), header(new));
+    let mut changes = Vec::new();
+    for j in 0..new.len() {
+        if new_matched[j] {
+            continue;
+        }
+        if let Some(i) = (0..old.len()).find(|&i| !old_matched[i] && old[i] == new[j]) {
+            old_matched[i] = true;
+            new_matched[j] = true;
+            changes.push(ChunkChange::Moved { chunk_type: new[j].chunk_type().to_string(), from: i, to: j });
+        }
+    }
+    for j in 0..new.len() {
+        if new_matched[j] {
+            continue;
+        }
+        if let Some(i) = (0..old.len()).find(|&i| !old_matched[i] && old[i].chunk_type() == new[j].chunk_type()) {
+            old_matched[i] = true;
+            new_matched[j] = true;
+            let fields = field_diffs(&old[i], &new[j], old_header.as_ref(), new_header.as_ref());
+            changes.push(ChunkChange::Modified { chunk_type: new[j].chunk_type().to_string(), from: i, to: j, fields });
+        }
+    }
+    changes.extend((0..old.len()).filter(|&i| !old_matched[i])
+        .map(|i| ChunkChange::Removed { chunk_type: old[i].chunk_type().to_string(), index: i }));
+    changes.extend((0..new.len()).filter(|&j| !new_matched[j])
+        .map(|j| ChunkChange::Added { chunk_type: new[j].chunk_type().to_string(), index: j }));
+    changes
+}
+
+fn header(chunks: &[Chunk]) -> Option<Ihdr> {
+    chunks.iter().find(|chunk| chunk.chunk_type().bytes() == *b"IHDR").and_then(|chunk| Ihdr::parse(chunk.data()).ok())
+}
+
+/// Index pairs of the longest run of identical chunks common to both lists.
+/// The common prefix and suffix are matched directly and the middle goes
+/// through Hirschberg's algorithm, so memory stays linear in the chunk count.
+fn longest_common_subsequence(old: &[Chunk], new: &[Chunk]) -> Vec<(usize, usize)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    hirschberg(&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix], (prefix, prefix), &mut pairs);
+    pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+    pairs
+}
+
+/// Splits `old` in half and finds where a longest common subsequence
+/// crosses the split from the lengths computed forwards over the first half
+/// and backwards over the second, then recurses into both sides. `start`
+/// is the position of the slices in the full lists.
+fn hirschberg(old: &[Chunk], new: &[Chunk], start: (usize, usize), pairs: &mut Vec<(usize, usize)>) {
+    if old.is_empty() || new.is_empty() {
+        return;
+    }
+    if old.len() == 1 {
+        if let Some(j) = new.iter().position(|chunk| *chunk == old[0]) {
+            pairs.push((start.0, start.1 + j));
+        }
+        return;
+    }
+    let mid = old.len() / 2;
+    let forward = lcs_lengths(old[..mid].iter(), new.iter());
+    let backward = lcs_lengths(old[mid..].iter().rev(), new.iter().rev());
+    let split = (0..=new.len()).max_by_key(|&j| forward[j] + backward[new.len() - j]).unwrap();
+    hirschberg(&old[..mid], &new[..split], start, pairs);
+    hirschberg(&old[mid..], &new[split..], (start.0 + mid, start.1 + split), pairs);
+}
+
+/// Lengths of the longest common subsequence of all of `old` and each
+/// prefix of `new`, keeping one row of the table at a time.
+fn lcs_lengths<'a>(old: impl Iterator<Item = &'a Chunk>, new: impl Iterator<Item = &'a Chunk> + Clone) -> Vec<usize> {
+    let mut row = vec![0; new.clone().count() + 1];
+    for a in old {
+        let mut diagonal = 0;
+        for (j, b) in new.clone().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if a == b { diagonal + 1 } else { above.max(row[j]) };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+/// Describes how two chunks of the same type differ: field by field for
+/// known types, each parsed against its own file's header, and by length
+/// and contents otherwise.
+fn field_diffs(old: &Chunk, new: &Chunk, old_header: Option<&Ihdr>, new_header: Option<&Ihdr>) -> Vec<String> {
+    if KnownChunk::is_known(&old.chunk_type())
+        && let (Ok(old_known), Ok(new_known)) = (KnownChunk::from_chunk(old, old_header), KnownChunk::from_chunk(new, new_header)) {
+        let (old_fields, new_fields) = (fields(&old_known), fields(&new_known));
+        let mut diffs = Vec::new();
+        for (name, a) in &old_fields {
+            match new_fields.iter().find(|(other, _)| other == name) {
+                Some((_, b)) => diffs.extend(field_diff(name, a, b)),
+                None => diffs.push(format!("{}: {} -> none", name, a))
+            }
+        }
+        for (name, b) in new_fields.iter().filter(|(name, _)| !old_fields.iter().any(|(other, _)| other == name)) {
+            diffs.push(format!("{}: none -> {}", name, b));
+        }
+        return truncate(diffs);
+    }
+
+    let mut fields = Vec::new();
+    if old.length() != new.length() {
+        fields.push(format!("length: {} -> {}", old.length(), new.length()));
+    }
+    match (old.data_as_string(), new.data_as_string()) {
+        (Ok(a), Ok(b)) if a.len() <= 80 && b.len() <= 80 => fields.push(format!("data: {:?} -> {:?}", a, b)),
+        _ => fields.push(format!("data: {}", byte_diff(old.data(), new.data())))
+    }
+    fields
+}
+
+/// A field of a parsed chunk. Byte strings are compared rather than printed.
+enum Field<'a> {
+    Value(String),
+    Bytes(&'a [u8]),
+}
+
+impl Display for Field<'_> {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Field::Value(value) => write!(f, "{}", value),
+            Field::Bytes(bytes) => write!(f, "{} bytes", bytes.len())
+        }
+    }
+}
+
+fn field_diff(name: &str, old: &Field, new: &Field) -> Option<String> {
+    match (old, new) {
+        (Field::Bytes(a), Field::Bytes(b)) if a != b && a.len() == b.len() => Some(format!("{}: {}", name, byte_diff(a, b))),
+        (Field::Bytes(a), Field::Bytes(b)) if a == b => None,
+        (Field::Value(a), Field::Value(b)) if a == b => None,
+        _ => Some(format!("{}: {} -> {}", name, old, new))
+    }
+}
+
+fn list<T: Debug>(name: &str, values: &[T]) -> Vec<(String, Field<'static>)> {
+    values.iter().enumerate()
+        .map(|(indx, value)| (format!("{}[{}]", name, indx), Field::Value(format!("{:?}", value))))
+        .collect()
+}
+
+fn byte_diff(old: &[u8], new: &[u8]) -> String {
+    let first = old.iter().zip(new).position(|(a, b)| a != b).unwrap_or(old.len().min(new.len()));
+    let differing = old.iter().zip(new).filter(|(a, b)| a != b).count();
+    format!("{} bytes differ, first at byte {}", differing + old.len().abs_diff(new.len()), first)
+}
+
+/// The named fields of a parsed chunk. Lists are expanded per element so a
+/// single changed palette entry shows up on its own.
+fn fields(known: &KnownChunk) -> Vec<(String, Field<'_>)> {
+    let value = |name: &str, value: &dyn Debug| (name.to_string(), Field::Value(format!("{:?}", value)));
+    match known {
+        KnownChunk::Ihdr(c) => vec![
+            value("width", &c.width), value("height", &c.height), value("bit_depth", &c.bit_depth),
+            value("color_type", &c.color_type), value("compression_method", &c.compression_method),
+            value("filter_method", &c.filter_method), value("interlace_method", &c.interlace_method),
+        ],
+        KnownChunk::Plte(c) => list("entries", &c.entries),
+        KnownChunk::Trns(Trns::Gray(gray)) => vec![value("gray", gray)],
+        KnownChunk::Trns(Trns::Rgb(r, g, b)) => vec![value("red", r), value("green", g), value("blue", b)],
+        KnownChunk::Trns(Trns::Palette(alphas)) => list("alphas", alphas),
+        KnownChunk::Gama(c) => vec![value("gamma", &c.gamma)],
+        KnownChunk::Chrm(c) => vec![
+            value("white_point", &c.white_point), value("red", &c.red), value("green", &c.green), value("blue", &c.blue),
+        ],
+        KnownChunk::Srgb(c) => vec![value("intent", &c.intent)],
+        KnownChunk::Iccp(c) => vec![
+            value("name", &c.name), value("compression_method", &c.compression_method),
+            ("profile".to_string(), Field::Bytes(&c.profile)),
+        ],
+        KnownChunk::Sbit(c) => list("bits", &c.bits),
+        KnownChunk::Bkgd(Bkgd::PaletteIndex(index)) => vec![value("palette_index", index)],
+        KnownChunk::Bkgd(Bkgd::Gray(gray)) => vec![value("gray", gray)],
+        KnownChunk::Bkgd(Bkgd::Rgb(r, g, b)) => vec![value("red", r), value("green", g), value("blue", b)],
+        KnownChunk::Hist(c) => list("frequencies", &c.frequencies),
+        KnownChunk::Phys(c) => vec![
+            value("pixels_per_unit_x", &c.pixels_per_unit_x), value("pixels_per_unit_y", &c.pixels_per_unit_y), value("unit", &c.unit),
+        ],
+        KnownChunk::Splt(c) => {
+            let mut fields = vec![value("name", &c.name), value("sample_depth", &c.sample_depth)];
+            fields.extend(list("entries", &c.entries));
+            fields
+        },
+        KnownChunk::Time(c) => vec![
+            value("year", &c.year), value("month", &c.month), value("day", &c.day),
+            value("hour", &c.hour), value("minute", &c.minute), value("second", &c.second),
+        ],
+        KnownChunk::Exif(c) => vec![("data".to_string(), Field::Bytes(&c.data))],
+        KnownChunk::Cicp(c) => vec![
+            value("colour_primaries", &c.colour_primaries), value("transfer_function", &c.transfer_function),
+            value("matrix_coefficients", &c.matrix_coefficients), value("video_full_range", &c.video_full_range),
+        ],
+        KnownChunk::Mdcv(c) => vec![
+            value("red", &c.primaries[0]), value("green", &c.primaries[1]), value("blue", &c.primaries[2]),
+            value("white_point", &c.white_point), value("max_luminance", &c.max_luminance), value("min_luminance", &c.min_luminance),
+        ],
+        KnownChunk::Clli(c) => vec![
+            value("max_content_light_level", &c.max_content_light_level),
+            value("max_frame_average_light_level", &c.max_frame_average_light_level),
+        ],
+        KnownChunk::Actl(c) => vec![value("num_frames", &c.num_frames), value("num_plays", &c.num_plays)],
+        KnownChunk::Fctl(c) => vec![
+            value("sequence_number", &c.sequence_number), value("width", &c.width), value("height", &c.height),
+            value("x_offset", &c.x_offset), value("y_offset", &c.y_offset),
+            value("delay_num", &c.delay_num), value("delay_den", &c.delay_den),
+            value("dispose_op", &c.dispose_op), value("blend_op", &c.blend_op),
+        ],
+        KnownChunk::Fdat(c) => vec![
+            value("sequence_number", &c.sequence_number), ("frame_data".to_string(), Field::Bytes(&c.frame_data)),
+        ],
+    }
+}
+
+fn truncate(mut fields: Vec<String>) -> Vec<String> {
+    if fields.len() > MAX_FIELD_DIFFS {
+        let more = fields.len() - MAX_FIELD_DIFFS;
+        fields.truncate(MAX_FIELD_DIFFS);
+        fields.push(format!("... and {} more", more));
+    }
+    fields
+}
+
+/// How the decoded pixels of two images of the same size differ.
+pub struct PixelDiff {
+    pub differing: usize,
+    /// Largest difference in any channel, in 16-bit sample units.
+    pub max_delta: u16,
+    /// The old image faded to light gray, with every differing pixel in red.
+    pub image: Image,
+}
+
+impl PixelDiff {
+    pub fn identical(&self) -> bool {
+        self.differing == 0
+    }
+}
+
+pub fn diff_pixels(old: &Image, new: &Image) -> Result<PixelDiff> {
+    if (old.width(), old.height()) != (new.width(), new.height()) {
+        return Err(format!("Images are {}x{} and {}x{}", old.width(), old.height(), new.width(), new.height()).into());
+    }
+    let mut differing = 0;
+    let mut max_delta = 0;
+    let pixels = old.pixels().iter().zip(new.pixels()).map(|(a, b)| {
+        let delta = (0..4).map(|c| a[c].abs_diff(b[c])).max().unwrap();
+        max_delta = max_delta.max(delta);
+        if delta > 0 {
+            differing += 1;
+            [u16::MAX, 0, 0, u16::MAX]
+        } else {
+            let luma = ((a[0] as u32 * 299 + a[1] as u32 * 587 + a[2] as u32 * 114) / 1000 * a[3] as u32 / u16::MAX as u32) as u16;
+            let dimmed = luma / 4 + u16::MAX / 2;
+            [dimmed, dimmed, dimmed, u16::MAX]
+        }
+    }).collect();
+    Ok(PixelDiff { differing, max_delta, image: Image::new(old.width(), old.height(), pixels)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::known_chunk::{ColorType, Gama};
+
+    fn ihdr(width: u32) -> Chunk {
+        let header = Ihdr { width, height: 1, bit_depth: 8, color_type: ColorType::Rgb, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        Chunk::from_type_str("IHDR", header.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_identical_files() {
+        let png = Png::from_chunks(vec![ihdr(1), Chunk::from_type_str("IEND", Vec::new()).unwrap()]);
+        assert!(diff_chunks(&png, &png).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_changes() {
+        let gama = Chunk::from_type_str("gAMA", Gama { gamma: 45455 }.to_bytes()).unwrap();
+        let text = Chunk::from_type_str("tEXt", b"a".to_vec()).unwrap();
+        let idat = Chunk::from_type_str("IDAT", b"x".to_vec()).unwrap();
+        let iend = Chunk::from_type_str("IEND", Vec::new()).unwrap();
+        let old = Png::from_chunks(vec![
+            ihdr(1), gama.clone(), text.clone(), idat.clone(), iend.clone(),
+            Chunk::from_type_str("ruSt", b"old message".to_vec()).unwrap(),
+        ]);
+        let new = Png::from_chunks(vec![
+            ihdr(2), text, idat, gama, iend,
+            Chunk::from_type_str("ruSt", b"new message".to_vec()).unwrap(),
+            Chunk::from_type_str("teSt", b"added".to_vec()).unwrap(),
+        ]);
+        let changes = diff_chunks(&old, &new);
+        assert_eq!(changes, vec![
+            ChunkChange::Moved { chunk_type: "gAMA".to_string(), from: 1, to: 3 },
+            ChunkChange::Modified { chunk_type: "IHDR".to_string(), from: 0, to: 0, fields: vec!["width: 1 -> 2".to_string()] },
+            ChunkChange::Modified { chunk_type: "ruSt".to_string(), from: 5, to: 5,
+                fields: vec!["data: \"old message\" -> \"new message\"".to_string()] },
+            ChunkChange::Added { chunk_type: "teSt".to_string(), index: 6 },
+        ]);
+    }
+
+    #[test]
+    fn test_removed_chunk() {
+        let old = Png::from_chunks(vec![
+            ihdr(1),
+            Chunk::from_type_str("tEXt", b"a".to_vec()).unwrap(),
+            Chunk::from_type_str("IEND", Vec::new()).unwrap(),
+        ]);
+        let new = Png::from_chunks(vec![ihdr(1), Chunk::from_type_str("IEND", Vec::new()).unwrap()]);
+        assert_eq!(diff_chunks(&old, &new), vec![ChunkChange::Removed { chunk_type: "tEXt".to_string(), index: 1 }]);
+    }
+
+    #[test]
+    fn test_chunks_parse_against_their_own_header() {
+        let gray = Ihdr { width: 1, height: 1, bit_depth: 8, color_type: ColorType::Grayscale, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        let old = Png::from_chunks(vec![ihdr(1), Chunk::from_type_str("bKGD", vec![0, 1, 0, 2, 0, 3]).unwrap()]);
+        let new = Png::from_chunks(vec![
+            Chunk::from_type_str("IHDR", gray.to_bytes()).unwrap(),
+            Chunk::from_type_str("bKGD", vec![0, 5]).unwrap(),
+        ]);
+        let changes = diff_chunks(&old, &new);
+        assert_eq!(changes[0], ChunkChange::Modified { chunk_type: "IHDR".to_string(), from: 0, to: 0,
+            fields: vec!["color_type: Rgb -> Grayscale".to_string()] });
+        assert_eq!(changes[1], ChunkChange::Modified { chunk_type: "bKGD".to_string(), from: 1, to: 1,
+            fields: ["red: 1 -> none", "green: 2 -> none", "blue: 3 -> none", "gray: none -> 5"].map(String::from).to_vec() });
+    }
+
+    #[test]
+    fn test_byte_fields() {
+        let exif = |data: &[u8]| Png::from_chunks(vec![Chunk::from_type_str("eXIf", [b"MM\0*".as_slice(), data].concat()).unwrap()]);
+        let changes = diff_chunks(&exif(&[1, 2, 3]), &exif(&[1, 9, 3]));
+        assert_eq!(changes, vec![ChunkChange::Modified { chunk_type: "eXIf".to_string(), from: 0, to: 0,
+            fields: vec!["data: 1 bytes differ, first at byte 5".to_string()] }]);
+    }
+
+    #[test]
+    fn test_long_chunk_lists() {
+        let text = |indx: usize| Chunk::from_type_str("tEXt", format!("chunk {}", indx).into_bytes()).unwrap();
+        let old: Vec<Chunk> = (0..5000).map(text).collect();
+        let mut new = old.clone();
+        new.remove(2500);
+        new.swap(10, 4000);
+        let pairs = longest_common_subsequence(&old, &new);
+        assert_eq!(pairs.len(), 4997);
+        assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        assert!(pairs.iter().all(|&(i, j)| old[i] == new[j]));
+
+        let letters = |word: &str| word.bytes().map(|b| Chunk::from_type_str("tEXt", vec![b]).unwrap()).collect::<Vec<_>>();
+        assert_eq!(longest_common_subsequence(&letters("ABCBDAB"), &letters("BDCABA")).len(), 4);
+    }
+
+    #[test]
+    fn test_diff_pixels() {
+        let old = Image::from_rgba8(2, 1, &[0, 0, 0, 255, 10, 10, 10, 255]).unwrap();
+        let mut new = old.clone();
+        new.set_pixel(1, 0, [10 * 257, 12 * 257, 10 * 257, u16::MAX]);
+        let diff = diff_pixels(&old, &new).unwrap();
+        assert!(!diff.identical());
+        assert_eq!((diff.differing, diff.max_delta), (1, 2 * 257));
+        assert_eq!(diff.image.pixel(1, 0), [u16::MAX, 0, 0, u16::MAX]);
+        assert!(diff_pixels(&old, &old).unwrap().identical());
+        assert!(diff_pixels(&old, &Image::blank(1, 1)).is_err());
+    }
+}
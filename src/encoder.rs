@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use crate::chunk::Chunk;
+use crate::decoder::paeth;
+use crate::image::Image;
+use crate::known_chunk::{ColorType, Ihdr, Plte, Trns};
+use crate::png::Png;
+use crate::Result;
+
+/// Image data is split into IDAT/fdAT chunks of at most this many bytes.
+pub const MAX_DATA_CHUNK: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// Use the same filter type (0-4) on every scanline.
+    Fixed(u8),
+    /// Pick the filter with the smallest sum of absolute differences per scanline.
+    Adaptive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    pub filter: FilterStrategy,
+    /// zlib compression level, 0-10.
+    pub compression_level: u8,
+    /// RGBA8 palette entries, required for `ColorType::Indexed`.
+    pub palette: Option<Vec<[u8; 4]>>,
+}
+
+impl EncodeOptions {
+    /// RGBA at 8 bits per sample, or 16 when the image needs it.
+    pub fn for_image(image: &Image) -> EncodeOptions {
+        EncodeOptions {
+            color_type: ColorType::Rgba,
+            bit_depth: if image.fits_in_8_bits() { 8 } else { 16 },
+            filter: FilterStrategy::Adaptive,
+            compression_level: 6,
+            palette: None,
+        }
+    }
+
+    /// The PLTE and tRNS chunks for an indexed image. tRNS is left out
+    /// when every entry is opaque.
+    pub fn palette_chunks(&self) -> Option<(Plte, Option<Trns>)> {
+        let palette = self.palette.as_ref()?;
+        let plte = Plte { entries: palette.iter().map(|&[r, g, b, _]| [r, g, b]).collect() };
+        let alphas: Vec<u8> = palette.iter().map(|entry| entry[3]).collect();
+        let trns = alphas.iter().rposition(|&alpha| alpha != 255)
+            .map(|last| Trns::Palette(alphas[..=last].to_vec()));
+        Some((plte, trns))
+    }
+
+    pub fn header(&self, width: u32, height: u32) -> Ihdr {
+        Ihdr {
+            width,
+            height,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+}
+
+/// Encodes an image as IHDR, IDAT and IEND chunks.
+pub fn encode(image: &Image, options: &EncodeOptions) -> Result<Png> {
+    let header = options.header(image.width(), image.height());
+    header.validate()?;
+    let mut chunks = vec![Chunk::from_type_str("IHDR", header.to_bytes())?];
+    if options.color_type == ColorType::Indexed
+        && let Some((plte, trns)) = options.palette_chunks() {
+        chunks.push(Chunk::from_type_str("PLTE", plte.to_bytes())?);
+        if let Some(trns) = trns {
+            chunks.push(Chunk::from_type_str("tRNS", trns.to_bytes())?);
+        }
+    }
+    for data in split_data(&encode_image_data(image, options)?) {
+        chunks.push(Chunk::from_type_str("IDAT", data)?);
+    }
+    chunks.push(Chunk::from_type_str("IEND", Vec::new())?);
+    Ok(Png::from_chunks(chunks))
+}
+
+/// Filters and compresses `image` into a zlib stream suitable for IDAT or fdAT.
+pub fn encode_image_data(image: &Image, options: &EncodeOptions) -> Result<Vec<u8>> {
+    Ok(compress_to_vec_zlib(&filter_image(image, options)?, options.compression_level))
+}
+
+/// Packs `image` into scanlines at the options' color type and bit depth
+/// and filters them, ready for compression. Samples are truncated to lower
+/// bit depths, so reducing the depth is only lossless for images whose
+/// samples already fit.
+pub fn filter_image(image: &Image, options: &EncodeOptions) -> Result<Vec<u8>> {
+    if !options.color_type.allowed_bit_depths().contains(&options.bit_depth) {
+        return Err(format!("Cannot encode {:?} at bit depth {}", options.color_type, options.bit_depth).into());
+    }
+    let indices = match (&options.color_type, &options.palette) {
+        (ColorType::Indexed, Some(palette)) => {
+            if palette.len() > 1 << options.bit_depth {
+                return Err(format!("{} palette entries don't fit in {} bits", palette.len(), options.bit_depth).into());
+            }
+            Some(palette.iter().enumerate()
+                .map(|(indx, rgba)| (rgba.map(|c| c as u16 * 257), indx as u16))
+                .collect::<HashMap<[u16; 4], u16>>())
+        },
+        (ColorType::Indexed, None) => return Err("Indexed images need a palette to be encoded".into()),
+        _ => None
+    };
+    let lines = (0..image.height())
+        .map(|y| pack_line(image, y, options.color_type, options.bit_depth, indices.as_ref()))
+        .collect::<Result<Vec<Vec<u8>>>>()?;
+    let bpp = (options.color_type.channels() * options.bit_depth as usize).div_ceil(8);
+    Ok(filter_lines(&lines, bpp, options.filter))
+}
+
+/// Splits image data into pieces for consecutive IDAT or fdAT chunks.
+pub fn split_data(data: &[u8]) -> Vec<Vec<u8>> {
+    if data.is_empty() {
+        return vec![Vec::new()];
+    }
+    data.chunks(MAX_DATA_CHUNK).map(|piece| piece.to_vec()).collect()
+}
+
+/// Prefixes each unfiltered scanline with its filter type and filters it.
+pub fn filter_lines(lines: &[Vec<u8>], bpp: usize, strategy: FilterStrategy) -> Vec<u8> {
+    let mut out = Vec::with_capacity(lines.iter().map(|line| line.len() + 1).sum());
+    let mut prev = vec![0u8; lines.first().map_or(0, |line| line.len())];
+    for line in lines {
+        let filtered = match strategy {
+            FilterStrategy::Fixed(filter) => (filter, filter_line(filter, line, &prev, bpp)),
+            FilterStrategy::Adaptive => (0..=4)
+                .map(|filter| (filter, filter_line(filter, line, &prev, bpp)))
+                .min_by_key(|(_, filtered)| filtered.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>())
+                .unwrap(),
+        };
+        out.push(filtered.0);
+        out.extend(filtered.1);
+        prev = line.clone();
+    }
+    out
+}
+
+fn filter_line(filter: u8, line: &[u8], prev: &[u8], bpp: usize) -> Vec<u8> {
+    (0..line.len()).map(|i| {
+        let left = if i >= bpp { line[i - bpp] } else { 0 };
+        let upper_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predictor = match filter {
+            1 => left,
+            2 => prev[i],
+            3 => ((left as u16 + prev[i] as u16) / 2) as u8,
+            4 => paeth(left, prev[i], upper_left),
+            _ => 0
+        };
+        line[i].wrapping_sub(predictor)
+    }).collect()
+}
+
+fn pack_line(image: &Image, y: u32, color_type: ColorType, bit_depth: u8, indices: Option<&HashMap<[u16; 4], u16>>) -> Result<Vec<u8>> {
+    let mut line = Vec::with_capacity((image.width() as usize * color_type.channels() * bit_depth as usize).div_ceil(8));
+    let (mut bits, mut filled) = (0u16, 0);
+    for x in 0..image.width() {
+        let pixel = image.pixel(x, y);
+        let [r, g, b, a] = pixel;
+        let index;
+        let samples: &[u16] = match color_type {
+            ColorType::Indexed => {
+                index = match indices.and_then(|indices| indices.get(&pixel)) {
+                    Some(&index) => index,
+                    None => return Err(format!("Pixel at ({}, {}) is not in the palette", x, y).into())
+                };
+                &[index]
+            },
+            ColorType::Grayscale => &[r],
+            ColorType::GrayscaleAlpha => &[r, a],
+            ColorType::Rgb => &[r, g, b],
+            _ => &[r, g, b, a],
+        };
+        for &sample in samples {
+            let sample = if color_type == ColorType::Indexed { sample } else { sample >> (16 - bit_depth) };
+            match bit_depth {
+                16 => line.extend(sample.to_be_bytes()),
+                8 => line.push(sample as u8),
+                _ => {
+                    bits = (bits << bit_depth) | sample;
+                    filled += bit_depth;
+                    if filled == 8 {
+                        line.push(bits as u8);
+                        (bits, filled) = (0, 0);
+                    }
+                }
+            }
+        }
+    }
+    if filled > 0 {
+        line.push((bits << (8 - filled)) as u8);
+    }
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::unfilter;
+
+    fn gradient(width: u32, height: u32) -> Image {
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|i| [(i * 7) as u8, (i * 13) as u8, (i * 29) as u8, 255 - i as u8])
+            .collect();
+        Image::from_rgba8(width, height, &rgba).unwrap()
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let image = gradient(7, 5);
+        let png = encode(&image, &EncodeOptions::for_image(&image)).unwrap();
+        assert_eq!(png.decode().unwrap(), image);
+    }
+
+    #[test]
+    fn test_encode_16_bit_round_trip() {
+        let mut image = gradient(3, 3);
+        image.set_pixel(1, 1, [1, 2, 3, 4]);
+        let options = EncodeOptions::for_image(&image);
+        assert_eq!(options.bit_depth, 16);
+        let png = encode(&image, &options).unwrap();
+        assert_eq!(png.decode().unwrap(), image);
+    }
+
+    #[test]
+    fn test_every_filter_round_trips() {
+        let lines = vec![vec![1, 2, 3, 4, 5, 6], vec![9, 8, 7, 6, 5, 4]];
+        for filter in 0..=4 {
+            let filtered = filter_lines(&lines, 3, FilterStrategy::Fixed(filter));
+            let mut prev = vec![0u8; 6];
+            for (i, row) in filtered.chunks(7).enumerate() {
+                let mut line = row[1..].to_vec();
+                unfilter(row[0], &mut line, &prev, 3).unwrap();
+                assert_eq!(line, lines[i]);
+                prev = line;
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_indexed_with_transparency() {
+        let image = Image::from_rgba8(3, 1, &[255, 0, 0, 255, 0, 0, 255, 128, 255, 0, 0, 255]).unwrap();
+        let options = EncodeOptions {
+            color_type: ColorType::Indexed,
+            bit_depth: 1,
+            palette: Some(vec![[0, 0, 255, 128], [255, 0, 0, 255]]),
+            ..EncodeOptions::for_image(&image)
+        };
+        let png = encode(&image, &options).unwrap();
+        assert_eq!(png.palette().unwrap().unwrap().entries, vec![[0, 0, 255], [255, 0, 0]]);
+        assert_eq!(png.transparency().unwrap(), Some(Trns::Palette(vec![128])));
+        assert_eq!(png.decode().unwrap(), image);
+
+        let missing = EncodeOptions { palette: Some(vec![[0, 0, 255, 128]]), ..options };
+        assert!(encode(&image, &missing).is_err());
+    }
+
+    #[test]
+    fn test_encode_low_bit_depth_gray() {
+        let image = Image::from_rgba8(5, 2, &[0, 0, 0, 255, 255, 255, 255, 255].repeat(5)).unwrap();
+        for bit_depth in [1, 2, 4] {
+            let options = EncodeOptions { color_type: ColorType::Grayscale, bit_depth, ..EncodeOptions::for_image(&image) };
+            let png = encode(&image, &options).unwrap();
+            assert_eq!(png.decode().unwrap(), image);
+        }
+    }
+
+    #[test]
+    fn test_encode_gray() {
+        let image = Image::from_rgba8(2, 1, &[10, 10, 10, 255, 20, 20, 20, 255]).unwrap();
+        let options = EncodeOptions { color_type: ColorType::Grayscale, ..EncodeOptions::for_image(&image) };
+        let png = encode(&image, &options).unwrap();
+        assert_eq!(png.ihdr().unwrap().color_type, ColorType::Grayscale);
+        assert_eq!(png.decode().unwrap(), image);
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt::Write;
+use std::ops::Range;
+use crate::chunk::CRC_PNG;
+use crate::known_chunk::{Ihdr, KnownChunk};
+use crate::png::Png;
+use crate::Result;
+
+/// Bytes shown per row.
+const ROW: usize = 16;
+
+pub struct HexdumpOptions {
+    /// Only show bytes in this range of file offsets.
+    pub window: Option<Range<usize>>,
+    /// Only show the chunk with this type, or at this index.
+    pub chunk: Option<String>,
+    /// Color each field with ANSI escapes.
+    pub color: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Signature,
+    Length,
+    Type,
+    Data,
+    Crc(bool),
+    /// Bytes that don't form a complete chunk.
+    Unparsed,
+}
+
+impl Field {
+    fn color(&self) -> &'static str {
+        match self {
+            Field::Signature => "35",
+            Field::Length => "33",
+            Field::Type => "36",
+            Field::Data => "0",
+            Field::Crc(true) => "32",
+            Field::Crc(false) => "1;31",
+            Field::Unparsed => "2",
+        }
+    }
+}
+
+/// A run of the file shown under one heading: the signature, a chunk, or
+/// whatever is left after the last complete chunk.
+struct Section {
+    range: Range<usize>,
+    heading: String,
+    chunk: Option<(usize, String)>,
+    fields: Vec<(Range<usize>, Field)>,
+}
+
+/// Walks the chunk structure the way `Png::try_from` does, but without
+/// stopping at CRC mismatches, so they can be shown.
+fn sections(bytes: &[u8]) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut pos = 0;
+    if bytes.len() >= 8 {
+        let valid = bytes[..8] == Png::STANDARD_HEADER;
+        sections.push(Section {
+            range: 0..8,
+            heading: format!("signature{}", if valid { "" } else { " (INVALID)" }),
+            chunk: None,
+            fields: vec![(0..8, Field::Signature)],
+        });
+        pos = 8;
+    }
+    let mut indx = 0;
+    while bytes.len() - pos >= 12 {
+        let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = match pos.checked_add(12 + length) {
+            Some(end) if end <= bytes.len() => end,
+            _ => break
+        };
+        let chunk_type = String::from_utf8_lossy(&bytes[pos + 4..pos + 8]).to_string();
+        let data = &bytes[pos + 8..end - 4];
+        let expected = CRC_PNG.checksum(&bytes[pos + 4..end - 4]);
+        let found = u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap());
+
+        let mut heading = format!("chunk {} at {:#x}: {}, length {}, CRC {:08x} ", indx, pos, chunk_type, length, found);
+        if expected == found {
+            heading.push_str("ok");
+        } else {
+            write!(heading, "MISMATCH (expected {:08x})", expected).unwrap();
+        }
+        if chunk_type == "IHDR"
+            && let Ok(ihdr) = Ihdr::parse(data) {
+            write!(heading, " | {}", KnownChunk::Ihdr(ihdr)).unwrap();
+        }
+        sections.push(Section {
+            range: pos..end,
+            heading,
+            chunk: Some((indx, chunk_type)),
+            fields: vec![
+                (pos..pos + 4, Field::Length),
+                (pos + 4..pos + 8, Field::Type),
+                (pos + 8..end - 4, Field::Data),
+                (end - 4..end, Field::Crc(expected == found)),
+            ],
+        });
+        pos = end;
+        indx += 1;
+    }
+    if pos < bytes.len() {
+        sections.push(Section {
+            range: pos..bytes.len(),
+            heading: format!("{} bytes at {:#x} are not a complete chunk", bytes.len() - pos, pos),
+            chunk: None,
+            fields: vec![(pos..bytes.len(), Field::Unparsed)],
+        });
+    }
+    sections
+}
+
+/// Renders `bytes` as a hex dump with a heading at each chunk boundary.
+/// Rows start at each chunk's first byte, so fields line up per chunk.
+pub fn hexdump(bytes: &[u8], options: &HexdumpOptions) -> Result<String> {
+    let window = options.window.clone().unwrap_or(0..bytes.len());
+    let sections: Vec<Section> = sections(bytes).into_iter()
+        .filter(|section| match (&options.chunk, &section.chunk) {
+            (Some(selected), Some((indx, chunk_type))) => *selected == indx.to_string() || selected == chunk_type,
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+        .filter(|section| section.range.start < window.end && window.start < section.range.end)
+        .collect();
+    if sections.is_empty() {
+        return Err("Nothing to show for that selection".into());
+    }
+
+    let mut out = String::new();
+    for section in sections {
+        writeln!(out, "{}", section.heading).unwrap();
+        for row in section.range.clone().step_by(ROW) {
+            let shown: Vec<usize> = (row..(row + ROW).min(section.range.end))
+                .filter(|pos| window.contains(pos))
+                .collect();
+            if shown.is_empty() {
+                continue;
+            }
+            write!(out, "{:08x}  ", row).unwrap();
+            let skipped = shown[0] - row;
+            out.push_str(&"   ".repeat(skipped));
+            for &pos in &shown {
+                let field = section.fields.iter().find(|(range, _)| range.contains(&pos)).unwrap().1;
+                if options.color {
+                    write!(out, "\x1b[{}m{:02x}\x1b[0m ", field.color(), bytes[pos]).unwrap();
+                } else {
+                    write!(out, "{:02x} ", bytes[pos]).unwrap();
+                }
+            }
+            out.push_str(&"   ".repeat(ROW - skipped - shown.len()));
+            let ascii: String = shown.iter()
+                .map(|&pos| if bytes[pos].is_ascii_graphic() { bytes[pos] as char } else { '.' })
+                .collect();
+            writeln!(out, " |{}|", ascii).unwrap();
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    fn options() -> HexdumpOptions {
+        HexdumpOptions { window: None, chunk: None, color: false }
+    }
+
+    #[test]
+    fn test_headings() {
+        let dump = hexdump(&IMAGE[..100], &options()).unwrap();
+        let headings: Vec<&str> = dump.lines().filter(|line| !line.starts_with('0')).collect();
+        assert_eq!(headings.len(), 3);
+        assert_eq!(headings[0], "signature");
+        assert!(headings[1].starts_with("chunk 0 at 0x8: IHDR, length 13, CRC"));
+        assert!(headings[1].contains("ok | IHDR: 386x395"));
+        assert_eq!(headings[2], "67 bytes at 0x21 are not a complete chunk");
+        assert!(dump.contains("00000008  00 00 00 0d 49 48 44 52"));
+    }
+
+    #[test]
+    fn test_crc_mismatch_is_marked() {
+        let mut bytes = IMAGE[..33].to_vec();
+        bytes[32] ^= 1;
+        let dump = hexdump(&bytes, &HexdumpOptions { color: true, ..options() }).unwrap();
+        assert!(dump.contains("MISMATCH"));
+        assert!(dump.contains("\x1b[1;31m"));
+    }
+
+    #[test]
+    fn test_selection() {
+        let dump = hexdump(IMAGE, &HexdumpOptions { chunk: Some("IEND".to_string()), ..options() }).unwrap();
+        assert_eq!(dump.lines().count(), 2);
+        assert!(dump.starts_with("chunk 2 at 0xa1c7: IEND"));
+
+        let dump = hexdump(IMAGE, &HexdumpOptions { window: Some(10..14), ..options() }).unwrap();
+        assert_eq!(dump.lines().nth(1).unwrap(), format!("00000008        00 0d 49 48 {} |..IH|", "   ".repeat(10)));
+        assert!(hexdump(IMAGE, &HexdumpOptions { chunk: Some("zzZz".to_string()), ..options() }).is_err());
+    }
+}
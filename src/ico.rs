@@ -0,0 +1,244 @@
+use std::convert::TryFrom;
+use crate::png::Png;
+use crate::Error;
+use crate::Result;
+
+/// Size of the ICONDIR header before the entries.
+const DIR_HEADER: usize = 6;
+/// Size of each ICONDIRENTRY.
+const DIR_ENTRY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconKind {
+    Icon,
+    Cursor,
+}
+
+/// The image stored for one size entry.
+pub enum IconImage {
+    /// A complete PNG, signature included.
+    Png(Png),
+    /// A BMP without its file header, kept as is.
+    Bitmap(Vec<u8>),
+}
+
+impl IconImage {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            IconImage::Png(png) => png.as_bytes(),
+            IconImage::Bitmap(bytes) => bytes.clone(),
+        }
+    }
+}
+
+pub struct IconEntry {
+    width: u8,
+    height: u8,
+    colors: u8,
+    reserved: u8,
+    /// Color planes for icons, the hotspot x coordinate for cursors.
+    planes: u16,
+    /// Bits per pixel for icons, the hotspot y coordinate for cursors.
+    bit_count: u16,
+    /// Where the image started in the file it was read from.
+    offset: u32,
+    image: IconImage,
+}
+
+impl IconEntry {
+    /// Width in pixels; the directory stores 256 as 0.
+    pub fn width(&self) -> u32 {
+        if self.width == 0 { 256 } else { self.width as u32 }
+    }
+
+    pub fn height(&self) -> u32 {
+        if self.height == 0 { 256 } else { self.height as u32 }
+    }
+
+    /// Palette size, or 0 when the image has no palette.
+    pub fn colors(&self) -> u8 {
+        self.colors
+    }
+
+    pub fn planes(&self) -> u16 {
+        self.planes
+    }
+
+    pub fn bit_count(&self) -> u16 {
+        self.bit_count
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn image(&self) -> &IconImage {
+        &self.image
+    }
+
+    pub fn png(&self) -> Option<&Png> {
+        match &self.image {
+            IconImage::Png(png) => Some(png),
+            IconImage::Bitmap(_) => None,
+        }
+    }
+
+    pub fn png_mut(&mut self) -> Option<&mut Png> {
+        match &mut self.image {
+            IconImage::Png(png) => Some(png),
+            IconImage::Bitmap(_) => None,
+        }
+    }
+}
+
+/// An ICO or CUR file: a directory of entries, each holding a PNG or a BMP.
+pub struct Ico {
+    kind: IconKind,
+    entries: Vec<IconEntry>,
+}
+
+impl Ico {
+    pub fn kind(&self) -> IconKind {
+        self.kind
+    }
+
+    pub fn entries(&self) -> &[IconEntry] {
+        &self.entries
+    }
+
+    pub fn entry_mut(&mut self, indx: usize) -> Result<&mut IconEntry> {
+        let len = self.entries.len();
+        self.entries.get_mut(indx).ok_or(format!("Entry {} is out of range, the file has {}", indx, len).into())
+    }
+
+    /// Writes the directory followed by every image in directory order,
+    /// with offsets and sizes recomputed for images that changed size.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let images: Vec<Vec<u8>> = self.entries.iter().map(|entry| entry.image.as_bytes()).collect();
+        let kind: u16 = match self.kind {
+            IconKind::Icon => 1,
+            IconKind::Cursor => 2,
+        };
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        let mut offset = DIR_HEADER + DIR_ENTRY * self.entries.len();
+        for (entry, image) in self.entries.iter().zip(&images) {
+            bytes.extend_from_slice(&[entry.width, entry.height, entry.colors, entry.reserved]);
+            bytes.extend_from_slice(&entry.planes.to_le_bytes());
+            bytes.extend_from_slice(&entry.bit_count.to_le_bytes());
+            bytes.extend_from_slice(&(image.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += image.len();
+        }
+        for image in images {
+            bytes.extend(image);
+        }
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Ico {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let le_u16 = |pos: usize| u16::from_le_bytes([bytes[pos], bytes[pos + 1]]);
+        let le_u32 = |pos: usize| u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+        if bytes.len() < DIR_HEADER || le_u16(0) != 0 {
+            return Err("Not an ICO or CUR file".into());
+        }
+        let kind = match le_u16(2) {
+            1 => IconKind::Icon,
+            2 => IconKind::Cursor,
+            kind => return Err(format!("Unknown icon resource type {}", kind).into())
+        };
+        let count = le_u16(4) as usize;
+        if bytes.len() < DIR_HEADER + count * DIR_ENTRY {
+            return Err(format!("Directory of {} entries is truncated", count).into());
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        for indx in 0..count {
+            let pos = DIR_HEADER + indx * DIR_ENTRY;
+            let (size, offset) = (le_u32(pos + 8), le_u32(pos + 12));
+            let data = (offset as usize).checked_add(size as usize)
+                .and_then(|end| bytes.get(offset as usize..end))
+                .ok_or(format!("Entry {} ({} bytes at offset {}) runs past the end of the file", indx, size, offset))?;
+            let image = if data.starts_with(&Png::STANDARD_HEADER) {
+                IconImage::Png(Png::try_from(data).map_err(|e| format!("Entry {} is not a valid PNG: {}", indx, e))?)
+            } else {
+                IconImage::Bitmap(data.to_vec())
+            };
+            entries.push(IconEntry {
+                width: bytes[pos],
+                height: bytes[pos + 1],
+                colors: bytes[pos + 2],
+                reserved: bytes[pos + 3],
+                planes: le_u16(pos + 4),
+                bit_count: le_u16(pos + 6),
+                offset,
+                image,
+            });
+        }
+        Ok(Ico { kind, entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    /// A cursor with a BMP entry followed by image.png.
+    fn testing_cur() -> Vec<u8> {
+        let bitmap = [40u8; 48];
+        let mut bytes = vec![0, 0, 2, 0, 2, 0];
+        bytes.extend_from_slice(&[16, 16, 0, 0, 3, 0, 4, 0]);
+        bytes.extend_from_slice(&(bitmap.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&38u32.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0, 1, 0, 32, 0]);
+        bytes.extend_from_slice(&(IMAGE.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(38 + bitmap.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&bitmap);
+        bytes.extend_from_slice(IMAGE);
+        bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        let ico = Ico::try_from(testing_cur().as_slice()).unwrap();
+        assert_eq!(ico.kind(), IconKind::Cursor);
+        assert_eq!(ico.entries().len(), 2);
+        assert!(ico.entries()[0].png().is_none());
+        assert_eq!((ico.entries()[0].planes(), ico.entries()[0].bit_count()), (3, 4));
+        let entry = &ico.entries()[1];
+        assert_eq!((entry.width(), entry.height(), entry.offset()), (256, 256, 86));
+        assert_eq!(entry.png().unwrap().as_bytes(), IMAGE);
+    }
+
+    #[test]
+    fn test_round_trip_after_edit() {
+        let bytes = testing_cur();
+        let mut ico = Ico::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(ico.as_bytes(), bytes);
+
+        let png = ico.entry_mut(1).unwrap().png_mut().unwrap();
+        png.append_chunk(Chunk::from_type_str("ruSt", b"hidden".to_vec()).unwrap());
+        let edited = Ico::try_from(ico.as_bytes().as_slice()).unwrap();
+        let png = edited.entries()[1].png().unwrap();
+        assert_eq!(png.chunks().last().unwrap().data(), b"hidden");
+        assert_eq!(edited.as_bytes().len(), bytes.len() + 18);
+        assert!(ico.entry_mut(2).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Ico::try_from(IMAGE).is_err());
+        let mut bytes = testing_cur();
+        bytes.truncate(100);
+        assert!(Ico::try_from(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::Result;
+
+/// The most pixels a decoded image may have, 1 GiB at 8 bytes a pixel. A
+/// few bytes of compressed data can claim far more than that.
+pub const MAX_PIXELS: u64 = 1 << 27;
+
+/// A decoded image in canonical form: every pixel as 16-bit RGBA, whatever
+/// the color type and bit depth it was stored with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<[u16; 4]>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, pixels: Vec<[u16; 4]>) -> Result<Image> {
+        if pixels.len() != width as usize * height as usize {
+            return Err(format!("Expected {} pixels for {}x{}, found {}", width as usize * height as usize, width, height, pixels.len()).into());
+        }
+        Ok(Image { width, height, pixels })
+    }
+
+    /// Fails for sizes over `MAX_PIXELS`, so callers can check before
+    /// allocating an image from untrusted dimensions.
+    pub fn check_size(width: u32, height: u32) -> Result<()> {
+        if width as u64 * height as u64 > MAX_PIXELS {
+            return Err(format!("A {}x{} image has more than {} pixels", width, height, MAX_PIXELS).into());
+        }
+        Ok(())
+    }
+
+    /// A fully transparent black image.
+    pub fn blank(width: u32, height: u32) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![[0; 4]; width as usize * height as usize],
+        }
+    }
+
+    pub fn from_rgba8(width: u32, height: u32, rgba: &[u8]) -> Result<Image> {
+        if !rgba.len().is_multiple_of(4) {
+            return Err("RGBA data is not a multiple of 4 bytes".into());
+        }
+        let pixels = rgba.chunks_exact(4)
+            .map(|p| [p[0] as u16 * 257, p[1] as u16 * 257, p[2] as u16 * 257, p[3] as u16 * 257])
+            .collect();
+        Image::new(width, height, pixels)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[u16; 4]] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [[u16; 4]] {
+        &mut self.pixels
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u16; 4] {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u16; 4]) {
+        self.pixels[y as usize * self.width as usize + x as usize] = pixel;
+    }
+
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|p| p.map(|c| (c >> 8) as u8))
+            .collect()
+    }
+
+    /// Big-endian RGBA16 samples, row by row.
+    pub fn to_rgba16_bytes(&self) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|p| p.iter().flat_map(|c| c.to_be_bytes()).collect::<Vec<u8>>())
+            .collect()
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.pixels.iter().all(|p| p[3] == u16::MAX)
+    }
+
+    pub fn is_grayscale(&self) -> bool {
+        self.pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2])
+    }
+
+    /// True when every sample survives a round trip through 8 bits.
+    pub fn fits_in_8_bits(&self) -> bool {
+        self.pixels.iter().all(|p| p.iter().all(|c| c % 257 == 0))
+    }
+
+    /// Copies out the `width` x `height` region whose top-left corner is at (`x`, `y`).
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Image> {
+        let inside = |start: u32, len: u32, size: u32| start.checked_add(len).is_some_and(|end| end <= size);
+        if !inside(x, width, self.width) || !inside(y, height, self.height) {
+            return Err(format!("Region {}x{} at ({}, {}) is outside the {}x{} image", width, height, x, y, self.width, self.height).into());
+        }
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for row in y..y + height {
+            let start = row as usize * self.width as usize + x as usize;
+            pixels.extend_from_slice(&self.pixels[start..start + width as usize]);
+        }
+        Image::new(width, height, pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_rgba8_scales_to_16_bits() {
+        let image = Image::from_rgba8(1, 1, &[255, 128, 0, 255]).unwrap();
+        assert_eq!(image.pixel(0, 0), [65535, 128 * 257, 0, 65535]);
+        assert_eq!(image.to_rgba8(), vec![255, 128, 0, 255]);
+        assert!(image.fits_in_8_bits());
+        assert!(image.is_opaque());
+    }
+
+    #[test]
+    fn test_wrong_pixel_count() {
+        assert!(Image::new(2, 2, vec![[0; 4]; 3]).is_err());
+    }
+
+    #[test]
+    fn test_crop() {
+        let mut image = Image::blank(3, 3);
+        image.set_pixel(1, 1, [1, 2, 3, 4]);
+        let cropped = image.crop(1, 1, 2, 2).unwrap();
+        assert_eq!(cropped.pixel(0, 0), [1, 2, 3, 4]);
+        assert!(image.crop(2, 2, 2, 2).is_err());
+        assert!(image.crop(1, 0, u32::MAX, 1).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use crate::Result;
+
+/// Key files are one line per key: a label naming the algorithm and what
+/// the key is for, then the key in hex.
+pub fn to_key_line(label: &str, key: &[u8; 32]) -> String {
+    format!("{} {}\n", label, hex(key))
+}
+
+/// Lowercase hex, as printed for keys, key ids and digests.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Finds the key with `label` in the contents of a key file.
+pub fn from_key_file(contents: &str, label: &str) -> Result<[u8; 32]> {
+    let hex = contents.lines()
+        .find_map(|line| line.strip_prefix(label).and_then(|rest| rest.strip_prefix(' ')))
+        .ok_or(format!("No {} key found", label))?
+        .trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(format!("Invalid {} key", label).into());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| format!("Invalid {} key", label))?;
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_lines() {
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8 * 7);
+        let file = format!("# comment\n{}other-label {}", to_key_line("test-public", &key), "00".repeat(32));
+        assert_eq!(from_key_file(&file, "test-public").unwrap(), key);
+        assert!(from_key_file(&file, "test-secret").is_err());
+        assert!(from_key_file("test-public abc", "test-public").is_err());
+        assert!(from_key_file(&format!("test-public {}", "zz".repeat(32)), "test-public").is_err());
+    }
+}
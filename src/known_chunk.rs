@@ -0,0 +1,1118 @@
+use std::{convert::TryFrom, fmt::{Display, Formatter}, str::FromStr};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl ColorType {
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(format!("Invalid color type {value}").into())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+impl Ihdr {
+    pub fn parse(data: &[u8]) -> Result<Ihdr> {
+        expect_length("IHDR", data, 13)?;
+        let ihdr = Ihdr {
+            width: be_u32(data, 0),
+            height: be_u32(data, 4),
+            bit_depth: data[8],
+            color_type: ColorType::try_from(data[9])?,
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: data[12],
+        };
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.width > i32::MAX as u32 {
+            return Err(format!("Invalid width {}", self.width).into());
+        }
+        if self.height == 0 || self.height > i32::MAX as u32 {
+            return Err(format!("Invalid height {}", self.height).into());
+        }
+        if !self.color_type.allowed_bit_depths().contains(&self.bit_depth) {
+            return Err(format!("Bit depth {} is not allowed for {:?}", self.bit_depth, self.color_type).into());
+        }
+        if self.compression_method != 0 {
+            return Err(format!("Unknown compression method {}", self.compression_method).into());
+        }
+        if self.filter_method != 0 {
+            return Err(format!("Unknown filter method {}", self.filter_method).into());
+        }
+        if self.interlace_method > 1 {
+            return Err(format!("Unknown interlace method {}", self.interlace_method).into());
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.width.to_be_bytes().into_iter()
+            .chain(self.height.to_be_bytes())
+            .chain([self.bit_depth, self.color_type as u8, self.compression_method, self.filter_method, self.interlace_method])
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plte {
+    pub entries: Vec<[u8; 3]>,
+}
+
+impl Plte {
+    pub fn parse(data: &[u8]) -> Result<Plte> {
+        if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 256 * 3 {
+            return Err(format!("Invalid PLTE length {}", data.len()).into());
+        }
+        Ok(Plte {
+            entries: data.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect()
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.entries.iter().flatten().copied().collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trns {
+    Gray(u16),
+    Rgb(u16, u16, u16),
+    Palette(Vec<u8>),
+}
+
+impl Trns {
+    /// Without a header the layout is guessed from the length, which is
+    /// ambiguous for palettes of exactly 2 or 6 entries.
+    pub fn parse(data: &[u8], header: Option<&Ihdr>) -> Result<Trns> {
+        match header.map(|h| h.color_type) {
+            Some(ColorType::Grayscale) => {
+                expect_length("tRNS", data, 2)?;
+                Ok(Trns::Gray(be_u16(data, 0)))
+            },
+            Some(ColorType::Rgb) => {
+                expect_length("tRNS", data, 6)?;
+                Ok(Trns::Rgb(be_u16(data, 0), be_u16(data, 2), be_u16(data, 4)))
+            },
+            Some(ColorType::Indexed) => {
+                if data.len() > 256 {
+                    return Err(format!("Invalid tRNS length {}", data.len()).into());
+                }
+                Ok(Trns::Palette(data.to_vec()))
+            },
+            Some(color_type) => Err(format!("tRNS is not allowed for {:?}", color_type).into()),
+            None => match data.len() {
+                2 => Ok(Trns::Gray(be_u16(data, 0))),
+                6 => Ok(Trns::Rgb(be_u16(data, 0), be_u16(data, 2), be_u16(data, 4))),
+                len if len <= 256 => Ok(Trns::Palette(data.to_vec())),
+                len => Err(format!("Invalid tRNS length {len}").into())
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Trns::Gray(gray) => gray.to_be_bytes().to_vec(),
+            Trns::Rgb(r, g, b) => [r, g, b].iter().flat_map(|c| c.to_be_bytes()).collect(),
+            Trns::Palette(alphas) => alphas.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gama {
+    pub gamma: u32,
+}
+
+impl Gama {
+    pub fn parse(data: &[u8]) -> Result<Gama> {
+        expect_length("gAMA", data, 4)?;
+        let gamma = be_u32(data, 0);
+        if gamma == 0 {
+            return Err("gAMA value must be non-zero".into());
+        }
+        Ok(Gama { gamma })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.gamma.to_be_bytes().to_vec()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chrm {
+    pub white_point: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+impl Chrm {
+    pub fn parse(data: &[u8]) -> Result<Chrm> {
+        expect_length("cHRM", data, 32)?;
+        let pair = |at: usize| (be_u32(data, at), be_u32(data, at + 4));
+        Ok(Chrm {
+            white_point: pair(0),
+            red: pair(8),
+            green: pair(16),
+            blue: pair(24),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.white_point, self.red, self.green, self.blue].iter()
+            .flat_map(|(x, y)| x.to_be_bytes().into_iter().chain(y.to_be_bytes()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Srgb {
+    pub intent: RenderingIntent,
+}
+
+impl Srgb {
+    pub fn parse(data: &[u8]) -> Result<Srgb> {
+        expect_length("sRGB", data, 1)?;
+        let intent = match data[0] {
+            0 => RenderingIntent::Perceptual,
+            1 => RenderingIntent::RelativeColorimetric,
+            2 => RenderingIntent::Saturation,
+            3 => RenderingIntent::AbsoluteColorimetric,
+            intent => return Err(format!("Invalid sRGB rendering intent {intent}").into())
+        };
+        Ok(Srgb { intent })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        vec![self.intent as u8]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iccp {
+    pub name: String,
+    pub compression_method: u8,
+    /// The zlib-compressed ICC profile, as stored in the chunk.
+    pub profile: Vec<u8>,
+}
+
+impl Iccp {
+    pub fn parse(data: &[u8]) -> Result<Iccp> {
+        let (name, rest) = split_keyword("iCCP", data)?;
+        if rest.is_empty() {
+            return Err("iCCP is missing its compression method".into());
+        }
+        if rest[0] != 0 {
+            return Err(format!("Unknown iCCP compression method {}", rest[0]).into());
+        }
+        Ok(Iccp {
+            name,
+            compression_method: rest[0],
+            profile: rest[1..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(keyword_bytes("iCCP", &self.name)?.into_iter()
+            .chain([0, self.compression_method])
+            .chain(self.profile.iter().copied())
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sbit {
+    /// Significant bits per channel, in the channel order of the image.
+    pub bits: Vec<u8>,
+}
+
+impl Sbit {
+    pub fn parse(data: &[u8], header: Option<&Ihdr>) -> Result<Sbit> {
+        if data.is_empty() || data.len() > 4 {
+            return Err(format!("Invalid sBIT length {}", data.len()).into());
+        }
+        let max_bits = match header {
+            Some(header) => {
+                let channels = match header.color_type {
+                    ColorType::Indexed => 3,
+                    color_type => color_type.channels(),
+                };
+                expect_length("sBIT", data, channels)?;
+                if header.color_type == ColorType::Indexed { 8 } else { header.bit_depth }
+            },
+            None => 16
+        };
+        if let Some(bits) = data.iter().find(|&&bits| bits == 0 || bits > max_bits) {
+            return Err(format!("Invalid sBIT significant bits {bits}").into());
+        }
+        Ok(Sbit { bits: data.to_vec() })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bits.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bkgd {
+    PaletteIndex(u8),
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+impl Bkgd {
+    pub fn parse(data: &[u8], header: Option<&Ihdr>) -> Result<Bkgd> {
+        let bkgd = match data.len() {
+            1 => Bkgd::PaletteIndex(data[0]),
+            2 => Bkgd::Gray(be_u16(data, 0)),
+            6 => Bkgd::Rgb(be_u16(data, 0), be_u16(data, 2), be_u16(data, 4)),
+            len => return Err(format!("Invalid bKGD length {len}").into())
+        };
+        if let Some(header) = header {
+            let matches_header = matches!((&bkgd, header.color_type),
+                (Bkgd::PaletteIndex(_), ColorType::Indexed)
+                | (Bkgd::Gray(_), ColorType::Grayscale | ColorType::GrayscaleAlpha)
+                | (Bkgd::Rgb(..), ColorType::Rgb | ColorType::Rgba));
+            if !matches_header {
+                return Err(format!("bKGD layout does not match {:?}", header.color_type).into());
+            }
+        }
+        Ok(bkgd)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Bkgd::PaletteIndex(index) => vec![*index],
+            Bkgd::Gray(gray) => gray.to_be_bytes().to_vec(),
+            Bkgd::Rgb(r, g, b) => [r, g, b].iter().flat_map(|c| c.to_be_bytes()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hist {
+    pub frequencies: Vec<u16>,
+}
+
+impl Hist {
+    pub fn parse(data: &[u8]) -> Result<Hist> {
+        if data.is_empty() || !data.len().is_multiple_of(2) || data.len() > 512 {
+            return Err(format!("Invalid hIST length {}", data.len()).into());
+        }
+        Ok(Hist {
+            frequencies: data.chunks_exact(2).map(|f| u16::from_be_bytes([f[0], f[1]])).collect()
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.frequencies.iter().flat_map(|f| f.to_be_bytes()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysUnit {
+    Unknown = 0,
+    Metre = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phys {
+    pub pixels_per_unit_x: u32,
+    pub pixels_per_unit_y: u32,
+    pub unit: PhysUnit,
+}
+
+impl Phys {
+    pub fn parse(data: &[u8]) -> Result<Phys> {
+        expect_length("pHYs", data, 9)?;
+        let unit = match data[8] {
+            0 => PhysUnit::Unknown,
+            1 => PhysUnit::Metre,
+            unit => return Err(format!("Invalid pHYs unit specifier {unit}").into())
+        };
+        Ok(Phys {
+            pixels_per_unit_x: be_u32(data, 0),
+            pixels_per_unit_y: be_u32(data, 4),
+            unit,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.pixels_per_unit_x.to_be_bytes().into_iter()
+            .chain(self.pixels_per_unit_y.to_be_bytes())
+            .chain([self.unit as u8])
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpltEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    pub frequency: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Splt {
+    pub name: String,
+    pub sample_depth: u8,
+    pub entries: Vec<SpltEntry>,
+}
+
+impl Splt {
+    pub fn parse(data: &[u8]) -> Result<Splt> {
+        let (name, rest) = split_keyword("sPLT", data)?;
+        if rest.is_empty() {
+            return Err("sPLT is missing its sample depth".into());
+        }
+        let sample_depth = rest[0];
+        let entry_size = match sample_depth {
+            8 => 6,
+            16 => 10,
+            depth => return Err(format!("Invalid sPLT sample depth {depth}").into())
+        };
+        let entries = &rest[1..];
+        if !entries.len().is_multiple_of(entry_size) {
+            return Err(format!("sPLT entries are not a multiple of {entry_size} bytes").into());
+        }
+        let entries = entries.chunks_exact(entry_size).map(|e| {
+            if sample_depth == 8 {
+                SpltEntry {
+                    red: e[0] as u16,
+                    green: e[1] as u16,
+                    blue: e[2] as u16,
+                    alpha: e[3] as u16,
+                    frequency: be_u16(e, 4),
+                }
+            } else {
+                SpltEntry {
+                    red: be_u16(e, 0),
+                    green: be_u16(e, 2),
+                    blue: be_u16(e, 4),
+                    alpha: be_u16(e, 6),
+                    frequency: be_u16(e, 8),
+                }
+            }
+        }).collect();
+        Ok(Splt { name, sample_depth, entries })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = keyword_bytes("sPLT", &self.name)?;
+        bytes.extend([0, self.sample_depth]);
+        for e in &self.entries {
+            if self.sample_depth == 8 {
+                bytes.extend([e.red as u8, e.green as u8, e.blue as u8, e.alpha as u8]);
+            } else {
+                bytes.extend([e.red, e.green, e.blue, e.alpha].iter().flat_map(|c| c.to_be_bytes()));
+            }
+            bytes.extend(e.frequency.to_be_bytes());
+        }
+        Ok(bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Time {
+    pub fn parse(data: &[u8]) -> Result<Time> {
+        expect_length("tIME", data, 7)?;
+        let time = Time {
+            year: be_u16(data, 0),
+            month: data[2],
+            day: data[3],
+            hour: data[4],
+            minute: data[5],
+            second: data[6],
+        };
+        let in_range = (1..=12).contains(&time.month)
+            && (1..=31).contains(&time.day)
+            && time.hour <= 23
+            && time.minute <= 59
+            && time.second <= 60; // 60 allows for leap seconds
+        if !in_range {
+            return Err(format!("tIME value out of range: {time}").into());
+        }
+        Ok(time)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.year.to_be_bytes().into_iter()
+            .chain([self.month, self.day, self.hour, self.minute, self.second])
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exif {
+    pub data: Vec<u8>,
+}
+
+impl Exif {
+    pub fn parse(data: &[u8]) -> Result<Exif> {
+        if !data.starts_with(b"II*\0") && !data.starts_with(b"MM\0*") {
+            return Err("eXIf does not start with a TIFF byte order mark".into());
+        }
+        Ok(Exif { data: data.to_vec() })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.data.starts_with(b"MM")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cicp {
+    pub colour_primaries: u8,
+    pub transfer_function: u8,
+    pub matrix_coefficients: u8,
+    pub video_full_range: bool,
+}
+
+impl Cicp {
+    pub fn parse(data: &[u8]) -> Result<Cicp> {
+        expect_length("cICP", data, 4)?;
+        // PNG only carries RGB data, so the matrix must be the identity
+        if data[2] != 0 {
+            return Err(format!("Invalid cICP matrix coefficients {}", data[2]).into());
+        }
+        let video_full_range = match data[3] {
+            0 => false,
+            1 => true,
+            flag => return Err(format!("Invalid cICP full range flag {flag}").into())
+        };
+        Ok(Cicp {
+            colour_primaries: data[0],
+            transfer_function: data[1],
+            matrix_coefficients: data[2],
+            video_full_range,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        vec![self.colour_primaries, self.transfer_function, self.matrix_coefficients, self.video_full_range as u8]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mdcv {
+    /// Red, green and blue display primaries in units of 0.00002.
+    pub primaries: [(u16, u16); 3],
+    pub white_point: (u16, u16),
+    /// Luminance values in units of 0.0001 cd/m².
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+impl Mdcv {
+    pub fn parse(data: &[u8]) -> Result<Mdcv> {
+        expect_length("mDCv", data, 24)?;
+        let pair = |at: usize| (be_u16(data, at), be_u16(data, at + 2));
+        let mdcv = Mdcv {
+            primaries: [pair(0), pair(4), pair(8)],
+            white_point: pair(12),
+            max_luminance: be_u32(data, 16),
+            min_luminance: be_u32(data, 20),
+        };
+        if mdcv.min_luminance >= mdcv.max_luminance {
+            return Err("mDCv minimum luminance must be below the maximum".into());
+        }
+        Ok(mdcv)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.primaries.iter().chain([&self.white_point])
+            .flat_map(|(x, y)| x.to_be_bytes().into_iter().chain(y.to_be_bytes()))
+            .chain(self.max_luminance.to_be_bytes())
+            .chain(self.min_luminance.to_be_bytes())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clli {
+    /// Light levels in units of 0.0001 cd/m².
+    pub max_content_light_level: u32,
+    pub max_frame_average_light_level: u32,
+}
+
+impl Clli {
+    pub fn parse(data: &[u8]) -> Result<Clli> {
+        expect_length("cLLi", data, 8)?;
+        Ok(Clli {
+            max_content_light_level: be_u32(data, 0),
+            max_frame_average_light_level: be_u32(data, 4),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.max_content_light_level.to_be_bytes().into_iter()
+            .chain(self.max_frame_average_light_level.to_be_bytes())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actl {
+    pub num_frames: u32,
+    /// Zero means the animation loops forever.
+    pub num_plays: u32,
+}
+
+impl Actl {
+    pub fn parse(data: &[u8]) -> Result<Actl> {
+        expect_length("acTL", data, 8)?;
+        let num_frames = be_u32(data, 0);
+        if num_frames == 0 {
+            return Err("acTL must declare at least one frame".into());
+        }
+        Ok(Actl { num_frames, num_plays: be_u32(data, 4) })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.num_frames.to_be_bytes().into_iter()
+            .chain(self.num_plays.to_be_bytes())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None = 0,
+    Background = 1,
+    Previous = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source = 0,
+    Over = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fctl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl Fctl {
+    pub fn parse(data: &[u8]) -> Result<Fctl> {
+        expect_length("fcTL", data, 26)?;
+        let dispose_op = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            op => return Err(format!("Invalid fcTL dispose op {op}").into())
+        };
+        let blend_op = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            op => return Err(format!("Invalid fcTL blend op {op}").into())
+        };
+        let fctl = Fctl {
+            sequence_number: be_u32(data, 0),
+            width: be_u32(data, 4),
+            height: be_u32(data, 8),
+            x_offset: be_u32(data, 12),
+            y_offset: be_u32(data, 16),
+            delay_num: be_u16(data, 20),
+            delay_den: be_u16(data, 22),
+            dispose_op,
+            blend_op,
+        };
+        if fctl.width == 0 || fctl.height == 0 {
+            return Err("fcTL frame must not be empty".into());
+        }
+        Ok(fctl)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.sequence_number, self.width, self.height, self.x_offset, self.y_offset].iter()
+            .flat_map(|v| v.to_be_bytes())
+            .chain(self.delay_num.to_be_bytes())
+            .chain(self.delay_den.to_be_bytes())
+            .chain([self.dispose_op as u8, self.blend_op as u8])
+            .collect()
+    }
+
+    /// The frame delay in seconds; a zero denominator means 1/100 s units.
+    pub fn delay_seconds(&self) -> f64 {
+        let den = if self.delay_den == 0 { 100 } else { self.delay_den };
+        self.delay_num as f64 / den as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fdat {
+    pub sequence_number: u32,
+    pub frame_data: Vec<u8>,
+}
+
+impl Fdat {
+    pub fn parse(data: &[u8]) -> Result<Fdat> {
+        if data.len() < 4 {
+            return Err("fdAT is missing its sequence number".into());
+        }
+        Ok(Fdat {
+            sequence_number: be_u32(data, 0),
+            frame_data: data[4..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.sequence_number.to_be_bytes().into_iter()
+            .chain(self.frame_data.iter().copied())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KnownChunk {
+    Ihdr(Ihdr),
+    Plte(Plte),
+    Trns(Trns),
+    Gama(Gama),
+    Chrm(Chrm),
+    Srgb(Srgb),
+    Iccp(Iccp),
+    Sbit(Sbit),
+    Bkgd(Bkgd),
+    Hist(Hist),
+    Phys(Phys),
+    Splt(Splt),
+    Time(Time),
+    Exif(Exif),
+    Cicp(Cicp),
+    Mdcv(Mdcv),
+    Clli(Clli),
+    Actl(Actl),
+    Fctl(Fctl),
+    Fdat(Fdat),
+}
+
+impl KnownChunk {
+    pub const TYPES: [&'static str; 20] = [
+        "IHDR", "PLTE", "tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "sBIT", "bKGD",
+        "hIST", "pHYs", "sPLT", "tIME", "eXIf", "cICP", "mDCv", "cLLi",
+        "acTL", "fcTL", "fdAT",
+    ];
+
+    pub fn is_known(chunk_type: &ChunkType) -> bool {
+        KnownChunk::TYPES.iter().any(|t| t.as_bytes() == chunk_type.bytes())
+    }
+
+    /// Parses a chunk, using the image header to resolve chunks whose layout
+    /// depends on the color type (tRNS, sBIT and bKGD).
+    pub fn from_chunk(chunk: &Chunk, header: Option<&Ihdr>) -> Result<KnownChunk> {
+        let data = chunk.data();
+        let chunk_type = chunk.chunk_type().to_string();
+        let known = match chunk_type.as_str() {
+            "IHDR" => KnownChunk::Ihdr(Ihdr::parse(data)?),
+            "PLTE" => KnownChunk::Plte(Plte::parse(data)?),
+            "tRNS" => KnownChunk::Trns(Trns::parse(data, header)?),
+            "gAMA" => KnownChunk::Gama(Gama::parse(data)?),
+            "cHRM" => KnownChunk::Chrm(Chrm::parse(data)?),
+            "sRGB" => KnownChunk::Srgb(Srgb::parse(data)?),
+            "iCCP" => KnownChunk::Iccp(Iccp::parse(data)?),
+            "sBIT" => KnownChunk::Sbit(Sbit::parse(data, header)?),
+            "bKGD" => KnownChunk::Bkgd(Bkgd::parse(data, header)?),
+            "hIST" => KnownChunk::Hist(Hist::parse(data)?),
+            "pHYs" => KnownChunk::Phys(Phys::parse(data)?),
+            "sPLT" => KnownChunk::Splt(Splt::parse(data)?),
+            "tIME" => KnownChunk::Time(Time::parse(data)?),
+            "eXIf" => KnownChunk::Exif(Exif::parse(data)?),
+            "cICP" => KnownChunk::Cicp(Cicp::parse(data)?),
+            "mDCv" => KnownChunk::Mdcv(Mdcv::parse(data)?),
+            "cLLi" => KnownChunk::Clli(Clli::parse(data)?),
+            "acTL" => KnownChunk::Actl(Actl::parse(data)?),
+            "fcTL" => KnownChunk::Fctl(Fctl::parse(data)?),
+            "fdAT" => KnownChunk::Fdat(Fdat::parse(data)?),
+            _ => return Err(format!("{} is not a known chunk type", chunk_type).into())
+        };
+        Ok(known)
+    }
+
+    pub fn chunk_type(&self) -> ChunkType {
+        let name = match self {
+            KnownChunk::Ihdr(_) => "IHDR",
+            KnownChunk::Plte(_) => "PLTE",
+            KnownChunk::Trns(_) => "tRNS",
+            KnownChunk::Gama(_) => "gAMA",
+            KnownChunk::Chrm(_) => "cHRM",
+            KnownChunk::Srgb(_) => "sRGB",
+            KnownChunk::Iccp(_) => "iCCP",
+            KnownChunk::Sbit(_) => "sBIT",
+            KnownChunk::Bkgd(_) => "bKGD",
+            KnownChunk::Hist(_) => "hIST",
+            KnownChunk::Phys(_) => "pHYs",
+            KnownChunk::Splt(_) => "sPLT",
+            KnownChunk::Time(_) => "tIME",
+            KnownChunk::Exif(_) => "eXIf",
+            KnownChunk::Cicp(_) => "cICP",
+            KnownChunk::Mdcv(_) => "mDCv",
+            KnownChunk::Clli(_) => "cLLi",
+            KnownChunk::Actl(_) => "acTL",
+            KnownChunk::Fctl(_) => "fcTL",
+            KnownChunk::Fdat(_) => "fdAT",
+        };
+        ChunkType::from_str(name).unwrap()
+    }
+
+    /// Fails only for iCCP and sPLT names that can't be written as a keyword.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            KnownChunk::Ihdr(c) => c.to_bytes(),
+            KnownChunk::Plte(c) => c.to_bytes(),
+            KnownChunk::Trns(c) => c.to_bytes(),
+            KnownChunk::Gama(c) => c.to_bytes(),
+            KnownChunk::Chrm(c) => c.to_bytes(),
+            KnownChunk::Srgb(c) => c.to_bytes(),
+            KnownChunk::Iccp(c) => c.to_bytes()?,
+            KnownChunk::Sbit(c) => c.to_bytes(),
+            KnownChunk::Bkgd(c) => c.to_bytes(),
+            KnownChunk::Hist(c) => c.to_bytes(),
+            KnownChunk::Phys(c) => c.to_bytes(),
+            KnownChunk::Splt(c) => c.to_bytes()?,
+            KnownChunk::Time(c) => c.to_bytes(),
+            KnownChunk::Exif(c) => c.to_bytes(),
+            KnownChunk::Cicp(c) => c.to_bytes(),
+            KnownChunk::Mdcv(c) => c.to_bytes(),
+            KnownChunk::Clli(c) => c.to_bytes(),
+            KnownChunk::Actl(c) => c.to_bytes(),
+            KnownChunk::Fctl(c) => c.to_bytes(),
+            KnownChunk::Fdat(c) => c.to_bytes(),
+        })
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        Ok(Chunk::new(self.chunk_type(), self.to_bytes()?))
+    }
+}
+
+impl TryFrom<&Chunk> for KnownChunk {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        KnownChunk::from_chunk(chunk, None)
+    }
+}
+
+impl Display for Time {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+impl Display for KnownChunk {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}: ", self.chunk_type())?;
+        match self {
+            KnownChunk::Ihdr(c) => write!(f, "{}x{}, bit depth {}, color type {:?}, compression {}, filter {}, interlace {}",
+                c.width, c.height, c.bit_depth, c.color_type, c.compression_method, c.filter_method, c.interlace_method),
+            KnownChunk::Plte(c) => write!(f, "{} palette entries", c.entries.len()),
+            KnownChunk::Trns(Trns::Gray(gray)) => write!(f, "transparent gray {gray}"),
+            KnownChunk::Trns(Trns::Rgb(r, g, b)) => write!(f, "transparent rgb({r}, {g}, {b})"),
+            KnownChunk::Trns(Trns::Palette(alphas)) => write!(f, "{} palette alpha values", alphas.len()),
+            KnownChunk::Gama(c) => write!(f, "gamma {:.5}", c.gamma as f64 / 100000.0),
+            KnownChunk::Chrm(c) => {
+                let xy = |(x, y): (u32, u32)| format!("({:.5}, {:.5})", x as f64 / 100000.0, y as f64 / 100000.0);
+                write!(f, "white {}, red {}, green {}, blue {}", xy(c.white_point), xy(c.red), xy(c.green), xy(c.blue))
+            },
+            KnownChunk::Srgb(c) => write!(f, "rendering intent {:?}", c.intent),
+            KnownChunk::Iccp(c) => write!(f, "profile {:?}, {} compressed bytes", c.name, c.profile.len()),
+            KnownChunk::Sbit(c) => write!(f, "significant bits {:?}", c.bits),
+            KnownChunk::Bkgd(Bkgd::PaletteIndex(index)) => write!(f, "background palette index {index}"),
+            KnownChunk::Bkgd(Bkgd::Gray(gray)) => write!(f, "background gray {gray}"),
+            KnownChunk::Bkgd(Bkgd::Rgb(r, g, b)) => write!(f, "background rgb({r}, {g}, {b})"),
+            KnownChunk::Hist(c) => write!(f, "{} frequencies", c.frequencies.len()),
+            KnownChunk::Phys(c) => match c.unit {
+                PhysUnit::Metre => write!(f, "{}x{} pixels per metre ({:.0}x{:.0} dpi)",
+                    c.pixels_per_unit_x, c.pixels_per_unit_y,
+                    c.pixels_per_unit_x as f64 * 0.0254, c.pixels_per_unit_y as f64 * 0.0254),
+                PhysUnit::Unknown => write!(f, "aspect ratio {}:{}", c.pixels_per_unit_x, c.pixels_per_unit_y),
+            },
+            KnownChunk::Splt(c) => write!(f, "palette {:?}, sample depth {}, {} entries", c.name, c.sample_depth, c.entries.len()),
+            KnownChunk::Time(c) => write!(f, "last modified {c} UTC"),
+            KnownChunk::Exif(c) => write!(f, "{} bytes, {} endian", c.data.len(), if c.is_big_endian() { "big" } else { "little" }),
+            KnownChunk::Cicp(c) => write!(f, "colour primaries {}, transfer function {}, matrix coefficients {}, full range {}",
+                c.colour_primaries, c.transfer_function, c.matrix_coefficients, c.video_full_range),
+            KnownChunk::Mdcv(c) => {
+                let xy = |(x, y): (u16, u16)| format!("({:.5}, {:.5})", x as f64 * 0.00002, y as f64 * 0.00002);
+                write!(f, "primaries {} {} {}, white {}, luminance {:.4}-{:.4} cd/m²",
+                    xy(c.primaries[0]), xy(c.primaries[1]), xy(c.primaries[2]), xy(c.white_point),
+                    c.min_luminance as f64 * 0.0001, c.max_luminance as f64 * 0.0001)
+            },
+            KnownChunk::Clli(c) => write!(f, "max content light level {:.4} cd/m², max frame average {:.4} cd/m²",
+                c.max_content_light_level as f64 * 0.0001, c.max_frame_average_light_level as f64 * 0.0001),
+            KnownChunk::Actl(c) => write!(f, "{} frames, {}", c.num_frames,
+                if c.num_plays == 0 { "loops forever".to_string() } else { format!("plays {} times", c.num_plays) }),
+            KnownChunk::Fctl(c) => write!(f, "sequence {}, {}x{} at ({}, {}), delay {}/{} s, dispose {:?}, blend {:?}",
+                c.sequence_number, c.width, c.height, c.x_offset, c.y_offset, c.delay_num, c.delay_den, c.dispose_op, c.blend_op),
+            KnownChunk::Fdat(c) => write!(f, "sequence {}, {} bytes of frame data", c.sequence_number, c.frame_data.len()),
+        }
+    }
+}
+
+fn be_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+fn be_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn expect_length(name: &str, data: &[u8], len: usize) -> Result<()> {
+    if data.len() != len {
+        return Err(format!("Invalid {} length {}, expected {}", name, data.len(), len).into());
+    }
+    Ok(())
+}
+
+/// Splits a null-terminated Latin-1 keyword off the front of `data`.
+fn split_keyword<'a>(name: &str, data: &'a [u8]) -> Result<(String, &'a [u8])> {
+    let end = match data.iter().position(|&b| b == 0) {
+        Some(end) => end,
+        None => return Err(format!("{name} keyword is not null-terminated").into())
+    };
+    let keyword = &data[..end];
+    check_keyword(name, keyword)?;
+    Ok((keyword.iter().map(|&b| b as char).collect(), &data[end + 1..]))
+}
+
+/// Encodes a keyword as Latin-1, holding it to the rules `split_keyword`
+/// checks on parse.
+fn keyword_bytes(name: &str, keyword: &str) -> Result<Vec<u8>> {
+    let bytes = keyword.chars()
+        .map(|c| u8::try_from(c).map_err(|_| format!("{name} keyword has {c:?}, which is not Latin-1")))
+        .collect::<std::result::Result<Vec<u8>, String>>()?;
+    check_keyword(name, &bytes)?;
+    Ok(bytes)
+}
+
+/// 1 to 79 printable Latin-1 bytes, without leading, trailing or repeated spaces.
+fn check_keyword(name: &str, keyword: &[u8]) -> Result<()> {
+    let printable = keyword.iter().all(|&b| (32..=126).contains(&b) || b >= 161);
+    if keyword.is_empty() || keyword.len() > 79 || !printable
+        || keyword.starts_with(b" ") || keyword.ends_with(b" ") || keyword.windows(2).any(|w| w == b"  ") {
+        return Err(format!("Invalid {name} keyword").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(chunk_type: &str, data: &[u8]) -> KnownChunk {
+        let known = KnownChunk::try_from(&Chunk::from_type_str(chunk_type, data.to_vec()).unwrap()).unwrap();
+        assert_eq!(known.to_bytes().unwrap(), data);
+        assert_eq!(known.chunk_type().to_string(), chunk_type);
+        known
+    }
+
+    fn rgba_header() -> Ihdr {
+        Ihdr::parse(&[0, 0, 0, 50, 0, 0, 0, 50, 8, 6, 0, 0, 0]).unwrap()
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let known = round_trip("IHDR", &[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0]);
+        match known {
+            KnownChunk::Ihdr(ihdr) => {
+                assert_eq!(ihdr.width, 50);
+                assert_eq!(ihdr.height, 40);
+                assert_eq!(ihdr.color_type, ColorType::Rgba);
+            },
+            _ => panic!("Expected IHDR")
+        }
+    }
+
+    #[test]
+    fn test_ihdr_invalid_bit_depth() {
+        assert!(Ihdr::parse(&[0, 0, 0, 50, 0, 0, 0, 40, 4, 2, 0, 0, 0]).is_err());
+        assert!(Ihdr::parse(&[0, 0, 0, 0, 0, 0, 0, 40, 8, 2, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_context_free_round_trips() {
+        round_trip("PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        round_trip("gAMA", &[0, 0, 177, 143]);
+        round_trip("cHRM", &(1..=32).collect::<Vec<u8>>());
+        round_trip("sRGB", &[0]);
+        round_trip("iCCP", b"ICC profile\0\0\x78\x9c\x03\x00");
+        round_trip("hIST", &[0, 1, 0, 2, 0, 3]);
+        round_trip("pHYs", &[0, 0, 14, 195, 0, 0, 14, 195, 1]);
+        round_trip("sPLT", b"six\0\x08\x01\x02\x03\x04\x00\x05");
+        round_trip("sPLT", b"sixteen\0\x10\x00\x01\x00\x02\x00\x03\x00\x04\x00\x05");
+        round_trip("tIME", &[7, 233, 10, 18, 23, 59, 60]);
+        round_trip("eXIf", b"MM\0*\0\0\0\x08");
+        round_trip("cICP", &[1, 13, 0, 1]);
+        round_trip("mDCv", &(1..=16).chain([0, 152, 150, 128, 0, 0, 0, 50]).collect::<Vec<u8>>());
+        round_trip("cLLi", &[0, 0, 3, 232, 0, 0, 1, 244]);
+    }
+
+    #[test]
+    fn test_header_dependent_round_trips() {
+        let header = rgba_header();
+        let bkgd = KnownChunk::from_chunk(&Chunk::from_type_str("bKGD", vec![0, 1, 0, 2, 0, 3]).unwrap(), Some(&header)).unwrap();
+        assert_eq!(bkgd, KnownChunk::Bkgd(Bkgd::Rgb(1, 2, 3)));
+        let sbit = KnownChunk::from_chunk(&Chunk::from_type_str("sBIT", vec![8, 8, 8, 8]).unwrap(), Some(&header)).unwrap();
+        assert_eq!(sbit.to_bytes().unwrap(), vec![8, 8, 8, 8]);
+        assert!(KnownChunk::from_chunk(&Chunk::from_type_str("sBIT", vec![8, 8, 8]).unwrap(), Some(&header)).is_err());
+        assert!(KnownChunk::from_chunk(&Chunk::from_type_str("bKGD", vec![1]).unwrap(), Some(&header)).is_err());
+        assert!(KnownChunk::from_chunk(&Chunk::from_type_str("tRNS", vec![0, 1]).unwrap(), Some(&header)).is_err());
+    }
+
+    #[test]
+    fn test_trns_depends_on_color_type() {
+        let mut header = rgba_header();
+        header.color_type = ColorType::Indexed;
+        let trns = Trns::parse(&[0, 255], Some(&header)).unwrap();
+        assert_eq!(trns, Trns::Palette(vec![0, 255]));
+        assert_eq!(Trns::parse(&[0, 255], None).unwrap(), Trns::Gray(255));
+    }
+
+    #[test]
+    fn test_field_validation() {
+        assert!(Srgb::parse(&[4]).is_err());
+        assert!(Phys::parse(&[0, 0, 0, 1, 0, 0, 0, 1, 2]).is_err());
+        assert!(Time::parse(&[7, 233, 13, 1, 0, 0, 0]).is_err());
+        assert!(Time::parse(&[7, 233, 1, 1, 24, 0, 0]).is_err());
+        assert!(Gama::parse(&[0, 0, 0, 0]).is_err());
+        assert!(Plte::parse(&[1, 2]).is_err());
+        assert!(Cicp::parse(&[1, 13, 1, 1]).is_err());
+        assert!(Exif::parse(b"not exif").is_err());
+        assert!(Iccp::parse(b" bad\0\0").is_err());
+    }
+
+    #[test]
+    fn test_animation_chunk_round_trips() {
+        round_trip("acTL", &[0, 0, 0, 3, 0, 0, 0, 0]);
+        let fctl = [0, 0, 0, 1, 0, 0, 0, 8, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0, 1, 0, 1, 0, 10, 2, 1];
+        match round_trip("fcTL", &fctl) {
+            KnownChunk::Fctl(fctl) => {
+                assert_eq!(fctl.dispose_op, DisposeOp::Previous);
+                assert_eq!(fctl.blend_op, BlendOp::Over);
+                assert_eq!(fctl.delay_seconds(), 0.1);
+            },
+            _ => panic!("Expected fcTL")
+        }
+        round_trip("fdAT", &[0, 0, 0, 2, 120, 156]);
+        assert!(Actl::parse(&[0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(Fctl::parse(&[0; 26]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_chunk_type() {
+        let unknown = Chunk::from_type_str("RuSt", b"data".to_vec()).unwrap();
+        assert!(!KnownChunk::is_known(&unknown.chunk_type()));
+        assert!(KnownChunk::try_from(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_to_chunk_has_valid_crc() {
+        let known = KnownChunk::Srgb(Srgb { intent: RenderingIntent::Perceptual });
+        let chunk = known.to_chunk().unwrap();
+        assert_eq!(chunk.crc(), 2932743401);
+    }
+
+    #[test]
+    fn test_keywords_are_checked_on_write() {
+        let iccp = |name: &str| Iccp { name: name.to_string(), compression_method: 0, profile: vec![] }.to_bytes();
+        assert_eq!(iccp("caf\u{e9}").unwrap()[..5], [b'c', b'a', b'f', 0xe9, 0]);
+        assert!(iccp("\u{2713} profile").is_err());
+        assert!(iccp(" padded").is_err());
+        assert!(iccp(&"x".repeat(80)).is_err());
+        let splt = Splt { name: String::new(), sample_depth: 8, entries: vec![] };
+        assert!(KnownChunk::Splt(splt).to_chunk().is_err());
+    }
+
+    #[test]
+    fn test_known_chunk_display() {
+        let known = round_trip("tIME", &[7, 233, 10, 18, 9, 5, 0]);
+        assert_eq!(known.to_string(), "tIME: last modified 2025-10-18 09:05:00 UTC");
+    }
+}
@@ -0,0 +1,29 @@
+pub mod apng;
+pub mod carve;
+pub mod chunk;
+pub mod chunk_reader;
+pub mod chunk_ref;
+pub mod chunk_writer;
+pub mod chunk_type;
+pub mod convert;
+pub mod decoder;
+pub mod diff;
+pub mod encoder;
+pub mod hexdump;
+pub mod ico;
+pub mod image;
+pub mod keys;
+pub mod known_chunk;
+pub mod optimize;
+pub mod payload;
+pub mod mapped;
+pub mod mng;
+pub mod png;
+pub mod reed_solomon;
+pub mod repair;
+pub mod seal;
+pub mod sign;
+pub mod strip;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
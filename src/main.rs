@@ -1,12 +1,17 @@
-use std::env::{self, args};
+use std::env::args;
+use std::fs;
+use std::io::{stdout, IsTerminal};
 
-mod args;
-mod chunk;
-mod chunk_type;
-mod png;
+use pngme::Result;
+use pngme::convert::Format;
+use pngme::hexdump::HexdumpOptions;
+use pngme::keys;
+use pngme::payload::{Compression, Decoded, PayloadOptions, DEFAULT_PARITY};
+use pngme::seal;
+use pngme::sign;
+use pngme::strip::StripProfile;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+mod args;
 
 fn main() -> Result<()> {
     let input: Vec<String> = args().collect();
@@ -16,28 +21,35 @@ fn main() -> Result<()> {
             if input.len() < 5 {
                 return Err("Not enough arguments".into());
             }
-            let mut args = args::Args::new(input[2].as_str(), args::PngArgs::Encode(input[3].clone(), input[4].clone()));
-            match args.encode() {
+            let options = payload_options(&input)?;
+            let positional = positional(&input);
+            if positional.len() < 5 {
+                return Err("Not enough arguments".into());
+            }
+            let args = args::MessageArgs::new(positional[2].as_str());
+            match args.encode(&positional[3], &positional[4], &options) {
                 Ok(_) => println!("Message encoded successfully"),
                 Err(e) => return Err(format!("Unable to encode message because of {}", e).into())
             }
         }, 
         "decode" => {
-            if input.len() < 4 {
+            let positional = positional(&input);
+            if positional.len() < 4 {
                 return Err("Not enough arguments".into());
             }
-            let args = args::Args::new(input[2].as_str(), args::PngArgs::Decode(input[3].clone()));
-            match args.decode() {
-                Some(message) => println!("Message decoded successfully: Message is \n {:?}", message),
-                None => return Err(format!("Message doesnt exist").into())
+            let args = args::MessageArgs::new(positional[2].as_str());
+            match args.decode(&positional[3], flag(&input, "key").as_deref()) {
+                Ok(Some(decoded)) => print_decoded(&decoded),
+                Ok(None) => return Err("Message doesnt exist".to_string().into()),
+                Err(e) => return Err(format!("Unable to decode message because of {}", e).into())
             }
         }, 
         "delete" => {
             if input.len() < 4 {
                 return Err("Not enough arguments".into());
             }
-            let mut args = args::Args::new(input[2].as_str(), args::PngArgs::Delete(input[3].clone()));
-            match args.delete() {
+            let args = args::MessageArgs::new(input[2].as_str());
+            match args.delete(&input[3], has_flag(&input, "pad")) {
                 Ok(_) => println!("Message deleted successfully"),
                 Err(e) => return Err(format!("Unable to delete message because of {}", e).into())
             }
@@ -46,10 +58,381 @@ fn main() -> Result<()> {
             if input.len() < 3 {
                 return Err("Not enough arguments".into());
             }
-            let args = args::Args::new(input[2].as_str(), args::PngArgs::Print());
-            args.print();
+            if has_flag(&input, "mmap") {
+                args::InspectArgs::new(input[2].as_str())?.print()?;
+            } else {
+                let args = args::Args::new(input[2].as_str(), args::PngArgs::Print());
+                args.print();
+            }
         }, 
+        "list" => {
+            if input.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            args::InspectArgs::new(input[2].as_str())?.list();
+        },
+        "animate" => {
+            let positional = positional(&input);
+            if positional.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let default_delay: u16 = flag(&input, "delay").unwrap_or("100".to_string()).parse()?;
+            let frames = positional[3..].iter().map(|frame| match frame.rsplit_once(':') {
+                Some((path, delay)) if delay.parse::<u16>().is_ok() => (path.to_string(), delay.parse().unwrap()),
+                _ => (frame.clone(), default_delay)
+            }).collect();
+            let raw_size = match flag(&input, "size") {
+                Some(size) => match size.split_once('x') {
+                    Some((w, h)) => Some((w.parse()?, h.parse()?)),
+                    None => return Err("Expected --size=WIDTHxHEIGHT".into())
+                },
+                None => None
+            };
+            let animate = args::AnimateArgs {
+                output: positional[2].clone(),
+                frames,
+                raw_size,
+                num_plays: flag(&input, "loops").unwrap_or("0".to_string()).parse()?,
+                crop_to_changes: has_flag(&input, "crop"),
+                hidden_default: flag(&input, "hidden"),
+            };
+            match animate.run() {
+                Ok(_) => println!("Animation written to {}", positional[2]),
+                Err(e) => return Err(format!("Unable to animate because of {}", e).into())
+            }
+        },
+        "explode" => {
+            if input.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let args = args::Args::new(input[2].as_str(), args::PngArgs::Explode(input[3].clone()));
+            match args.explode() {
+                Ok(count) => println!("Extracted {} frames", count),
+                Err(e) => return Err(format!("Unable to extract frames because of {}", e).into())
+            }
+        },
+        "drop-frames" | "retime" | "set-loops" | "reverse" => {
+            let positional = positional(&input);
+            let func = match (input[1].to_lowercase().as_str(), positional.len()) {
+                ("drop-frames", 4..) => args::PngArgs::DropFrames(frame_range(&positional[3])?),
+                ("retime", 4..) => args::PngArgs::Retime(positional[3].parse()?, flag(&input, "frames").map(|frames| frame_range(&frames)).transpose()?),
+                ("set-loops", 4..) => args::PngArgs::SetLoops(positional[3].parse()?),
+                ("reverse", 3..) => args::PngArgs::Reverse(),
+                _ => return Err("Not enough arguments".into())
+            };
+            let mut args = args::Args::new(input[2].as_str(), func);
+            match args.edit_frames() {
+                Ok(_) => println!("Animation updated successfully"),
+                Err(e) => return Err(format!("Unable to edit animation because of {}", e).into())
+            }
+        },
+        "repair" => {
+            let positional = positional(&input);
+            if positional.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let repair = args::RepairArgs {
+                input: positional[2].clone(),
+                output: positional[3].clone(),
+                report: flag(&input, "report"),
+            };
+            match repair.run() {
+                Ok(0) => println!("Nothing to repair, copied to {}", positional[3]),
+                Ok(count) => println!("Made {} repairs, written to {}", count, positional[3]),
+                Err(e) => return Err(format!("Unable to repair because of {}", e).into())
+            }
+        },
+        "fix-dimensions" => {
+            let positional = positional(&input);
+            if positional.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let fix = args::FixDimensionsArgs {
+                input: positional[2].clone(),
+                output: positional.get(3).cloned(),
+                limit: flag(&input, "limit").unwrap_or("4096".to_string()).parse()?,
+            };
+            if let Err(e) = fix.run() {
+                return Err(format!("Unable to recover dimensions because of {}", e).into());
+            }
+        },
+        "carve" => {
+            let positional = positional(&input);
+            if positional.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let carve = args::CarveArgs {
+                input: positional[2].clone(),
+                prefix: positional[3].clone(),
+                keep_partial: has_flag(&input, "partial"),
+            };
+            match carve.run() {
+                Ok(count) => println!("Extracted {} PNG files", count),
+                Err(e) => return Err(format!("Unable to carve because of {}", e).into())
+            }
+        },
+        "strip" => {
+            if input.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let list = |types: String| types.split(',').map(|t| t.to_string()).collect();
+            let profile = match (flag(&input, "profile").as_deref(), flag(&input, "keep"), flag(&input, "remove")) {
+                (None, Some(keep), None) => StripProfile::Allow(list(keep)),
+                (None, None, Some(remove)) => StripProfile::Deny(list(remove)),
+                (None | Some("default"), None, None) => StripProfile::Default,
+                (Some("all"), None, None) => StripProfile::AllAncillary,
+                (Some("color"), None, None) => StripProfile::KeepColorManagement,
+                (Some(profile), None, None) => return Err(format!("Unknown strip profile {}", profile).into()),
+                _ => return Err("Use only one of --profile, --keep and --remove".into())
+            };
+            let mut args = args::Args::new(input[2].as_str(), args::PngArgs::Strip(profile));
+            match args.strip() {
+                Ok((chunks, bytes)) => println!("Removed {} chunks, saving {} bytes", chunks, bytes),
+                Err(e) => return Err(format!("Unable to strip because of {}", e).into())
+            }
+        },
+        "optimize" => {
+            if input.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let mut args = args::Args::new(input[2].as_str(), args::PngArgs::Optimize(!has_flag(&input, "strip")));
+            match args.optimize() {
+                Ok((before, after)) => println!("Optimized from {} to {} bytes", before, after),
+                Err(e) => return Err(format!("Unable to optimize because of {}", e).into())
+            }
+        },
+        "diff" => {
+            let positional = positional(&input);
+            if positional.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let diff = args::DiffArgs {
+                old: positional[2].clone(),
+                new: positional[3].clone(),
+                compare_pixels: has_flag(&input, "pixels"),
+                diff_image: flag(&input, "diff-image"),
+            };
+            if let Err(e) = diff.run() {
+                return Err(format!("Unable to diff because of {}", e).into());
+            }
+        },
+        "convert" => {
+            let positional = positional(&input);
+            if positional.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let format = match flag(&input, "format") {
+                Some(name) => Format::from_name(&name.to_lowercase())?,
+                None => Format::from_path(&positional[3])?
+            };
+            let convert = args::ConvertArgs { input: positional[2].clone(), output: positional[3].clone(), format };
+            match convert.run() {
+                Ok(_) => println!("Wrote {} to {}", format, positional[3]),
+                Err(e) => return Err(format!("Unable to convert because of {}", e).into())
+            }
+        },
+        "import" => {
+            let positional = positional(&input);
+            if positional.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let message = match (positional.get(4), positional.get(5)) {
+                (Some(chunk_type), Some(message)) => Some((chunk_type.clone(), message.clone())),
+                (Some(_), None) => return Err("A chunk type needs a message".into()),
+                _ => None
+            };
+            let options = payload_options(&input)?;
+            let import = args::ImportArgs { input: positional[2].clone(), output: positional[3].clone(), message, options };
+            match import.run() {
+                Ok(_) => println!("Wrote {}", positional[3]),
+                Err(e) => return Err(format!("Unable to import because of {}", e).into())
+            }
+        },
+        "ico" => {
+            let positional = positional(&input);
+            if positional.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let mut ico = args::IcoArgs::new(&positional[3]).map_err(|e| format!("Unable to read icon because of {}", e))?;
+            let entry = |indx: usize| -> Result<usize> {
+                match positional.get(indx) {
+                    Some(entry) => Ok(entry.parse()?),
+                    None => Err("Not enough arguments".into())
+                }
+            };
+            match (positional[2].as_str(), positional.get(5), positional.get(6)) {
+                ("list", _, _) => ico.list(),
+                ("extract", _, _) => {
+                    let prefix = positional.get(4).ok_or("Not enough arguments")?;
+                    let count = ico.extract(prefix).map_err(|e| format!("Unable to extract because of {}", e))?;
+                    println!("Extracted {} PNG entries", count);
+                },
+                ("encode", Some(chunk_type), Some(message)) => {
+                    match ico.encode(entry(4)?, chunk_type, message, &payload_options(&input)?) {
+                        Ok(_) => println!("Message encoded successfully"),
+                        Err(e) => return Err(format!("Unable to encode message because of {}", e).into())
+                    }
+                },
+                ("decode", Some(chunk_type), _) => {
+                    match ico.decode(entry(4)?, chunk_type, flag(&input, "key").as_deref()) {
+                        Ok(Some(decoded)) => print_decoded(&decoded),
+                        Ok(None) => return Err("Message doesnt exist".into()),
+                        Err(e) => return Err(format!("Unable to decode message because of {}", e).into())
+                    }
+                },
+                ("delete", Some(chunk_type), _) => {
+                    match ico.delete(entry(4)?, chunk_type) {
+                        Ok(_) => println!("Message deleted successfully"),
+                        Err(e) => return Err(format!("Unable to delete message because of {}", e).into())
+                    }
+                },
+                _ => return Err("Usage: ico list|extract|encode|decode|delete <file> [...]".into())
+            }
+        },
+        "mng" => {
+            if input.len() < 4 {
+                return Err("Not enough arguments".into());
+            }
+            let mng = args::MngArgs::new(&input[3]).map_err(|e| format!("Unable to read MNG because of {}", e))?;
+            match (input[2].as_str(), input.get(4)) {
+                ("list", _) => mng.list(),
+                ("extract", Some(prefix)) => {
+                    let count = mng.extract(prefix).map_err(|e| format!("Unable to extract because of {}", e))?;
+                    println!("Extracted {} subimages", count);
+                },
+                _ => return Err("Usage: mng list|extract <file> [prefix]".into())
+            }
+        },
+        "sign" => {
+            let positional = positional(&input);
+            let key = flag(&input, "key").ok_or("A secret key is needed, pass --key=<name>.key")?;
+            if positional.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let types = match flag(&input, "include") {
+                Some(types) => types.split(',').filter(|t| !t.is_empty()).map(|t| t.to_string()).collect(),
+                None => sign::default_signed_types()
+            };
+            let mut args = args::Args::new(positional[2].as_str(), args::PngArgs::Sign(key, types));
+            match args.sign() {
+                Ok(count) => println!("Signed {} chunks", count),
+                Err(e) => return Err(format!("Unable to sign because of {}", e).into())
+            }
+        },
+        "verify-signature" => {
+            let positional = positional(&input);
+            let key = flag(&input, "key").ok_or("A public key is needed, pass --key=<name>.pub")?;
+            if positional.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let args = args::Args::new(positional[2].as_str(), args::PngArgs::VerifySignature(key));
+            let verification = args.verify_signature().map_err(|e| format!("Signature is not valid: {}", e))?;
+            println!("Valid signature by key {}", keys::hex(&verification.key_id));
+            for (indx, chunk_type) in &verification.covered {
+                println!("  signed    #{} {}", indx, chunk_type);
+            }
+            for (indx, chunk_type) in &verification.uncovered {
+                println!("  unsigned  #{} {}", indx, chunk_type);
+            }
+        },
+        "hash" => {
+            let positional = positional(&input);
+            if positional.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let hash = args::HashArgs { files: positional[2..].to_vec() };
+            if let Err(e) = hash.run() {
+                return Err(format!("Unable to hash because of {}", e).into());
+            }
+        },
+        "keygen" => {
+            if input.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let keygen = args::KeygenArgs { name: input[2].clone() };
+            if let Err(e) = keygen.run() {
+                return Err(format!("Unable to generate keys because of {}", e).into());
+            }
+        },
+        "hexdump" => {
+            let positional = positional(&input);
+            if positional.len() < 3 {
+                return Err("Not enough arguments".into());
+            }
+            let window = match (flag(&input, "offset"), flag(&input, "length")) {
+                (None, None) => None,
+                (offset, length) => {
+                    let offset: usize = offset.unwrap_or("0".to_string()).parse()?;
+                    let length: usize = length.map_or(Ok(usize::MAX), |length| length.parse())?;
+                    Some(offset..offset.saturating_add(length))
+                }
+            };
+            let hexdump = args::HexdumpArgs {
+                input: positional[2].clone(),
+                options: HexdumpOptions {
+                    window,
+                    chunk: flag(&input, "chunk"),
+                    color: stdout().is_terminal() && !has_flag(&input, "no-color"),
+                },
+            };
+            if let Err(e) = hexdump.run() {
+                return Err(format!("Unable to dump because of {}", e).into());
+            }
+        },
         _ => return Err("Invalid command".into())
     }
-    Ok({})
+    Ok(())
+}
+
+/// Returns the value of a `--name=value` flag.
+fn flag(input: &[String], name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    input.iter().find_map(|arg| arg.strip_prefix(&prefix).map(|value| value.to_string()))
+}
+
+fn has_flag(input: &[String], name: &str) -> bool {
+    input.iter().any(|arg| *arg == format!("--{}", name))
+}
+
+/// The `--fec`, `--compress` and `--to` options of commands that hide a
+/// message.
+fn payload_options(input: &[String]) -> Result<PayloadOptions> {
+    Ok(PayloadOptions {
+        fec: match flag(input, "fec") {
+            Some(parity) => Some(parity.parse()?),
+            None => has_flag(input, "fec").then_some(DEFAULT_PARITY)
+        },
+        compression: match flag(input, "compress") {
+            Some(name) => Some(Compression::from_name(&name)?),
+            None => has_flag(input, "compress").then_some(Compression::Zlib)
+        },
+        recipients: match flag(input, "to") {
+            Some(paths) => paths.split(',')
+                .map(|path| seal::public_key_from_file(&fs::read_to_string(path)?))
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new()
+        },
+    })
+}
+
+fn print_decoded(decoded: &Decoded) {
+    println!("Message decoded successfully: Message is \n {:?}", String::from_utf8_lossy(&decoded.message));
+    if decoded.corrected > 0 {
+        println!("Corrected {} damaged bytes", decoded.corrected);
+    }
+}
+
+/// Every argument that is not a `--flag`.
+fn positional(input: &[String]) -> Vec<String> {
+    input.iter().filter(|arg| !arg.starts_with("--")).cloned().collect()
+}
+
+/// Parses a zero-based inclusive frame range such as `3` or `2-5`.
+fn frame_range(range: &str) -> Result<std::ops::Range<usize>> {
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    let (first, last): (usize, usize) = (first.parse()?, last.parse()?);
+    if last < first {
+        return Err(format!("Invalid frame range {}", range).into());
+    }
+    Ok(first..last + 1)
 }
\ No newline at end of file
@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use memmap2::Mmap;
+use crate::chunk_reader::{ChunkHeader, MAX_CHUNK_LENGTH};
+use crate::chunk_ref::ChunkRef;
+use crate::chunk_type::ChunkType;
+use crate::decoder;
+use crate::image::Image;
+use crate::known_chunk::{Ihdr, Plte, Trns};
+use crate::png::{self, Png};
+use crate::Result;
+
+/// A PNG opened through a memory map. Opening only walks the chunk headers
+/// to build an index; chunk data is paged in (and its CRC checked) when a
+/// chunk is actually asked for.
+pub struct MappedPng {
+    map: Mmap,
+    index: Vec<ChunkHeader>,
+}
+
+impl MappedPng {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedPng> {
+        let map = map_file(path)?;
+        let index = build_index(&map)?;
+        Ok(MappedPng { map, index })
+    }
+
+    /// Offsets, lengths and types of every chunk, in file order.
+    pub fn index(&self) -> &[ChunkHeader] {
+        &self.index
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The size of the mapped file in bytes.
+    pub fn file_size(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    /// Reads and CRC-checks the chunk at position `indx` in the index.
+    pub fn chunk(&self, indx: usize) -> Result<ChunkRef<'_>> {
+        match self.index.get(indx) {
+            Some(header) => ChunkRef::try_from(&self.map[header.offset as usize..]),
+            None => Err(format!("No chunk at index {}", indx).into())
+        }
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Result<Option<ChunkRef<'_>>> {
+        match self.index.iter().position(|header| header.chunk_type.bytes() == chunk_type.as_bytes()) {
+            Some(indx) => Ok(Some(self.chunk(indx)?)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        match self.chunk_by_type("IHDR")? {
+            Some(chunk) => Ihdr::parse(chunk.data()),
+            None => Err("Missing IHDR chunk".into())
+        }
+    }
+
+    /// The concatenated contents of every IDAT chunk.
+    pub fn image_data(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for (indx, header) in self.index.iter().enumerate() {
+            if header.chunk_type.bytes() == *b"IDAT" {
+                data.extend_from_slice(self.chunk(indx)?.data());
+            }
+        }
+        Ok(data)
+    }
+
+    /// Decodes the default image, touching only the chunks it needs.
+    pub fn decode(&self) -> Result<Image> {
+        let header = self.ihdr()?;
+        let palette = match self.chunk_by_type("PLTE")? {
+            Some(chunk) => Some(Plte::parse(chunk.data())?),
+            None => None
+        };
+        let transparency = match self.chunk_by_type("tRNS")? {
+            Some(chunk) => Some(Trns::parse(chunk.data(), Some(&header))?),
+            None => None
+        };
+        decoder::decode_image(&header, palette.as_ref(), transparency.as_ref(), &self.image_data()?)
+    }
+
+    /// Like `Png::pad_chunk_in_place`, for chunk `indx` of the index, with
+    /// `file` the mapped file opened for writing.
+    pub fn pad_chunk_in_place<F: Read + Write + Seek>(&mut self, file: &mut F, indx: usize) -> Result<()> {
+        let header = self.index.get_mut(indx).ok_or_else(|| format!("No chunk at index {}", indx))?;
+        let padding = png::padding_for(&header.chunk_type, header.length)?;
+        png::write_chunk_at(file, header.offset, &header.chunk_type, header.length, &padding)?;
+        header.chunk_type = padding.chunk_type();
+        Ok(())
+    }
+
+    /// Copies every chunk into an owned `Png`.
+    pub fn to_png(&self) -> Result<Png> {
+        let mut chunks = Vec::with_capacity(self.index.len());
+        for indx in 0..self.index.len() {
+            chunks.push(self.chunk(indx)?.to_chunk());
+        }
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+/// Maps a whole file read only.
+pub fn map_file<P: AsRef<Path>>(path: P) -> Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: the map is read only. Another process truncating the file
+    // while it is mapped is outside what this tool guards against.
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+/// Walks the chunk headers of a whole PNG file without reading any chunk
+/// data or checking CRCs.
+pub fn build_index(bytes: &[u8]) -> Result<Vec<ChunkHeader>> {
+    if bytes.len() < 8 || bytes[0..8] != Png::STANDARD_HEADER {
+        return Err("Incorrect initial bytes".into());
+    }
+    let mut index = Vec::new();
+    let mut offset = 8;
+    while offset < bytes.len() {
+        if bytes.len() - offset < 12 {
+            return Err(format!("Truncated chunk header at offset {}", offset).into());
+        }
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        if length > MAX_CHUNK_LENGTH {
+            return Err(format!("Chunk length {} at offset {} is too large", length, offset).into());
+        }
+        let chunk_type = match ChunkType::try_from(<[u8; 4]>::try_from(&bytes[offset + 4..offset + 8]).unwrap()) {
+            Ok(chunk_type) => chunk_type,
+            Err(e) => return Err(format!("Invalid chunk type at offset {} because of {}", offset, e).into())
+        };
+        let end = offset + 12 + length as usize;
+        if end > bytes.len() {
+            return Err(format!("Truncated {} chunk at offset {}", chunk_type, offset).into());
+        }
+        index.push(ChunkHeader { length, chunk_type, offset: offset as u64 });
+        offset = end;
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    #[test]
+    fn test_build_index() {
+        let png = Png::try_from(IMAGE).unwrap();
+        let index = build_index(IMAGE).unwrap();
+        assert_eq!(index.len(), png.chunks().len());
+        assert_eq!(index[0].offset, 8);
+        for (header, chunk) in index.iter().zip(png.chunks()) {
+            assert_eq!(header.chunk_type, chunk.chunk_type());
+            assert_eq!(header.length, chunk.length());
+        }
+        assert!(build_index(&IMAGE[..IMAGE.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_mapped_png() {
+        let path = std::env::temp_dir().join(format!("pngme_mapped_{}.png", std::process::id()));
+        fs::write(&path, IMAGE).unwrap();
+        let mapped = MappedPng::open(&path).unwrap();
+        let png = Png::try_from(IMAGE).unwrap();
+        assert_eq!(mapped.file_size(), IMAGE.len() as u64);
+        assert_eq!(mapped.ihdr().unwrap(), png.ihdr().unwrap());
+        assert!(mapped.decode().unwrap() == png.decode().unwrap());
+        assert_eq!(mapped.to_png().unwrap().as_bytes(), IMAGE);
+        assert!(mapped.chunk(mapped.len()).is_err());
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pad_chunk_in_place() {
+        let path = std::env::temp_dir().join(format!("pngme_mapped_pad_{}.png", std::process::id()));
+        fs::write(&path, IMAGE).unwrap();
+        let mut mapped = MappedPng::open(&path).unwrap();
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        assert!(mapped.pad_chunk_in_place(&mut file, 0).is_err());
+        assert!(mapped.pad_chunk_in_place(&mut file, mapped.len()).is_err());
+        let last = mapped.len() - 1;
+        mapped.pad_chunk_in_place(&mut file, last).unwrap();
+        drop(mapped);
+
+        let padded = Png::try_from(fs::read(&path).unwrap().as_slice()).unwrap();
+        assert_eq!(padded.chunks()[last].chunk_type().to_string(), png::PADDING_CHUNK_TYPE);
+        assert_eq!(padded.as_bytes().len(), IMAGE.len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_crc_checked_lazily() {
+        let mut bytes = IMAGE.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let index = build_index(&bytes).unwrap();
+        let last = index.last().unwrap();
+        assert!(ChunkRef::try_from(&bytes[last.offset as usize..]).is_err());
+    }
+}
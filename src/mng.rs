@@ -0,0 +1,371 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use crate::chunk::Chunk;
+use crate::chunk_ref::PngRef;
+use crate::chunk_writer::ChunkWriter;
+use crate::png::Png;
+use crate::Error;
+use crate::Result;
+
+pub const MNG_SIGNATURE: [u8; 8] = [138, 77, 78, 71, 13, 10, 26, 10];
+pub const JNG_SIGNATURE: [u8; 8] = [139, 74, 78, 71, 13, 10, 26, 10];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Png,
+    Mng,
+    Jng,
+}
+
+impl Kind {
+    pub fn signature(&self) -> &'static [u8; 8] {
+        match self {
+            Kind::Png => &Png::STANDARD_HEADER,
+            Kind::Mng => &MNG_SIGNATURE,
+            Kind::Jng => &JNG_SIGNATURE,
+        }
+    }
+
+    /// File extension for a standalone file of this kind.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Kind::Png => "png",
+            Kind::Mng => "mng",
+            Kind::Jng => "jng",
+        }
+    }
+}
+
+/// The MNG header: frame size, timing and the nominal counts a decoder
+/// may use to plan ahead (0 means unspecified).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mhdr {
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub ticks_per_second: u32,
+    pub nominal_layer_count: u32,
+    pub nominal_frame_count: u32,
+    pub nominal_play_time: u32,
+    pub simplicity_profile: u32,
+}
+
+impl Mhdr {
+    pub fn parse(data: &[u8]) -> Result<Mhdr> {
+        expect_length("MHDR", data, 28)?;
+        let field = |indx: usize| be_u32(data, indx * 4);
+        Ok(Mhdr {
+            frame_width: field(0),
+            frame_height: field(1),
+            ticks_per_second: field(2),
+            nominal_layer_count: field(3),
+            nominal_frame_count: field(4),
+            nominal_play_time: field(5),
+            simplicity_profile: field(6),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.frame_width, self.frame_height, self.ticks_per_second, self.nominal_layer_count,
+            self.nominal_frame_count, self.nominal_play_time, self.simplicity_profile]
+            .iter().flat_map(|field| field.to_be_bytes()).collect()
+    }
+}
+
+impl Display for Mhdr {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "MHDR: {}x{}, {} ticks/s, {} layers, {} frames, play time {}, profile {:#x}",
+            self.frame_width, self.frame_height, self.ticks_per_second, self.nominal_layer_count,
+            self.nominal_frame_count, self.nominal_play_time, self.simplicity_profile)
+    }
+}
+
+/// The JNG header: a JPEG color image with an optional alpha channel that
+/// is stored either as PNG-style IDAT data or as a second JPEG.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jhdr {
+    pub width: u32,
+    pub height: u32,
+    /// 8 gray, 10 color, 12 gray with alpha, 14 color with alpha.
+    pub color_type: u8,
+    /// 8, 12, or 20 for 8- and 12-bit images in one file.
+    pub image_sample_depth: u8,
+    pub image_compression_method: u8,
+    pub image_interlace_method: u8,
+    pub alpha_sample_depth: u8,
+    /// 0 for IDAT alpha, 8 for JPEG alpha in JdAA chunks.
+    pub alpha_compression_method: u8,
+    pub alpha_filter_method: u8,
+    pub alpha_interlace_method: u8,
+}
+
+impl Jhdr {
+    pub fn parse(data: &[u8]) -> Result<Jhdr> {
+        expect_length("JHDR", data, 16)?;
+        let jhdr = Jhdr {
+            width: be_u32(data, 0),
+            height: be_u32(data, 4),
+            color_type: data[8],
+            image_sample_depth: data[9],
+            image_compression_method: data[10],
+            image_interlace_method: data[11],
+            alpha_sample_depth: data[12],
+            alpha_compression_method: data[13],
+            alpha_filter_method: data[14],
+            alpha_interlace_method: data[15],
+        };
+        if !matches!(jhdr.color_type, 8 | 10 | 12 | 14) {
+            return Err(format!("Invalid JNG color type {}", jhdr.color_type).into());
+        }
+        Ok(jhdr)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.extend_from_slice(&[self.color_type, self.image_sample_depth, self.image_compression_method,
+            self.image_interlace_method, self.alpha_sample_depth, self.alpha_compression_method,
+            self.alpha_filter_method, self.alpha_interlace_method]);
+        bytes
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.color_type >= 12
+    }
+}
+
+impl Display for Jhdr {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        let color = match self.color_type {
+            8 => "gray",
+            10 => "color",
+            12 => "gray with alpha",
+            _ => "color with alpha",
+        };
+        write!(f, "JHDR: {}x{}, {}, {}-bit JPEG", self.width, self.height, color, self.image_sample_depth)?;
+        if self.has_alpha() {
+            let storage = if self.alpha_compression_method == 8 { "JPEG" } else { "PNG" };
+            write!(f, ", {}-bit {} alpha", self.alpha_sample_depth, storage)?;
+        }
+        Ok(())
+    }
+}
+
+/// A PNG or JNG datastream embedded in an MNG, from its IHDR or JHDR
+/// through its IEND.
+pub struct Subimage {
+    kind: Kind,
+    /// Index of the header chunk in the containing file.
+    first_chunk: usize,
+    chunks: Vec<Chunk>,
+}
+
+impl Subimage {
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn first_chunk(&self) -> usize {
+        self.first_chunk
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// The subimage as a standalone PNG or JNG file.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut writer = ChunkWriter::with_signature(Vec::new(), self.kind.signature()).unwrap();
+        for chunk in &self.chunks {
+            writer.write_chunk(chunk).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+}
+
+/// An MNG or JNG file. Both use the PNG chunk layout behind their own
+/// signature.
+pub struct Mng {
+    kind: Kind,
+    chunks: Vec<Chunk>,
+}
+
+impl Mng {
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks.iter().find(|chunk| chunk.chunk_type().bytes() == chunk_type.as_bytes())
+    }
+
+    pub fn mhdr(&self) -> Result<Mhdr> {
+        match self.chunk_by_type("MHDR") {
+            Some(chunk) => Mhdr::parse(chunk.data()),
+            None => Err("Missing MHDR chunk".into())
+        }
+    }
+
+    pub fn jhdr(&self) -> Result<Jhdr> {
+        match self.chunk_by_type("JHDR") {
+            Some(chunk) => Jhdr::parse(chunk.data()),
+            None => Err("Missing JHDR chunk".into())
+        }
+    }
+
+    /// Every embedded PNG and JNG, or the whole image for a JNG file. An
+    /// empty PLTE in an embedded PNG refers to the MNG's global palette,
+    /// which is copied in so the extracted file stands alone.
+    pub fn subimages(&self) -> Vec<Subimage> {
+        if self.kind == Kind::Jng {
+            return vec![Subimage { kind: Kind::Jng, first_chunk: 0, chunks: self.chunks.clone() }];
+        }
+        let mut subimages = Vec::new();
+        let mut current: Option<Subimage> = None;
+        let mut global_palette: Option<&Chunk> = None;
+        for (indx, chunk) in self.chunks.iter().enumerate() {
+            let chunk_type = chunk.chunk_type().bytes();
+            match (&mut current, &chunk_type) {
+                (None, b"IHDR") => current = Some(Subimage { kind: Kind::Png, first_chunk: indx, chunks: vec![chunk.clone()] }),
+                (None, b"JHDR") => current = Some(Subimage { kind: Kind::Jng, first_chunk: indx, chunks: vec![chunk.clone()] }),
+                (None, b"PLTE") => global_palette = Some(chunk),
+                (None, _) => {},
+                (Some(subimage), b"PLTE") if chunk.length() == 0 && global_palette.is_some() => {
+                    subimage.chunks.push(global_palette.unwrap().clone());
+                },
+                (Some(subimage), _) => {
+                    subimage.chunks.push(chunk.clone());
+                    if &chunk_type == b"IEND" {
+                        subimages.extend(current.take());
+                    }
+                },
+            }
+        }
+        subimages
+    }
+}
+
+impl TryFrom<&[u8]> for Mng {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let kind = match bytes.get(..8) {
+            Some(signature) if signature == MNG_SIGNATURE => Kind::Mng,
+            Some(signature) if signature == JNG_SIGNATURE => Kind::Jng,
+            _ => return Err("Not an MNG or JNG file".into())
+        };
+        let file = PngRef::with_signature(bytes, kind.signature())?;
+        Ok(Mng { kind, chunks: file.chunks().map(|chunk| chunk.to_chunk()).collect() })
+    }
+}
+
+fn be_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn expect_length(name: &str, data: &[u8], len: usize) -> Result<()> {
+    if data.len() != len {
+        return Err(format!("Invalid {} length {}, expected {}", name, data.len(), len).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_reader::ChunkReader;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    fn mhdr() -> Mhdr {
+        Mhdr { frame_width: 386, frame_height: 395, ticks_per_second: 100, nominal_layer_count: 0,
+            nominal_frame_count: 1, nominal_play_time: 0, simplicity_profile: 1 }
+    }
+
+    fn jhdr() -> Jhdr {
+        Jhdr { width: 2, height: 2, color_type: 14, image_sample_depth: 8, image_compression_method: 8,
+            image_interlace_method: 0, alpha_sample_depth: 8, alpha_compression_method: 0,
+            alpha_filter_method: 0, alpha_interlace_method: 0 }
+    }
+
+    fn write(signature: &[u8; 8], chunks: &[Chunk]) -> Vec<u8> {
+        let mut writer = ChunkWriter::with_signature(Vec::new(), signature).unwrap();
+        for chunk in chunks {
+            writer.write_chunk(chunk).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn testing_mng() -> Vec<u8> {
+        let png = Png::try_from(IMAGE).unwrap();
+        let embedded: Vec<Chunk> = png.chunks().iter().take_while(|chunk| chunk.chunk_type().to_string() != "ruSt").cloned().collect();
+        let mut chunks = vec![
+            Chunk::from_type_str("MHDR", mhdr().to_bytes()).unwrap(),
+            Chunk::from_type_str("PLTE", vec![1, 2, 3]).unwrap(),
+        ];
+        chunks.extend(embedded);
+        chunks.extend([
+            Chunk::from_type_str("JHDR", jhdr().to_bytes()).unwrap(),
+            Chunk::from_type_str("JDAT", vec![0xff, 0xd8]).unwrap(),
+            Chunk::from_type_str("IEND", vec![]).unwrap(),
+        ]);
+        chunks.push(Chunk::from_type_str("MEND", vec![]).unwrap());
+        write(&MNG_SIGNATURE, &chunks)
+    }
+
+    #[test]
+    fn test_headers_round_trip() {
+        assert_eq!(Mhdr::parse(&mhdr().to_bytes()).unwrap(), mhdr());
+        assert_eq!(Jhdr::parse(&jhdr().to_bytes()).unwrap(), jhdr());
+        assert!(Jhdr::parse(&[0; 16]).is_err());
+        assert_eq!(jhdr().to_string(), "JHDR: 2x2, color with alpha, 8-bit JPEG, 8-bit PNG alpha");
+    }
+
+    #[test]
+    fn test_mng_subimages() {
+        let mng = Mng::try_from(testing_mng().as_slice()).unwrap();
+        assert_eq!(mng.kind(), Kind::Mng);
+        assert_eq!(mng.mhdr().unwrap(), mhdr());
+        let subimages = mng.subimages();
+        assert_eq!(subimages.len(), 2);
+        assert_eq!((subimages[0].kind(), subimages[0].first_chunk()), (Kind::Png, 2));
+        let png = Png::try_from(subimages[0].as_bytes().as_slice()).unwrap();
+        assert_eq!(png.decode().unwrap(), Png::try_from(IMAGE).unwrap().decode().unwrap());
+
+        let jng = Mng::try_from(subimages[1].as_bytes().as_slice()).unwrap();
+        assert_eq!(jng.kind(), Kind::Jng);
+        assert_eq!(jng.jhdr().unwrap(), jhdr());
+        assert_eq!(jng.subimages()[0].chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_global_palette_is_copied() {
+        let ihdr = Png::try_from(IMAGE).unwrap().chunks()[0].clone();
+        let chunks = vec![
+            Chunk::from_type_str("MHDR", mhdr().to_bytes()).unwrap(),
+            Chunk::from_type_str("PLTE", vec![1, 2, 3]).unwrap(),
+            ihdr,
+            Chunk::from_type_str("PLTE", vec![]).unwrap(),
+            Chunk::from_type_str("IDAT", vec![]).unwrap(),
+            Chunk::from_type_str("IEND", vec![]).unwrap(),
+            Chunk::from_type_str("MEND", vec![]).unwrap(),
+        ];
+        let mng = Mng::try_from(write(&MNG_SIGNATURE, &chunks).as_slice()).unwrap();
+        assert_eq!(mng.subimages()[0].chunks()[1].data(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_signatures() {
+        let bytes = testing_mng();
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+        assert!(ChunkReader::new(bytes.as_slice()).is_err());
+        let chunks: Vec<Chunk> = ChunkReader::with_signature(bytes.as_slice(), &MNG_SIGNATURE).unwrap()
+            .map(|chunk| chunk.unwrap()).collect();
+        assert_eq!(chunks.len(), Mng::try_from(bytes.as_slice()).unwrap().chunks().len());
+        assert!(Mng::try_from(IMAGE).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use crate::chunk::Chunk;
+use crate::decoder::scale_to_16;
+use crate::encoder::{self, EncodeOptions, FilterStrategy};
+use crate::image::Image;
+use crate::known_chunk::ColorType;
+use crate::png::Png;
+use crate::Result;
+
+/// zlib levels tried for every candidate encoding.
+pub const COMPRESSION_LEVELS: [u8; 2] = [6, 9];
+
+/// Ancillary chunks whose layout depends on the color type, bit depth or
+/// palette. They are dropped when the optimizer changes any of those.
+const LAYOUT_DEPENDENT_TYPES: [&str; 3] = ["sBIT", "bKGD", "hIST"];
+
+/// Ancillary chunks that describe color primaries. A profile for RGB can't
+/// describe gray samples or the other way around, so they are dropped when
+/// the optimizer moves between gray and color.
+const COLOR_SPACE_TYPES: [&str; 3] = ["iCCP", "cHRM", "cICP"];
+
+pub struct OptimizeReport {
+    /// The encoding that was kept, or `None` when the original image data
+    /// was already the smallest.
+    pub options: Option<EncodeOptions>,
+    /// Types of ancillary chunks that were removed.
+    pub dropped: Vec<String>,
+    pub original_size: usize,
+    pub optimized_size: usize,
+}
+
+/// Re-encodes the image in every lossless color type and bit depth it fits,
+/// with every filter strategy and `COMPRESSION_LEVELS`, and keeps the
+/// smallest result. Ancillary chunks (hidden messages included) are kept in
+/// place unless `keep_ancillary` is false.
+pub fn optimize(png: &Png, keep_ancillary: bool) -> Result<(Png, OptimizeReport)> {
+    if png.chunk_by_type("acTL").is_some() {
+        return Err("Animated PNGs can't be optimized".into());
+    }
+    let header = png.ihdr()?;
+    let image = png.decode()?;
+
+    let original_size = image_chunks_size(png.chunks().iter()
+        .filter(|chunk| matches!(&chunk.chunk_type().bytes(), b"PLTE" | b"tRNS" | b"IDAT")));
+    let mut best: Option<(EncodeOptions, Vec<u8>, usize)> = None;
+    for target in lossless_targets(&image) {
+        let palette_size = target.palette_chunks().map_or(0, |(plte, trns)|
+            plte.to_bytes().len() + 12 + trns.map_or(0, |trns| trns.to_bytes().len() + 12));
+        for filter in (0..=4).map(FilterStrategy::Fixed).chain([FilterStrategy::Adaptive]) {
+            let filtered = encoder::filter_image(&image, &EncodeOptions { filter, ..target.clone() })?;
+            for level in COMPRESSION_LEVELS {
+                let data = compress_to_vec_zlib(&filtered, level);
+                let size = palette_size + data.len() + data.len().div_ceil(encoder::MAX_DATA_CHUNK).max(1) * 12;
+                if best.as_ref().is_none_or(|(_, _, best_size)| size < *best_size) {
+                    best = Some((EncodeOptions { filter, compression_level: level, ..target.clone() }, data, size));
+                }
+            }
+        }
+    }
+    let replacement = best.filter(|(_, _, size)| *size < original_size);
+
+    let layout_changed = replacement.as_ref().is_some_and(|(options, _, _)|
+        options.color_type != header.color_type || options.bit_depth != header.bit_depth || options.color_type == ColorType::Indexed);
+    let is_gray = |color_type| matches!(color_type, ColorType::Grayscale | ColorType::GrayscaleAlpha);
+    let gray_changed = replacement.as_ref().is_some_and(|(options, _, _)| is_gray(options.color_type) != is_gray(header.color_type));
+    let mut dropped = Vec::new();
+    let mut chunks = Vec::new();
+    let mut image_written = false;
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        let name = chunk_type.to_string();
+        match (&replacement, name.as_str()) {
+            (Some((options, _, _)), "IHDR") => {
+                chunks.push(Chunk::from_type_str("IHDR", options.header(image.width(), image.height()).to_bytes())?);
+            },
+            (Some(_), "PLTE" | "tRNS") => {},
+            (Some((options, data, _)), "IDAT") => {
+                if !image_written {
+                    if let Some((plte, trns)) = options.palette_chunks() {
+                        chunks.push(Chunk::from_type_str("PLTE", plte.to_bytes())?);
+                        if let Some(trns) = trns {
+                            chunks.push(Chunk::from_type_str("tRNS", trns.to_bytes())?);
+                        }
+                    }
+                    for data in encoder::split_data(data) {
+                        chunks.push(Chunk::from_type_str("IDAT", data)?);
+                    }
+                    image_written = true;
+                }
+            },
+            _ if chunk_type.is_critical() || name == "tRNS" => chunks.push(chunk.clone()),
+            _ if layout_changed && LAYOUT_DEPENDENT_TYPES.contains(&name.as_str()) => dropped.push(name),
+            _ if gray_changed && COLOR_SPACE_TYPES.contains(&name.as_str()) => dropped.push(name),
+            _ if keep_ancillary => chunks.push(chunk.clone()),
+            _ => dropped.push(name)
+        }
+    }
+
+    let optimized = Png::from_chunks(chunks);
+    let report = OptimizeReport {
+        options: replacement.map(|(options, _, _)| options),
+        dropped,
+        original_size: png.as_bytes().len(),
+        optimized_size: optimized.as_bytes().len(),
+    };
+    Ok((optimized, report))
+}
+
+/// Every color type and bit depth that stores `image` exactly, each with
+/// its palette when indexed.
+pub fn lossless_targets(image: &Image) -> Vec<EncodeOptions> {
+    let base = EncodeOptions::for_image(image);
+    let gray = image.is_grayscale();
+    let opaque = image.is_opaque();
+    let color_type = match (gray, opaque) {
+        (true, true) => ColorType::Grayscale,
+        (true, false) => ColorType::GrayscaleAlpha,
+        (false, true) => ColorType::Rgb,
+        (false, false) => ColorType::Rgba,
+    };
+    let mut targets = vec![EncodeOptions { color_type, ..base.clone() }];
+
+    if gray && opaque
+        && let Some(bit_depth) = [1, 2, 4].into_iter().find(|&depth| fits_in_depth(image, depth)) {
+        targets.push(EncodeOptions { color_type, bit_depth, ..base.clone() });
+    }
+    if base.bit_depth == 8
+        && let Some(palette) = palette(image) {
+        let bit_depth = [1, 2, 4, 8].into_iter().find(|&depth| palette.len() <= 1 << depth).unwrap();
+        targets.push(EncodeOptions { color_type: ColorType::Indexed, bit_depth, palette: Some(palette), ..base });
+    }
+    targets
+}
+
+/// The distinct colors of an 8-bit image, if there are at most 256. Entries
+/// with transparency come first so tRNS can stop at the last of them.
+fn palette(image: &Image) -> Option<Vec<[u8; 4]>> {
+    let mut colors: Vec<[u8; 4]> = Vec::new();
+    for pixel in image.pixels() {
+        let rgba = pixel.map(|c| (c >> 8) as u8);
+        if !colors.contains(&rgba) {
+            if colors.len() == 256 {
+                return None;
+            }
+            colors.push(rgba);
+        }
+    }
+    colors.sort_by_key(|rgba| rgba[3] == 255);
+    Some(colors)
+}
+
+fn fits_in_depth(image: &Image, bit_depth: u8) -> bool {
+    image.pixels().iter().all(|p| scale_to_16(p[0] >> (16 - bit_depth), bit_depth) == p[0])
+}
+
+fn image_chunks_size<'a>(chunks: impl Iterator<Item = &'a Chunk>) -> usize {
+    chunks.map(|chunk| chunk.length() as usize + 12).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    fn two_color_png() -> Png {
+        let rgba: Vec<u8> = (0..64 * 64u32)
+            .flat_map(|i| if (i * i / 7) % 3 == 0 { [255, 0, 0, 255] } else { [0, 0, 0, 255] })
+            .collect();
+        let image = Image::from_rgba8(64, 64, &rgba).unwrap();
+        let mut png = encoder::encode(&image, &EncodeOptions { compression_level: 0, ..EncodeOptions::for_image(&image) }).unwrap();
+        png.append_chunk(Chunk::from_type_str("ruSt", b"hidden".to_vec()).unwrap());
+        png
+    }
+
+    #[test]
+    fn test_targets() {
+        let png = two_color_png();
+        let targets: Vec<(ColorType, u8)> = lossless_targets(&png.decode().unwrap()).iter()
+            .map(|target| (target.color_type, target.bit_depth))
+            .collect();
+        assert_eq!(targets, vec![(ColorType::Rgb, 8), (ColorType::Indexed, 1)]);
+
+        let gray = Image::from_rgba8(2, 1, &[0, 0, 0, 255, 255, 255, 255, 255]).unwrap();
+        assert_eq!(lossless_targets(&gray)[1].bit_depth, 1);
+    }
+
+    #[test]
+    fn test_optimize_is_lossless_and_keeps_messages() {
+        let png = two_color_png();
+        let (optimized, report) = optimize(&png, true).unwrap();
+        assert!(report.optimized_size < report.original_size);
+        assert_eq!(report.options.unwrap().color_type, ColorType::Indexed);
+        assert_eq!(optimized.decode().unwrap(), png.decode().unwrap());
+        assert_eq!(optimized.chunk_by_type("ruSt").unwrap().data(), b"hidden");
+
+        let (stripped, report) = optimize(&png, false).unwrap();
+        assert!(stripped.chunk_by_type("ruSt").is_none());
+        assert_eq!(report.dropped, vec!["ruSt"]);
+    }
+
+    #[test]
+    fn test_gray_drops_color_profiles() {
+        let rgba: Vec<u8> = (0..64 * 64u32).flat_map(|i| { let v = (i % 64) as u8 * 4; [v, v, v, 255] }).collect();
+        let image = Image::from_rgba8(64, 64, &rgba).unwrap();
+        let options = EncodeOptions { color_type: ColorType::Rgb, compression_level: 0, ..EncodeOptions::for_image(&image) };
+        let mut chunks = encoder::encode(&image, &options).unwrap().chunks().to_vec();
+        for chunk_type in ["iCCP", "cHRM", "gAMA"] {
+            chunks.insert(1, Chunk::from_type_str(chunk_type, vec![0; 4]).unwrap());
+        }
+        let png = Png::from_chunks(chunks);
+
+        let (optimized, report) = optimize(&png, true).unwrap();
+        assert_eq!(report.options.unwrap().color_type, ColorType::Grayscale);
+        assert_eq!(report.dropped, vec!["cHRM", "iCCP"]);
+        assert!(optimized.chunk_by_type("gAMA").is_some());
+        assert_eq!(optimized.decode().unwrap(), png.decode().unwrap());
+    }
+
+    #[test]
+    fn test_optimize_file() {
+        let png = Png::try_from(IMAGE).unwrap();
+        let (optimized, report) = optimize(&png, true).unwrap();
+        assert!(report.optimized_size <= report.original_size);
+        assert_eq!(optimized.decode().unwrap(), png.decode().unwrap());
+        assert_eq!(optimized.chunks().len(), png.chunks().len());
+    }
+}
@@ -0,0 +1,288 @@
+use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
+use miniz_oxide::inflate::{decompress_to_vec_with_limit, decompress_to_vec_zlib_with_limit};
+use x25519_dalek::PublicKey;
+use crate::reed_solomon::{self, MAX_BLOCK};
+use crate::seal::{self, Identity};
+use crate::Result;
+
+/// Marks a framed payload. 0xf0 can't start a UTF-8 sequence followed by
+/// ASCII, so no plain text message begins with these bytes.
+pub const MAGIC: [u8; 4] = [0xf0, b'p', b'm', b'e'];
+
+/// The body is split into Reed-Solomon blocks.
+pub const FLAG_FEC: u8 = 1;
+/// The message was compressed with zlib.
+pub const FLAG_ZLIB: u8 = 2;
+/// The message was compressed with raw deflate, without zlib's header and
+/// checksum.
+pub const FLAG_DEFLATE: u8 = 4;
+/// The message was sealed for one or more recipients' public keys.
+pub const FLAG_SEALED: u8 = 8;
+const KNOWN_FLAGS: u8 = FLAG_FEC | FLAG_ZLIB | FLAG_DEFLATE | FLAG_SEALED;
+
+/// Compression level used for messages.
+const COMPRESSION_LEVEL: u8 = 9;
+/// Largest message a compressed payload may expand to.
+pub const MAX_MESSAGE: usize = 1 << 26;
+
+/// Magic, flags and the parity bytes per block.
+const HEADER_LEN: usize = 6;
+/// The header is stored this many times and read back by majority vote,
+/// since the error correction can't protect its own parameters.
+const HEADER_COPIES: usize = 3;
+
+/// Parity bytes per block when error correction is asked for without a
+/// count: up to 16 corrupted bytes per 255 can be corrected.
+pub const DEFAULT_PARITY: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zlib,
+    /// Like zlib without the 6 bytes of header and checksum.
+    Deflate,
+}
+
+impl Compression {
+    pub fn from_name(name: &str) -> Result<Compression> {
+        match name {
+            "zlib" => Ok(Compression::Zlib),
+            "deflate" => Ok(Compression::Deflate),
+            _ => Err(format!("Unknown compression {:?}", name).into())
+        }
+    }
+
+    fn flag(&self) -> u8 {
+        match self {
+            Compression::Zlib => FLAG_ZLIB,
+            Compression::Deflate => FLAG_DEFLATE,
+        }
+    }
+
+    fn compress(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Zlib => compress_to_vec_zlib(message, COMPRESSION_LEVEL),
+            Compression::Deflate => compress_to_vec(message, COMPRESSION_LEVEL),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PayloadOptions {
+    /// Reed-Solomon parity bytes per block of up to 255 bytes. Each block
+    /// can correct half as many corrupted bytes.
+    pub fec: Option<u8>,
+    /// Compress the message first. Skipped when it wouldn't make the
+    /// message smaller.
+    pub compression: Option<Compression>,
+    /// Seal the message so only these keys can read it.
+    pub recipients: Vec<PublicKey>,
+}
+
+impl PayloadOptions {
+    /// Whether the payload needs a header at all. Without one the message
+    /// is stored as is, exactly like before payloads had options.
+    pub fn is_plain(&self) -> bool {
+        self.fec.is_none() && self.compression.is_none() && self.recipients.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    pub message: Vec<u8>,
+    /// Bytes repaired by error correction.
+    pub corrected: usize,
+}
+
+/// Wraps `message` as `options` asks: compression first, since encrypted
+/// data doesn't compress, then sealing, and error correction last so it
+/// covers every byte stored. Plain options return the message unchanged.
+///
+/// Compressing before sealing makes the sealed size depend on the contents
+/// and not just the length, so an attacker who can get their own text into
+/// a sealed message can learn about the rest from its size (as in CRIME).
+pub fn encode(message: &[u8], options: &PayloadOptions) -> Result<Vec<u8>> {
+    let mut flags = 0;
+    let mut body = message.to_vec();
+    if let Some(compression) = options.compression {
+        let compressed = compression.compress(message);
+        // Compression alone is what adds the header, so it has to pay for it.
+        let header_len = if options.fec.is_none() && options.recipients.is_empty() { HEADER_LEN * HEADER_COPIES } else { 0 };
+        if compressed.len() + header_len < body.len() {
+            flags |= compression.flag();
+            body = compressed;
+        }
+    }
+    if !options.recipients.is_empty() {
+        flags |= FLAG_SEALED;
+        body = seal::seal(&body, &options.recipients)?;
+    }
+    if flags == 0 && options.fec.is_none() {
+        return Ok(body);
+    }
+    let parity = options.fec.unwrap_or(0);
+    if options.fec.is_some() {
+        if parity == 0 || parity as usize >= MAX_BLOCK {
+            return Err(format!("Parity must be between 1 and {} bytes", MAX_BLOCK - 1).into());
+        }
+        flags |= FLAG_FEC;
+        body = fec_encode(&body, parity as usize)?;
+    }
+
+    let header = [MAGIC[0], MAGIC[1], MAGIC[2], MAGIC[3], flags, parity];
+    let mut payload = header.repeat(HEADER_COPIES);
+    payload.extend(body);
+    Ok(payload)
+}
+
+/// Unwraps a payload made by `encode`, opening sealed messages with
+/// `identity`. Anything without the header is a plain message and comes
+/// back unchanged.
+pub fn decode(payload: &[u8], identity: Option<&Identity>) -> Result<Decoded> {
+    let header = match read_header(payload) {
+        Some(header) => header,
+        None => return Ok(Decoded { message: payload.to_vec(), corrected: 0 })
+    };
+    let (flags, parity) = (header[4], header[5] as usize);
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(format!("Unknown payload flags {:#04x}", flags).into());
+    }
+    let mut body = payload[HEADER_LEN * HEADER_COPIES..].to_vec();
+    let mut corrected = 0;
+    if flags & FLAG_FEC != 0 {
+        (body, corrected) = fec_decode(&body, parity)?;
+    }
+    if flags & FLAG_SEALED != 0 {
+        let identity = identity.ok_or("Message is sealed, a key is needed to read it")?;
+        body = seal::open(&body, identity)?;
+    }
+    if flags & FLAG_ZLIB != 0 {
+        body = decompress_to_vec_zlib_with_limit(&body, MAX_MESSAGE)
+            .map_err(|e| format!("Unable to decompress message because of {:?}", e.status))?;
+    } else if flags & FLAG_DEFLATE != 0 {
+        body = decompress_to_vec_with_limit(&body, MAX_MESSAGE)
+            .map_err(|e| format!("Unable to decompress message because of {:?}", e.status))?;
+    }
+    Ok(Decoded { message: body, corrected })
+}
+
+/// The header by majority vote over its copies, if it carries `MAGIC`.
+fn read_header(payload: &[u8]) -> Option<[u8; HEADER_LEN]> {
+    let copies: Vec<&[u8]> = payload.get(..HEADER_LEN * HEADER_COPIES)?.chunks(HEADER_LEN).collect();
+    let header: [u8; HEADER_LEN] = std::array::from_fn(|i| {
+        let votes = |value: u8| copies.iter().filter(|copy| copy[i] == value).count();
+        copies.iter().map(|copy| copy[i]).max_by_key(|&value| votes(value)).unwrap()
+    });
+    (header[..4] == MAGIC).then_some(header)
+}
+
+fn fec_encode(body: &[u8], parity: usize) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    for block in body.chunks(MAX_BLOCK - parity) {
+        encoded.extend(reed_solomon::encode(block, parity)?);
+    }
+    Ok(encoded)
+}
+
+fn fec_decode(body: &[u8], parity: usize) -> Result<(Vec<u8>, usize)> {
+    let mut decoded = Vec::new();
+    let mut corrected = 0;
+    for (indx, block) in body.chunks(MAX_BLOCK).enumerate() {
+        let (data, errors) = reed_solomon::decode(block, parity)
+            .map_err(|e| format!("Unable to correct block {} because of {}", indx, e))?;
+        decoded.extend(data);
+        corrected += errors;
+    }
+    Ok((decoded, corrected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fec(parity: u8) -> PayloadOptions {
+        PayloadOptions { fec: Some(parity), ..PayloadOptions::default() }
+    }
+
+    fn compressed(compression: Compression) -> PayloadOptions {
+        PayloadOptions { compression: Some(compression), ..PayloadOptions::default() }
+    }
+
+    #[test]
+    fn test_plain_messages() {
+        assert_eq!(encode(b"hello", &PayloadOptions::default()).unwrap(), b"hello");
+        assert_eq!(decode(b"hello", None).unwrap(), Decoded { message: b"hello".to_vec(), corrected: 0 });
+        assert_eq!(decode(b"", None).unwrap().message, b"");
+    }
+
+    #[test]
+    fn test_corrects_corrupted_bytes() {
+        let message: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let payload = encode(&message, &fec(16)).unwrap();
+        // Four blocks of 239 data and 16 parity bytes, and a last partial one.
+        assert_eq!(payload.len(), 18 + 1000 + 5 * 16);
+
+        let mut corrupted = payload.clone();
+        corrupted[1] = 0;
+        for pos in (20..payload.len()).step_by(40) {
+            corrupted[pos] ^= 0xff;
+        }
+        let decoded = decode(&corrupted, None).unwrap();
+        assert_eq!(decoded.message, message);
+        assert_eq!(decoded.corrected, (20..payload.len()).step_by(40).count());
+    }
+
+    #[test]
+    fn test_too_much_damage() {
+        let mut payload = encode(b"hidden message", &fec(4)).unwrap();
+        payload[18..24].iter_mut().for_each(|b| *b ^= 0x55);
+        assert!(decode(&payload, None).is_err());
+        assert!(encode(b"x", &fec(0)).is_err());
+        assert!(encode(b"x", &fec(255)).is_err());
+    }
+
+    #[test]
+    fn test_compression() {
+        let message = "all work and no play makes jack a dull boy. ".repeat(50);
+        for compression in [Compression::Zlib, Compression::Deflate] {
+            let payload = encode(message.as_bytes(), &compressed(compression)).unwrap();
+            assert!(payload.len() < message.len() / 5);
+            assert_eq!(payload[4], compression.flag());
+            assert_eq!(decode(&payload, None).unwrap().message, message.as_bytes());
+        }
+
+        let options = PayloadOptions { fec: Some(8), compression: Some(Compression::Zlib), ..PayloadOptions::default() };
+        let mut payload = encode(message.as_bytes(), &options).unwrap();
+        payload[30] ^= 0xff;
+        assert_eq!(decode(&payload, None).unwrap(), Decoded { message: message.into_bytes(), corrected: 1 });
+    }
+
+    #[test]
+    fn test_compression_skipped_when_it_does_not_help() {
+        assert_eq!(encode(b"short", &compressed(Compression::Zlib)).unwrap(), b"short");
+        let payload = encode(b"short", &PayloadOptions { fec: Some(4), compression: Some(Compression::Deflate), ..PayloadOptions::default() }).unwrap();
+        assert_eq!(payload[4], FLAG_FEC);
+
+        // Saves a few bytes, but fewer than the header costs.
+        let message = b"pngme hides a short message inside a chunk of a png file".to_vec();
+        let saved = message.len() - Compression::Deflate.compress(&message).len();
+        assert!(saved > 0 && saved <= HEADER_LEN * HEADER_COPIES, "saved {}", saved);
+        assert_eq!(encode(&message, &compressed(Compression::Deflate)).unwrap(), message);
+    }
+
+    #[test]
+    fn test_sealed() {
+        let (alice, eve) = (Identity::generate(), Identity::generate());
+        let message = "for alice only, ".repeat(20);
+        let options = PayloadOptions {
+            fec: Some(16),
+            compression: Some(Compression::Zlib),
+            recipients: vec![alice.public_key()],
+        };
+        let payload = encode(message.as_bytes(), &options).unwrap();
+        assert_eq!(payload[4], FLAG_FEC | FLAG_ZLIB | FLAG_SEALED);
+        assert!(payload.len() < message.len());
+        assert_eq!(decode(&payload, Some(&alice)).unwrap().message, message.as_bytes());
+        assert!(decode(&payload, Some(&eve)).is_err());
+        assert!(decode(&payload, None).is_err());
+    }
+}
@@ -1,59 +1,90 @@
 use crate::chunk::Chunk;
+use crate::chunk_reader::ChunkReader;
+use crate::chunk_ref::PngRef;
+use crate::chunk_writer::ChunkWriter;
 use crate::chunk_type::ChunkType;
-use std::{convert::TryFrom, fmt::{Display, Formatter}, str::FromStr};
+use crate::decoder;
+use crate::image::Image;
+use crate::known_chunk::{Ihdr, KnownChunk, Plte, Trns};
+use sha2::{Digest, Sha256};
+use std::{convert::TryFrom, fmt::{Display, Formatter}, io::{Read, Seek, SeekFrom, Write}};
 use crate::Error;
 use crate::Result;
 
 pub struct Png {
     header: [u8; 8],
-    chunks: Vec<Chunk>
+    chunks: Vec<Chunk>,
+    /// Where each chunk started in the file it was read from, or `None` for
+    /// chunks added since.
+    offsets: Vec<Option<u64>>
 }
 
+/// Type of the chunk `Png::pad_chunk_in_place` leaves behind: ancillary,
+/// private and safe to copy, so decoders skip it.
+pub const PADDING_CHUNK_TYPE: &str = "paDd";
+
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
     
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        let send: Vec<u8> = Png::STANDARD_HEADER.iter()
-            .map(|&x| x)
-            .chain(chunks.iter().flat_map(|chunk| {
-                let bytes = u32::to_be_bytes(chunk.length()).iter().map(|&x| x)
-                    .chain(chunk.chunk_type().bytes())
-                    .chain(chunk.as_bytes().iter().map(|&x| x))
-                    .chain(u32::to_be_bytes(chunk.crc()))
-                    .collect::<Vec<u8>>();
-                bytes
-            }))
-            .collect();
-        let send: &[u8] = &send;
-        Png::try_from(send).unwrap()
+        Png {
+            header: Png::STANDARD_HEADER,
+            offsets: vec![None; chunks.len()],
+            chunks
+        }
+    }
+    
+    /// Reads a PNG chunk by chunk, without first buffering the whole stream.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Png> {
+        let mut reader = ChunkReader::new(reader)?;
+        let mut chunks = Vec::new();
+        let mut offsets = Vec::new();
+        while let Some(header) = reader.next_header()? {
+            let mut data = Vec::new();
+            reader.data().read_to_end(&mut data)?;
+            chunks.push(Chunk::new(header.chunk_type, data));
+            offsets.push(Some(header.offset));
+        }
+        Ok(Png {
+            header: Png::STANDARD_HEADER,
+            chunks,
+            offsets
+        })
     }
     
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
+        self.offsets.push(None);
+    }
+
+    /// Inserts `chunk` just before the last IEND, or at the end if there is
+    /// no IEND.
+    pub fn insert_before_iend(&mut self, chunk: Chunk) {
+        match self.chunks.iter().rposition(|chunk| chunk.chunk_type().bytes() == *b"IEND") {
+            Some(indx) => {
+                self.chunks.insert(indx, chunk);
+                self.offsets.insert(indx, None);
+            },
+            None => self.append_chunk(chunk)
+        }
     }
     
     pub fn remove_first_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
-        let bytes:[u8; 4] = chunk_type.as_bytes().try_into().unwrap();
+        let bytes:[u8; 4] = match chunk_type.as_bytes().try_into() {
+            Ok(bytes) => bytes,
+            Err(_) => return Err("Incorrect chunktype".into())
+        };
         match ChunkType::try_from(bytes) {
             Ok(chunk_type) => {
-                let mut target_chunk: Option<Chunk> = None;
-                for chunk in self.chunks.clone() {
-                    if chunk.chunk_type() == chunk_type {
-                        target_chunk = Some(chunk);
-                        break;
-                    }
-                }
-                
-                let target_chunk = target_chunk.unwrap();
-                match self.chunks().iter().position(|ch| *ch == target_chunk) {
+                match self.chunks.iter().position(|chunk| chunk.chunk_type() == chunk_type) {
                     Some(indx) => {
-                        let res = self.chunks.remove(indx);
-                        return Ok(res);
-                    }, 
-                    None => return Err("Chunktype not found".into())
+                        self.offsets.remove(indx);
+                        Ok(self.chunks.remove(indx))
+                    },
+                    None => Err("Chunktype not found".into())
                 }
             }, 
-            Err(_) => return Err("Incorrect chunktype".into())
+            Err(_) => Err("Incorrect chunktype".into())
         }
     }
     
@@ -61,6 +92,42 @@ impl Png {
         &self.chunks
     }
     
+    /// The byte offset of chunk `indx` in the file it was read from.
+    pub fn chunk_offset(&self, indx: usize) -> Option<u64> {
+        self.offsets.get(indx).copied().flatten()
+    }
+    
+    /// Overwrites the data of chunk `indx` in `file`, the file this `Png`
+    /// was read from, and updates its CRC there. Only the chunk's own bytes
+    /// are written, so `data` must be exactly as long as the current data.
+    pub fn patch_chunk_in_place<F: Read + Write + Seek>(&mut self, file: &mut F, indx: usize, data: Vec<u8>) -> Result<()> {
+        let chunk_type = self.chunk(indx)?.chunk_type();
+        self.write_in_place(file, indx, Chunk::new(chunk_type, data))
+    }
+    
+    /// Turns chunk `indx` into a zero-filled `PADDING_CHUNK_TYPE` chunk of
+    /// the same size, in `file` and in memory. Critical chunks can't be padded.
+    pub fn pad_chunk_in_place<F: Read + Write + Seek>(&mut self, file: &mut F, indx: usize) -> Result<()> {
+        let chunk = self.chunk(indx)?;
+        let padding = padding_for(&chunk.chunk_type(), chunk.length())?;
+        self.write_in_place(file, indx, padding)
+    }
+    
+    fn chunk(&self, indx: usize) -> Result<&Chunk> {
+        self.chunks.get(indx).ok_or_else(|| format!("No chunk at index {}", indx).into())
+    }
+    
+    fn write_in_place<F: Read + Write + Seek>(&mut self, file: &mut F, indx: usize, chunk: Chunk) -> Result<()> {
+        let current = self.chunk(indx)?;
+        let offset = match self.chunk_offset(indx) {
+            Some(offset) => offset,
+            None => return Err(format!("The {} chunk has no offset on disk", current.chunk_type()).into())
+        };
+        write_chunk_at(file, offset, &current.chunk_type(), current.length(), &chunk)?;
+        self.chunks[indx] = chunk;
+        Ok(())
+    }
+    
     pub fn header(&self) -> &[u8; 8] {
         &self.header
     }
@@ -81,63 +148,160 @@ impl Png {
         None
     }
     
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        match self.chunk_by_type("IHDR") {
+            Some(chunk) => Ihdr::parse(chunk.data()),
+            None => Err("Missing IHDR chunk".into())
+        }
+    }
+    
+    pub fn palette(&self) -> Result<Option<Plte>> {
+        match self.chunk_by_type("PLTE") {
+            Some(chunk) => Ok(Some(Plte::parse(chunk.data())?)),
+            None => Ok(None)
+        }
+    }
+    
+    pub fn transparency(&self) -> Result<Option<Trns>> {
+        match self.chunk_by_type("tRNS") {
+            Some(chunk) => Ok(Some(Trns::parse(chunk.data(), Some(&self.ihdr()?))?)),
+            None => Ok(None)
+        }
+    }
+    
+    /// The concatenated contents of every IDAT chunk.
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks.iter()
+            .filter(|chunk| chunk.chunk_type().bytes() == *b"IDAT")
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect()
+    }
+    
+    /// Decodes the default image to canonical RGBA16 pixels.
+    pub fn decode(&self) -> Result<Image> {
+        let header = self.ihdr()?;
+        decoder::decode_image(&header, self.palette()?.as_ref(), self.transparency()?.as_ref(), &self.image_data())
+    }
+    
+    /// SHA-256 of the decoded image: width and height as big-endian u32s,
+    /// then the RGBA16 samples. It stays the same whatever the color type,
+    /// bit depth, filtering, compression or chunk layout, so two files
+    /// showing the same pixels share it.
+    pub fn pixel_hash(&self) -> Result<[u8; 32]> {
+        let image = self.decode()?;
+        let mut hasher = Sha256::new();
+        hasher.update(image.width().to_be_bytes());
+        hasher.update(image.height().to_be_bytes());
+        hasher.update(image.to_rgba16_bytes());
+        Ok(hasher.finalize().into())
+    }
+
+    /// SHA-256 of the length, type and data of every critical chunk in
+    /// order, with the IDAT chunks hashed as one, so it ignores ancillary
+    /// chunks and how the image data is split.
+    pub fn critical_chunk_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        let mut hashed_idat = false;
+        for chunk in self.chunks.iter().filter(|chunk| chunk.chunk_type().is_critical()) {
+            let chunk_type = chunk.chunk_type().bytes();
+            if chunk_type == *b"IDAT" {
+                if !hashed_idat {
+                    let data = self.image_data();
+                    hasher.update((data.len() as u64).to_be_bytes());
+                    hasher.update(chunk_type);
+                    hasher.update(data);
+                    hashed_idat = true;
+                }
+                continue;
+            }
+            hasher.update((chunk.length() as u64).to_be_bytes());
+            hasher.update(chunk_type);
+            hasher.update(chunk.data());
+        }
+        hasher.finalize().into()
+    }
+
+    /// Parses every chunk with a known type, in file order. Unknown chunk
+    /// types are skipped.
+    pub fn known_chunks(&self) -> Vec<Result<KnownChunk>> {
+        let header = self.ihdr().ok();
+        self.chunks.iter()
+            .filter(|chunk| KnownChunk::is_known(&chunk.chunk_type()))
+            .map(|chunk| KnownChunk::from_chunk(chunk, header.as_ref()))
+            .collect()
+    }
+    
     pub fn as_bytes(&self) -> Vec<u8> {
-        return self.header().iter()
-            .map(|&x| x)
-            .chain(self.chunks.iter().flat_map(|chunk| {
-                let bytes = u32::to_be_bytes(chunk.length()).iter().map(|&x| x)
-                    .chain(chunk.chunk_type().bytes())
-                    .chain(chunk.as_bytes().iter().map(|&x| x))
-                    .chain(u32::to_be_bytes(chunk.crc()))
-                    .collect::<Vec<u8>>();
-                bytes
-            }))
-            .collect::<Vec<u8>>();
+        let mut writer = ChunkWriter::without_signature(self.header.to_vec());
+        for chunk in &self.chunks {
+            writer.write_chunk(chunk).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+}
+
+/// A zero-filled `PADDING_CHUNK_TYPE` chunk to stand in for a chunk of
+/// `chunk_type` and `length`. Critical chunks can't be padded.
+pub(crate) fn padding_for(chunk_type: &ChunkType, length: u32) -> Result<Chunk> {
+    if chunk_type.is_critical() {
+        return Err(format!("Cannot replace critical {} chunk with padding", chunk_type).into());
     }
+    Chunk::from_type_str(PADDING_CHUNK_TYPE, vec![0; length as usize])
+}
+
+/// Writes `chunk` over the chunk of `chunk_type` and `length` at `offset`
+/// in `file`. Nothing is written unless the file still has that chunk
+/// there and `chunk` is the same size.
+pub(crate) fn write_chunk_at<F: Read + Write + Seek>(file: &mut F, offset: u64, chunk_type: &ChunkType, length: u32, chunk: &Chunk) -> Result<()> {
+    if chunk.length() != length {
+        return Err(format!("New data is {} bytes but the {} chunk holds {}", chunk.length(), chunk_type, length).into());
+    }
+    let mut on_disk = [0u8; 8];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut on_disk)?;
+    if on_disk[0..4] != length.to_be_bytes() || on_disk[4..8] != chunk_type.bytes() {
+        return Err(format!("The file no longer has a {} chunk at offset {}", chunk_type, offset).into());
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut writer = ChunkWriter::without_signature(&mut *file);
+    writer.write_chunk(chunk)?;
+    writer.flush()
 }
 
 impl TryFrom<&[u8]> for Png {
     type Error = Error;
     
     fn try_from(bytes: &[u8]) -> Result<Self> {
-        let header:[u8; 8] = bytes[0..8].try_into().unwrap();
-        
-        if header != Png::STANDARD_HEADER {
-            return Err("Incorrect initial bytes".into());
-        }
-        
+        let png = PngRef::try_from(bytes)?;
         let mut chunks = Vec::new();
-        let remaining_bytes: &[u8] = &bytes[8..];
-        
-        let mut indx = 0;
-        while remaining_bytes.len() > indx {
-            match Chunk::try_from(&remaining_bytes[indx..]) {
-                Ok(chunk) => {
-                    let len = chunk.length();
-                    indx += len as usize + 12; // 12 -> 4 bytes for len, 4 bytes for chunktype, 4 bytes for crc 
-                    chunks.push(chunk);
-                    // println!("Success first chunk");
-                }, 
-                Err(e) => return Err(format!("Invalid chunk found because of {}", e).into()),
-            }
+        let mut offsets = Vec::new();
+        let mut offset = png.header().len() as u64;
+        for chunk in png.chunks() {
+            offsets.push(Some(offset));
+            offset += chunk.length() as u64 + 12;
+            chunks.push(chunk.to_chunk());
         }
-        
         Ok(Png {
-            header: header,
-            chunks: chunks
+            header: *png.header(),
+            chunks,
+            offsets
         })
     }
 }
 
 impl Display for Png {
     fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
-        let _ = write!(f, "{:?}\n", self.header).unwrap();
-        self.chunks().iter().for_each(|chunk| write!(f, "{:?}\n", chunk.data()).unwrap());
-        return Ok(());
+        writeln!(f, "{:?}", self.header).unwrap();
+        self.chunks().iter().for_each(|chunk| writeln!(f, "{:?}", chunk.data()).unwrap());
+        Ok(())
     }
 }
 
 #[cfg(test)]
+// The original tests are kept as they were written, from before clippy was
+// run with warnings denied.
+#[allow(unused_imports, clippy::let_and_return, clippy::map_clone, clippy::manual_is_ascii_check, clippy::needless_borrow, clippy::println_empty_string)]
 mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
@@ -281,6 +445,95 @@ mod tests {
         assert!(png.is_ok());
     }
 
+    #[test]
+    fn test_ihdr() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+        assert_eq!((ihdr.width, ihdr.height), (50, 50));
+        assert_eq!(ihdr.bit_depth, 8);
+    }
+
+    #[test]
+    fn test_known_chunks() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let known = png.known_chunks();
+        assert_eq!(known.len(), 4);
+        assert!(known.iter().all(|chunk| chunk.is_ok()));
+    }
+
+    #[test]
+    fn test_decode() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.decode().unwrap();
+        assert_eq!((image.width(), image.height()), (50, 50));
+        assert_eq!(image.pixels().len(), 2500);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let (optimized, _) = crate::optimize::optimize(&png, false).unwrap();
+        assert_ne!(optimized.as_bytes(), png.as_bytes());
+        assert_ne!(optimized.critical_chunk_hash(), png.critical_chunk_hash());
+        assert_eq!(optimized.pixel_hash().unwrap(), png.pixel_hash().unwrap());
+
+        // Splitting IDAT and dropping ancillary chunks keeps the critical hash.
+        let first_idat = png.chunks().iter().position(|chunk| chunk.chunk_type().bytes() == *b"IDAT").unwrap();
+        let mut chunks: Vec<Chunk> = png.chunks()[..first_idat].iter()
+            .filter(|chunk| chunk.chunk_type().is_critical())
+            .cloned()
+            .collect();
+        for part in png.image_data().chunks(100) {
+            chunks.push(Chunk::from_type_str("IDAT", part.to_vec()).unwrap());
+        }
+        chunks.extend(png.chunks()[first_idat..].iter()
+            .filter(|chunk| chunk.chunk_type().is_critical() && chunk.chunk_type().bytes() != *b"IDAT")
+            .cloned());
+        let split = Png::from_chunks(chunks);
+        assert_eq!(split.critical_chunk_hash(), png.critical_chunk_hash());
+
+        let mut image = png.decode().unwrap();
+        image.set_pixel(0, 0, [0x1234, 0, 0, u16::MAX]);
+        let changed = crate::encoder::encode(&image, &crate::encoder::EncodeOptions::for_image(&image)).unwrap();
+        assert_ne!(changed.pixel_hash().unwrap(), png.pixel_hash().unwrap());
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let png = Png::from_reader(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_chunk_offsets() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let from_reader = Png::from_reader(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.chunk_offset(0), Some(8));
+        assert_eq!(png.chunk_offset(1), Some(8 + 12 + 13));
+        for indx in 0..png.chunks().len() {
+            assert_eq!(png.chunk_offset(indx), from_reader.chunk_offset(indx));
+        }
+        assert_eq!(testing_png().chunk_offset(0), None);
+    }
+
+    #[test]
+    fn test_patch_chunk_in_place() {
+        let mut file = std::io::Cursor::new(testing_png().as_bytes());
+        let mut png = Png::try_from(file.get_ref().as_slice()).unwrap();
+        assert!(png.patch_chunk_in_place(&mut file, 2, b"I am a longer last chunk".to_vec()).is_err());
+        png.patch_chunk_in_place(&mut file, 2, b"I am a patched one!".to_vec()).unwrap();
+        png.pad_chunk_in_place(&mut file, 1).unwrap();
+
+        let reread = Png::try_from(file.get_ref().as_slice()).unwrap();
+        assert_eq!(reread.as_bytes(), png.as_bytes());
+        assert_eq!(reread.chunks()[2].data_as_string().unwrap(), "I am a patched one!");
+        assert_eq!(reread.chunks()[1].chunk_type().to_string(), PADDING_CHUNK_TYPE);
+        assert_eq!(reread.chunks()[1].data(), vec![0; 18]);
+        assert!(png.pad_chunk_in_place(&mut file, 0).is_err());
+        assert!(png.pad_chunk_in_place(&mut file, 3).is_err());
+        assert!(png.patch_chunk_in_place(&mut file, 3, Vec::new()).is_err());
+    }
+
     #[test]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
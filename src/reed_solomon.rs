@@ -0,0 +1,264 @@
+use crate::Result;
+
+/// Longest codeword over GF(2^8), data and parity together.
+pub const MAX_BLOCK: usize = 255;
+
+/// Reducing polynomial of GF(2^8), x^8 + x^4 + x^3 + x^2 + 1.
+const PRIMITIVE: u16 = 0x11d;
+
+struct Tables {
+    /// Powers of the generator 2, doubled up so sums of two logs index directly.
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const TABLES: Tables = build_tables();
+
+const fn build_tables() -> Tables {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= PRIMITIVE;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    Tables { exp, log }
+}
+
+fn mul(x: u8, y: u8) -> u8 {
+    if x == 0 || y == 0 {
+        return 0;
+    }
+    TABLES.exp[TABLES.log[x as usize] as usize + TABLES.log[y as usize] as usize]
+}
+
+fn div(x: u8, y: u8) -> u8 {
+    if x == 0 {
+        return 0;
+    }
+    TABLES.exp[(TABLES.log[x as usize] as usize + 255 - TABLES.log[y as usize] as usize) % 255]
+}
+
+fn pow2(power: usize) -> u8 {
+    TABLES.exp[power % 255]
+}
+
+fn inverse(x: u8) -> u8 {
+    TABLES.exp[255 - TABLES.log[x as usize] as usize]
+}
+
+// Polynomials are stored highest degree first.
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| mul(c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut sum = vec![0; len];
+    for (i, &c) in p.iter().enumerate() {
+        sum[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        sum[i + len - q.len()] ^= c;
+    }
+    sum
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut product = vec![0; p.len() + q.len() - 1];
+    for (i, &a) in p.iter().enumerate() {
+        for (j, &b) in q.iter().enumerate() {
+            product[i + j] ^= mul(a, b);
+        }
+    }
+    product
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p.iter().fold(0, |y, &c| mul(y, x) ^ c)
+}
+
+fn generator(parity: usize) -> Vec<u8> {
+    (0..parity).fold(vec![1], |g, i| poly_mul(&g, &[1, pow2(i)]))
+}
+
+/// Appends `parity` check bytes to `data`. Up to `parity / 2` corrupted
+/// bytes anywhere in the result can be corrected by `decode`.
+pub fn encode(data: &[u8], parity: usize) -> Result<Vec<u8>> {
+    if parity == 0 || data.len() + parity > MAX_BLOCK {
+        return Err(format!("A block holds at most {} bytes, not {} data and {} parity", MAX_BLOCK, data.len(), parity).into());
+    }
+    let generator = generator(parity);
+    let mut remainder = data.to_vec();
+    remainder.resize(data.len() + parity, 0);
+    for i in 0..data.len() {
+        let coefficient = remainder[i];
+        if coefficient != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                remainder[i + j] ^= mul(g, coefficient);
+            }
+        }
+    }
+    remainder[..data.len()].copy_from_slice(data);
+    Ok(remainder)
+}
+
+fn syndromes(block: &[u8], parity: usize) -> Vec<u8> {
+    (0..parity).map(|i| poly_eval(block, pow2(i))).collect()
+}
+
+/// Berlekamp-Massey: the polynomial whose roots locate the errors.
+fn error_locator(syndromes: &[u8]) -> Vec<u8> {
+    let mut locator = vec![1];
+    let mut old = vec![1];
+    for i in 0..syndromes.len() {
+        let mut delta = syndromes[i];
+        for j in 1..locator.len() {
+            delta ^= mul(locator[locator.len() - 1 - j], syndromes[i - j]);
+        }
+        old.push(0);
+        if delta != 0 {
+            if old.len() > locator.len() {
+                let new = poly_scale(&old, delta);
+                old = poly_scale(&locator, inverse(delta));
+                locator = new;
+            }
+            locator = poly_add(&locator, &poly_scale(&old, delta));
+        }
+    }
+    let leading = locator.iter().position(|&c| c != 0).unwrap_or(locator.len());
+    locator.split_off(leading)
+}
+
+/// Checks `block` against its trailing `parity` bytes and corrects up to
+/// `parity / 2` errors. Returns the data bytes and how many were corrected.
+pub fn decode(block: &[u8], parity: usize) -> Result<(Vec<u8>, usize)> {
+    if parity == 0 || block.len() <= parity || block.len() > MAX_BLOCK {
+        return Err(format!("Invalid block of {} bytes with {} parity", block.len(), parity).into());
+    }
+    let syndromes = syndromes(block, parity);
+    if syndromes.iter().all(|&s| s == 0) {
+        return Ok((block[..block.len() - parity].to_vec(), 0));
+    }
+
+    let locator = error_locator(&syndromes);
+    let errors = locator.len() - 1;
+    if errors * 2 > parity {
+        return Err("Too many errors to correct".into());
+    }
+    // Chien search: an error at position p is a root at 2^-(n-1-p).
+    let reversed: Vec<u8> = locator.iter().rev().copied().collect();
+    let positions: Vec<usize> = (0..block.len())
+        .filter(|&i| poly_eval(&reversed, pow2(i)) == 0)
+        .map(|i| block.len() - 1 - i)
+        .collect();
+    if positions.len() != errors {
+        return Err("Too many errors to correct".into());
+    }
+
+    // Forney: the error magnitudes from the evaluator polynomial.
+    let powers: Vec<usize> = positions.iter().map(|&p| block.len() - 1 - p).collect();
+    let errata_locator = powers.iter().fold(vec![1], |l, &i| poly_mul(&l, &poly_add(&[1], &[pow2(i), 0])));
+    // The syndrome polynomial S(x) = S_0 x + S_1 x^2 + ..., highest degree first.
+    let syndrome_poly: Vec<u8> = syndromes.iter().rev().copied().chain([0]).collect();
+    let product = poly_mul(&syndrome_poly, &errata_locator);
+    let evaluator = &product[product.len().saturating_sub(errata_locator.len())..];
+
+    let mut corrected = block.to_vec();
+    let roots: Vec<u8> = powers.iter().map(|&i| pow2(i)).collect();
+    for (i, &x) in roots.iter().enumerate() {
+        let x_inverse = inverse(x);
+        let locator_prime = roots.iter().enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |prime, (_, &other)| mul(prime, 1 ^ mul(x_inverse, other)));
+        if locator_prime == 0 {
+            return Err("Too many errors to correct".into());
+        }
+        let y = mul(x, poly_eval(evaluator, x_inverse));
+        corrected[positions[i]] ^= div(y, locator_prime);
+    }
+    if self::syndromes(&corrected, parity).iter().any(|&s| s != 0) {
+        return Err("Too many errors to correct".into());
+    }
+    corrected.truncate(block.len() - parity);
+    Ok((corrected, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field() {
+        for x in 1..=255u8 {
+            assert_eq!(mul(x, inverse(x)), 1);
+            assert_eq!(div(mul(x, 7), 7), x);
+        }
+    }
+
+    #[test]
+    fn test_corrects_up_to_half_the_parity() {
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 37 % 251) as u8).collect();
+        let block = encode(&data, 16).unwrap();
+        assert_eq!(block.len(), 216);
+        assert_eq!(decode(&block, 16).unwrap(), (data.clone(), 0));
+
+        let mut corrupted = block.clone();
+        for i in 0..8 {
+            corrupted[i * 27] ^= 0x5a + i as u8;
+        }
+        assert_eq!(decode(&corrupted, 16).unwrap(), (data.clone(), 8));
+
+        // Corrupting the parity bytes themselves is corrected too.
+        let mut corrupted = block.clone();
+        corrupted[215] = 0;
+        corrupted[201] ^= 1;
+        assert_eq!(decode(&corrupted, 16).unwrap(), (data, 2));
+    }
+
+    #[test]
+    fn test_random_errors() {
+        let mut seed: u32 = 12345;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as usize
+        };
+        for _ in 0..200 {
+            let (len, parity) = (1 + next() % 200, 2 + next() % 40);
+            let data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            let block = encode(&data, parity).unwrap();
+            let mut corrupted = block.clone();
+            let mut positions = Vec::new();
+            while positions.len() < parity / 2 {
+                let position = next() % block.len();
+                if !positions.contains(&position) {
+                    positions.push(position);
+                    corrupted[position] ^= 1 + (next() % 255) as u8;
+                }
+            }
+            assert_eq!(decode(&corrupted, parity).unwrap(), (data, parity / 2));
+        }
+    }
+
+    #[test]
+    fn test_too_many_errors() {
+        let block = encode(b"hidden message", 4).unwrap();
+        let mut corrupted = block.clone();
+        corrupted[0] ^= 1;
+        corrupted[3] ^= 1;
+        corrupted[6] ^= 1;
+        assert!(decode(&corrupted, 4).is_err());
+        assert!(encode(&[0; 250], 10).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use std::fmt::{Display, Formatter};
+use crc::Crc;
+use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, TINFLStatus};
+use crate::chunk::{Chunk, CRC_PNG};
+use crate::chunk_type::ChunkType;
+use crate::decoder;
+use crate::known_chunk::{Ihdr, KnownChunk};
+use crate::mapped;
+use crate::png::Png;
+use crate::Result;
+
+/// Chunk types whose signatures are trusted when resynchronizing after
+/// damage. Private and unknown chunks are only kept when a chunk boundary
+/// leads straight to them.
+const SYNC_TYPES: [&str; 7] = ["IHDR", "PLTE", "IDAT", "IEND", "tEXt", "zTXt", "iTXt"];
+
+/// Most image data inflated when checking candidate dimensions, however
+/// large the search limit.
+const MAX_INFLATED: u64 = 1 << 30;
+
+/// A single change made while repairing a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    Signature,
+    Crc { chunk_type: String, offset: usize, found: u32, expected: u32 },
+    Length { chunk_type: String, offset: usize, found: u32, fixed: u32 },
+    SkippedBytes { offset: usize, length: usize },
+    DroppedChunk { offset: usize, reason: String },
+    AddedIend,
+}
+
+impl Display for Repair {
+    fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Repair::Signature => write!(f, "Replaced damaged signature"),
+            Repair::Crc { chunk_type, offset, found, expected } =>
+                write!(f, "{} at offset {}: CRC {:08x} replaced with {:08x}", chunk_type, offset, found, expected),
+            Repair::Length { chunk_type, offset, found, fixed } =>
+                write!(f, "{} at offset {}: length {} corrected to {}", chunk_type, offset, found, fixed),
+            Repair::SkippedBytes { offset, length } =>
+                write!(f, "Skipped {} unreadable bytes at offset {}", length, offset),
+            Repair::DroppedChunk { offset, reason } =>
+                write!(f, "Dropped chunk at offset {}: {}", offset, reason),
+            Repair::AddedIend => write!(f, "Added missing IEND chunk"),
+        }
+    }
+}
+
+/// Rebuilds whatever chunks can be recovered from a damaged PNG, returning
+/// the repaired image and every change made to get there.
+pub fn repair(bytes: &[u8]) -> Result<(Png, Vec<Repair>)> {
+    let mut repairs = Vec::new();
+    let mut chunks = Vec::new();
+
+    let mut pos = Png::STANDARD_HEADER.len();
+    if bytes.len() < pos || bytes[..pos] != Png::STANDARD_HEADER {
+        repairs.push(Repair::Signature);
+        if !(bytes.len() >= 16 && &bytes[12..16] == b"IHDR") {
+            match find_sync(bytes, 0) {
+                Some(next) => {
+                    if next > 0 {
+                        repairs.push(Repair::SkippedBytes { offset: 0, length: next });
+                    }
+                    pos = next;
+                },
+                None => return Err("No recognizable chunks found".into())
+            }
+        }
+    }
+
+    while pos < bytes.len() {
+        if bytes.len() - pos < 12 {
+            repairs.push(Repair::SkippedBytes { offset: pos, length: bytes.len() - pos });
+            break;
+        }
+        let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap());
+        let chunk_type = match plausible_type(&bytes[pos + 4..pos + 8]) {
+            Some(chunk_type) => chunk_type,
+            None => {
+                let next = find_sync(bytes, pos + 1).unwrap_or(bytes.len());
+                repairs.push(Repair::SkippedBytes { offset: pos, length: next - pos });
+                pos = next;
+                continue;
+            }
+        };
+        let name = chunk_type.to_string();
+        let declared_end = pos.checked_add(12 + length as usize).filter(|&end| end <= bytes.len());
+
+        // A declared length is trusted if its CRC matches or it lands on
+        // the end of the file or another chunk header. Otherwise it is only
+        // replaced when it runs off the end of the file, or when the next
+        // chunk signature gives a length whose CRC matches.
+        let length = match declared_end {
+            Some(end) if crc_matches(bytes, pos, length) || is_boundary(bytes, end) => length,
+            _ => match find_sync(bytes, pos + 12) {
+                Some(next) if declared_end.is_none() || crc_matches(bytes, pos, (next - pos - 12) as u32) => {
+                    let fixed = (next - pos - 12) as u32;
+                    repairs.push(Repair::Length { chunk_type: name.clone(), offset: pos, found: length, fixed });
+                    fixed
+                },
+                _ if declared_end.is_some() => length,
+                _ => {
+                    repairs.push(Repair::DroppedChunk { offset: pos, reason: format!("{} chunk is truncated", name) });
+                    break;
+                }
+            }
+        };
+        let end = pos + 12 + length as usize;
+
+        let chunk = Chunk::new(chunk_type, bytes[pos + 8..end - 4].to_vec());
+        let found = u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap());
+        if found != chunk.crc() {
+            repairs.push(Repair::Crc { chunk_type: name, offset: pos, found, expected: chunk.crc() });
+        }
+        chunks.push(chunk);
+        pos = end;
+
+        // A damaged length is dealt with on the next pass; only bytes that
+        // can't be a chunk type at all are skipped here
+        if bytes.len() - pos >= 8 && plausible_type(&bytes[pos + 4..pos + 8]).is_none() {
+            let next = find_sync(bytes, pos).unwrap_or(bytes.len());
+            repairs.push(Repair::SkippedBytes { offset: pos, length: next - pos });
+            pos = next;
+        }
+    }
+
+    if chunks.is_empty() {
+        return Err("No recognizable chunks found".into());
+    }
+    if !chunks.iter().any(|chunk| chunk.chunk_type().bytes() == *b"IEND") {
+        chunks.push(Chunk::from_type_str("IEND", Vec::new())?);
+        repairs.push(Repair::AddedIend);
+    }
+    Ok((Png::from_chunks(chunks), repairs))
+}
+
+/// Width and height that give the IHDR CRC stored in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+    /// Whether the inflated image data is exactly the size these dimensions
+    /// need, or `None` if the image data couldn't be inflated.
+    pub matches_data: Option<bool>,
+}
+
+/// Searches widths and heights from 1 to `limit` for those that make the
+/// IHDR chunk match its stored CRC, for files whose dimensions were edited
+/// without updating the CRC. Candidates that fit the image data come first.
+pub fn recover_dimensions(bytes: &[u8], limit: u32) -> Result<Vec<Dimensions>> {
+    if bytes.len() < 33 || &bytes[12..16] != b"IHDR" || bytes[8..12] != 13u32.to_be_bytes() {
+        return Err("File does not start with an IHDR chunk".into());
+    }
+    let stored_crc = u32::from_be_bytes(bytes[29..33].try_into().unwrap());
+    // The stored width and height may be zero or huge, so only the other
+    // fields are checked
+    let mut fields = bytes[16..29].to_vec();
+    fields[0..8].copy_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1]);
+    let header = Ihdr::parse(&fields)?;
+    let largest = decoder::raw_data_size(&Ihdr { width: limit, height: limit, ..header.clone() });
+    let data_size = image_data(bytes).and_then(|data| inflated_size(&data, largest.min(MAX_INFLATED)));
+
+    let crc: &'static Crc<u32> = &CRC_PNG;
+    let mut prefix = crc.digest();
+    prefix.update(b"IHDR");
+    let mut found = Vec::new();
+    for width in 1..=limit {
+        let mut with_width = prefix.clone();
+        with_width.update(&width.to_be_bytes());
+        for height in 1..=limit {
+            let mut digest = with_width.clone();
+            digest.update(&height.to_be_bytes());
+            digest.update(&bytes[24..29]);
+            if digest.finalize() == stored_crc {
+                let candidate = Ihdr { width, height, ..header.clone() };
+                let matches_data = data_size.map(|size| size == Some(decoder::raw_data_size(&candidate)));
+                found.push(Dimensions { width, height, matches_data });
+            }
+        }
+    }
+    found.sort_by_key(|candidate| candidate.matches_data != Some(true));
+    Ok(found)
+}
+
+/// A copy of `bytes` with the IHDR width and height replaced and its CRC
+/// recomputed.
+pub fn set_dimensions(bytes: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    if bytes.len() < 33 || &bytes[12..16] != b"IHDR" {
+        return Err("File does not start with an IHDR chunk".into());
+    }
+    let mut bytes = bytes.to_vec();
+    bytes[16..20].copy_from_slice(&width.to_be_bytes());
+    bytes[20..24].copy_from_slice(&height.to_be_bytes());
+    let crc = CRC_PNG.checksum(&bytes[12..29]);
+    bytes[29..33].copy_from_slice(&crc.to_be_bytes());
+    Ok(bytes)
+}
+
+/// Size of the zlib stream `data` once inflated, `Some(None)` if that is
+/// more than `max` bytes, or `None` if it can't be inflated.
+fn inflated_size(data: &[u8], max: u64) -> Option<Option<u64>> {
+    match decompress_to_vec_zlib_with_limit(data, usize::try_from(max).unwrap_or(usize::MAX)) {
+        Ok(data) => Some(Some(data.len() as u64)),
+        Err(e) if e.status == TINFLStatus::HasMoreOutput => Some(None),
+        Err(_) => None
+    }
+}
+
+/// The IDAT contents of a file whose IHDR can't be trusted.
+fn image_data(bytes: &[u8]) -> Option<Vec<u8>> {
+    let index = mapped::build_index(bytes).ok()?;
+    let data = index.iter()
+        .filter(|header| header.chunk_type.bytes() == *b"IDAT")
+        .flat_map(|header| &bytes[header.offset as usize + 8..header.offset as usize + 8 + header.length as usize])
+        .copied()
+        .collect();
+    Some(data)
+}
+
+/// A chunk type made only of letters with the reserved bit clear.
+fn plausible_type(bytes: &[u8]) -> Option<ChunkType> {
+    let bytes: [u8; 4] = bytes.try_into().ok()?;
+    if !bytes.iter().all(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    ChunkType::try_from(bytes).ok()
+}
+
+fn is_sync_type(bytes: &[u8]) -> bool {
+    SYNC_TYPES.iter().chain(KnownChunk::TYPES.iter()).any(|t| t.as_bytes() == bytes)
+}
+
+/// Whether a chunk header that could be read in full starts at `pos`.
+fn is_boundary(bytes: &[u8], pos: usize) -> bool {
+    if pos == bytes.len() {
+        return true;
+    }
+    if bytes.len() - pos < 12 || plausible_type(&bytes[pos + 4..pos + 8]).is_none() {
+        return false;
+    }
+    let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+    pos + 12 + length <= bytes.len()
+}
+
+/// The start of the next chunk at or after `from` whose type is a trusted
+/// signature.
+fn find_sync(bytes: &[u8], from: usize) -> Option<usize> {
+    (from..bytes.len().saturating_sub(11)).find(|&pos| is_sync_type(&bytes[pos + 4..pos + 8]))
+}
+
+fn crc_matches(bytes: &[u8], pos: usize, length: u32) -> bool {
+    let end = pos + 12 + length as usize;
+    CRC_PNG.checksum(&bytes[pos + 4..end - 4]) == u32::from_be_bytes(bytes[end - 4..end].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    #[test]
+    fn test_intact_file_unchanged() {
+        let (png, repairs) = repair(IMAGE).unwrap();
+        assert!(repairs.is_empty());
+        assert_eq!(png.as_bytes(), IMAGE);
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut bytes = IMAGE.to_vec();
+        bytes[29] ^= 0xff;
+        let (png, repairs) = repair(&bytes).unwrap();
+        assert!(matches!(&repairs[..], [Repair::Crc { offset: 8, .. }]));
+        assert_eq!(png.as_bytes(), IMAGE);
+    }
+
+    #[test]
+    fn test_wrong_length() {
+        let mut bytes = IMAGE.to_vec();
+        bytes[33..37].copy_from_slice(&1_000_000u32.to_be_bytes());
+        let (png, repairs) = repair(&bytes).unwrap();
+        assert!(matches!(&repairs[..], [Repair::Length { found: 1_000_000, fixed: 41370, .. }]));
+        assert_eq!(png.as_bytes(), IMAGE);
+    }
+
+    #[test]
+    fn test_short_length() {
+        let mut bytes = IMAGE.to_vec();
+        bytes[33..37].copy_from_slice(&100u32.to_be_bytes());
+        let (png, repairs) = repair(&bytes).unwrap();
+        assert!(matches!(&repairs[..], [Repair::Length { found: 100, fixed: 41370, .. }]));
+        assert_eq!(png.as_bytes(), IMAGE);
+    }
+
+    #[test]
+    fn test_bad_crc_followed_by_garbage() {
+        let mut bytes = IMAGE[..33].to_vec();
+        bytes[29] ^= 0xff;
+        bytes.extend_from_slice(b"junkjunk");
+        bytes.extend_from_slice(&IMAGE[33..]);
+        let (png, repairs) = repair(&bytes).unwrap();
+        assert!(matches!(&repairs[..], [Repair::Crc { offset: 8, .. }, Repair::SkippedBytes { offset: 33, length: 8 }]));
+        assert_eq!(png.as_bytes(), IMAGE);
+    }
+
+    #[test]
+    fn test_recover_dimensions() {
+        let tampered = {
+            let mut bytes = IMAGE.to_vec();
+            bytes[20..24].copy_from_slice(&0u32.to_be_bytes());
+            bytes
+        };
+        let candidates = recover_dimensions(&tampered, 400).unwrap();
+        assert_eq!(candidates[0], Dimensions { width: 386, height: 395, matches_data: Some(true) });
+        assert_eq!(set_dimensions(&tampered, 386, 395).unwrap(), IMAGE);
+        assert!(recover_dimensions(&IMAGE[..20], 400).is_err());
+    }
+
+    #[test]
+    fn test_inflate_stops_at_largest_candidate() {
+        let data = image_data(IMAGE).unwrap();
+        let size = inflated_size(&data, MAX_INFLATED).unwrap().unwrap();
+        assert_eq!(inflated_size(&data, size), Some(Some(size)));
+        assert_eq!(inflated_size(&data, size - 1), Some(None));
+        assert_eq!(inflated_size(b"not zlib", size), None);
+    }
+
+    #[test]
+    fn test_garbage_and_missing_iend() {
+        let png = Png::try_from(IMAGE).unwrap();
+        let mut bytes = IMAGE[..33].to_vec();
+        bytes.extend_from_slice(b"garbage!");
+        bytes.extend_from_slice(&IMAGE[33..41415]);
+        let (repaired, repairs) = repair(&bytes).unwrap();
+        assert_eq!(repairs, vec![Repair::SkippedBytes { offset: 33, length: 8 }, Repair::AddedIend]);
+        assert!(repaired.decode().unwrap() == png.decode().unwrap());
+    }
+
+    #[test]
+    fn test_damaged_signature_and_truncation() {
+        let mut bytes = IMAGE[..1000].to_vec();
+        bytes[1] = b'X';
+        let (png, repairs) = repair(&bytes).unwrap();
+        assert_eq!(repairs[0], Repair::Signature);
+        assert!(matches!(repairs[1], Repair::DroppedChunk { offset: 33, .. }));
+        assert_eq!(repairs[2], Repair::AddedIend);
+        assert_eq!(png.chunks().len(), 2);
+        assert!(repair(b"not a png at all").is_err());
+    }
+}
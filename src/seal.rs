@@ -0,0 +1,156 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+use crate::keys;
+use crate::Result;
+
+pub const PUBLIC_KEY_LABEL: &str = "pngme-x25519-public";
+pub const SECRET_KEY_LABEL: &str = "pngme-x25519-secret";
+
+/// HKDF context for deriving each recipient's wrapping key.
+const HKDF_INFO: &[u8] = b"pngme sealed message v1";
+/// A 32-byte message key encrypted with ChaCha20-Poly1305.
+const WRAPPED_KEY_LEN: usize = 32 + 16;
+/// Every key is used for exactly one encryption, so a fixed nonce is safe.
+const NONCE: [u8; 12] = [0; 12];
+
+/// A recipient's X25519 key pair.
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity { secret: StaticSecret::random_from_rng(OsRng) }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.secret)
+    }
+
+    /// The key file: the secret key, with the public key alongside so it
+    /// can be recovered.
+    pub fn to_key_file(&self) -> String {
+        keys::to_key_line(SECRET_KEY_LABEL, self.secret.as_bytes()) + &public_key_file(&self.public_key())
+    }
+
+    pub fn from_key_file(contents: &str) -> Result<Identity> {
+        Ok(Identity { secret: StaticSecret::from(keys::from_key_file(contents, SECRET_KEY_LABEL)?) })
+    }
+}
+
+pub fn public_key_file(public: &PublicKey) -> String {
+    keys::to_key_line(PUBLIC_KEY_LABEL, public.as_bytes())
+}
+
+pub fn public_key_from_file(contents: &str) -> Result<PublicKey> {
+    Ok(PublicKey::from(keys::from_key_file(contents, PUBLIC_KEY_LABEL)?))
+}
+
+/// Encrypts `message` so that any one of `recipients` can open it.
+///
+/// A random message key encrypts the message. For each recipient, that key
+/// is wrapped with a key derived from X25519 between a fresh ephemeral key
+/// and the recipient's public key. Wrapped keys don't name their recipient;
+/// `open` tries each one. Layout: ephemeral public key, recipient count,
+/// wrapped keys, encrypted message.
+pub fn seal(message: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>> {
+    if recipients.is_empty() || recipients.len() > u8::MAX as usize {
+        return Err(format!("Between 1 and {} recipients are needed", u8::MAX).into());
+    }
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let mut message_key = [0u8; 32];
+    OsRng.fill_bytes(&mut message_key);
+
+    let mut sealed = ephemeral_public.as_bytes().to_vec();
+    sealed.push(recipients.len() as u8);
+    for recipient in recipients {
+        let wrapping_key = wrapping_key(ephemeral.diffie_hellman(recipient), &ephemeral_public, recipient)?;
+        sealed.extend(encrypt(&wrapping_key, &message_key)?);
+    }
+    sealed.extend(encrypt(&message_key, message)?);
+    Ok(sealed)
+}
+
+/// Decrypts a message made by `seal`, if `identity` is one of its recipients.
+pub fn open(sealed: &[u8], identity: &Identity) -> Result<Vec<u8>> {
+    let truncated = || "Sealed message is truncated";
+    let ephemeral_public: [u8; 32] = sealed.get(..32).ok_or_else(truncated)?.try_into().unwrap();
+    let count = *sealed.get(32).ok_or_else(truncated)? as usize;
+    let body_start = 33 + count * WRAPPED_KEY_LEN;
+    let wrapped_keys = sealed.get(33..body_start).ok_or_else(truncated)?;
+
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+    let shared = identity.secret.diffie_hellman(&ephemeral_public);
+    let wrapping_key = wrapping_key(shared, &ephemeral_public, &identity.public_key())?;
+    let message_key = wrapped_keys.chunks(WRAPPED_KEY_LEN)
+        .find_map(|wrapped| decrypt(&wrapping_key, wrapped).ok())
+        .ok_or("This key is not one of the message's recipients")?;
+    let message_key: [u8; 32] = message_key.try_into().map_err(|_| "Invalid message key")?;
+    decrypt(&message_key, &sealed[body_start..]).map_err(|_| "Sealed message has been altered".into())
+}
+
+/// The key wrapping the message key for one recipient, from the X25519
+/// secret shared by the ephemeral key and the recipient's key.
+fn wrapping_key(shared: SharedSecret, ephemeral_public: &PublicKey, recipient: &PublicKey) -> Result<[u8; 32]> {
+    if !shared.was_contributory() {
+        return Err("Invalid public key".into());
+    }
+    let salt = [ephemeral_public.as_bytes().as_slice(), recipient.as_bytes()].concat();
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(HKDF_INFO, &mut key)
+        .map_err(|_| "Unable to derive key")?;
+    Ok(key)
+}
+
+fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(Nonce::from_slice(&NONCE), plaintext)
+        .map_err(|_| "Unable to encrypt".into())
+}
+
+fn decrypt(key: &[u8; 32], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(Nonce::from_slice(&NONCE), ciphertext)
+        .map_err(|_| "Unable to decrypt".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_recipient_can_open() {
+        let (alice, bob, eve) = (Identity::generate(), Identity::generate(), Identity::generate());
+        let sealed = seal(b"meet at noon", &[alice.public_key(), bob.public_key()]).unwrap();
+        assert_eq!(sealed.len(), 33 + 2 * WRAPPED_KEY_LEN + 12 + 16);
+        assert_eq!(open(&sealed, &alice).unwrap(), b"meet at noon");
+        assert_eq!(open(&sealed, &bob).unwrap(), b"meet at noon");
+        assert!(open(&sealed, &eve).is_err());
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let alice = Identity::generate();
+        let mut sealed = seal(b"meet at noon", &[alice.public_key()]).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open(&sealed, &alice).is_err());
+        assert!(open(&sealed[..40], &alice).is_err());
+        assert!(seal(b"nobody", &[]).is_err());
+    }
+
+    #[test]
+    fn test_key_files() {
+        let alice = Identity::generate();
+        let restored = Identity::from_key_file(&alice.to_key_file()).unwrap();
+        assert_eq!(restored.public_key(), alice.public_key());
+        assert_eq!(public_key_from_file(&alice.to_key_file()).unwrap(), alice.public_key());
+        assert!(Identity::from_key_file(&public_key_file(&alice.public_key())).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use std::str::FromStr;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::keys;
+use crate::png::Png;
+use crate::strip::COLOR_MANAGEMENT_TYPES;
+use crate::Result;
+
+/// Ancillary, private and unsafe to copy: editors that change the image
+/// should drop it, since the signature no longer holds.
+pub const SIGNATURE_CHUNK_TYPE: &str = "siGN";
+
+pub const PUBLIC_KEY_LABEL: &str = "pngme-ed25519-public";
+pub const SECRET_KEY_LABEL: &str = "pngme-ed25519-secret";
+
+const VERSION: u8 = 1;
+/// Prefixed to everything signed, so the signature can't be reused as one
+/// over some other kind of data.
+const DOMAIN: &[u8] = b"pngme png signature v1\0";
+
+/// Ancillary chunks signed unless others are chosen: the ones that change
+/// how the image looks.
+pub fn default_signed_types() -> Vec<String> {
+    std::iter::once("tRNS").chain(COLOR_MANAGEMENT_TYPES).map(|t| t.to_string()).collect()
+}
+
+/// An Ed25519 key pair for signing.
+pub struct Signer25519 {
+    key: SigningKey,
+}
+
+impl Signer25519 {
+    pub fn generate() -> Signer25519 {
+        Signer25519 { key: SigningKey::generate(&mut OsRng) }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    pub fn to_key_file(&self) -> String {
+        keys::to_key_line(SECRET_KEY_LABEL, self.key.as_bytes()) + &verifying_key_file(&self.verifying_key())
+    }
+
+    pub fn from_key_file(contents: &str) -> Result<Signer25519> {
+        Ok(Signer25519 { key: SigningKey::from_bytes(&keys::from_key_file(contents, SECRET_KEY_LABEL)?) })
+    }
+}
+
+pub fn verifying_key_file(key: &VerifyingKey) -> String {
+    keys::to_key_line(PUBLIC_KEY_LABEL, key.as_bytes())
+}
+
+pub fn verifying_key_from_file(contents: &str) -> Result<VerifyingKey> {
+    Ok(VerifyingKey::from_bytes(&keys::from_key_file(contents, PUBLIC_KEY_LABEL)?)?)
+}
+
+/// The first 8 bytes of the SHA-256 of a public key, so a verifier can
+/// tell which key a signature claims to be from.
+pub fn key_id(key: &VerifyingKey) -> [u8; 8] {
+    Sha256::digest(key.as_bytes())[..8].try_into().unwrap()
+}
+
+/// The contents of a signature chunk.
+struct SignatureData {
+    key_id: [u8; 8],
+    /// Ancillary chunk types covered besides every critical chunk.
+    types: Vec<[u8; 4]>,
+    signature: Signature,
+}
+
+impl SignatureData {
+    fn parse(data: &[u8]) -> Result<SignatureData> {
+        if data.first() != Some(&VERSION) {
+            return Err("Unknown signature version".into());
+        }
+        let count = *data.get(9).ok_or("Signature chunk is truncated")? as usize;
+        if data.len() != 10 + count * 4 + 64 {
+            return Err(format!("Signature chunk is {} bytes, expected {}", data.len(), 10 + count * 4 + 64).into());
+        }
+        Ok(SignatureData {
+            key_id: data[1..9].try_into().unwrap(),
+            types: data[10..10 + count * 4].chunks(4).map(|t| t.try_into().unwrap()).collect(),
+            signature: Signature::from_bytes(data[10 + count * 4..].try_into().unwrap()),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![VERSION];
+        bytes.extend_from_slice(&self.key_id);
+        bytes.push(self.types.len() as u8);
+        bytes.extend(self.types.iter().flatten());
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        bytes
+    }
+}
+
+/// What a valid signature covers.
+pub struct Verification {
+    pub key_id: [u8; 8],
+    /// Index and type of every signed chunk.
+    pub covered: Vec<(usize, String)>,
+    /// Index and type of every chunk that could change without breaking
+    /// the signature.
+    pub uncovered: Vec<(usize, String)>,
+}
+
+fn is_covered(chunk: &Chunk, types: &[[u8; 4]]) -> bool {
+    let chunk_type = chunk.chunk_type();
+    chunk_type.to_string() != SIGNATURE_CHUNK_TYPE && (chunk_type.is_critical() || types.contains(&chunk_type.bytes()))
+}
+
+/// The bytes that are signed: the domain, the covered ancillary types, and
+/// the SHA-256 of the length, type and data of every covered chunk in order.
+fn signed_message(png: &Png, key_id: &[u8; 8], types: &[[u8; 4]]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for chunk in png.chunks().iter().filter(|chunk| is_covered(chunk, types)) {
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.data());
+    }
+    let mut message = DOMAIN.to_vec();
+    message.extend_from_slice(key_id);
+    message.push(types.len() as u8);
+    message.extend(types.iter().flatten());
+    message.extend(hasher.finalize());
+    message
+}
+
+/// A copy of `png` with a signature chunk over its critical chunks and the
+/// ancillary chunks of `types`, replacing any earlier signature.
+pub fn sign(png: &Png, signer: &Signer25519, types: &[String]) -> Result<Png> {
+    let types = types.iter()
+        .map(|t| ChunkType::from_str(t).map(|t| t.bytes()))
+        .collect::<Result<Vec<[u8; 4]>>>()?;
+    if types.len() > u8::MAX as usize {
+        return Err(format!("At most {} chunk types can be signed", u8::MAX).into());
+    }
+    let chunks: Vec<Chunk> = png.chunks().iter()
+        .filter(|chunk| chunk.chunk_type().to_string() != SIGNATURE_CHUNK_TYPE)
+        .cloned()
+        .collect();
+    let mut signed = Png::from_chunks(chunks);
+    let key_id = key_id(&signer.verifying_key());
+    let signature = signer.key.sign(&signed_message(&signed, &key_id, &types));
+    let data = SignatureData { key_id, types, signature };
+    signed.insert_before_iend(Chunk::from_type_str(SIGNATURE_CHUNK_TYPE, data.to_bytes())?);
+    Ok(signed)
+}
+
+/// Checks the signature chunk of `png` against `key`.
+pub fn verify(png: &Png, key: &VerifyingKey) -> Result<Verification> {
+    let chunk = png.chunk_by_type(SIGNATURE_CHUNK_TYPE).ok_or("The image is not signed")?;
+    let data = SignatureData::parse(chunk.data())?;
+    if data.key_id != key_id(key) {
+        return Err(format!("Signed by key {}, not by this key", keys::hex(&data.key_id)).into());
+    }
+    key.verify_strict(&signed_message(png, &data.key_id, &data.types), &data.signature)
+        .map_err(|_| "Signature does not match, the image was modified")?;
+
+    let (mut covered, mut uncovered) = (Vec::new(), Vec::new());
+    for (indx, chunk) in png.chunks().iter().enumerate() {
+        let entry = (indx, chunk.chunk_type().to_string());
+        if is_covered(chunk, &data.types) {
+            covered.push(entry);
+        } else if entry.1 != SIGNATURE_CHUNK_TYPE {
+            uncovered.push(entry);
+        }
+    }
+    Ok(Verification { key_id: data.key_id, covered, uncovered })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_reader::ChunkReader;
+
+    const IMAGE: &[u8] = include_bytes!("../image.png");
+
+    fn types(entries: &[(usize, String)]) -> Vec<&str> {
+        entries.iter().map(|(_, t)| t.as_str()).collect()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = Signer25519::generate();
+        let png = Png::try_from(IMAGE).unwrap();
+        let signed = sign(&png, &signer, &default_signed_types()).unwrap();
+        let verification = verify(&signed, &signer.verifying_key()).unwrap();
+        assert_eq!(types(&verification.covered), ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(types(&verification.uncovered), ["ruSt"]);
+        assert_eq!(verification.key_id, key_id(&signer.verifying_key()));
+
+        // Re-signing replaces the old signature.
+        let resigned = sign(&signed, &signer, &["ruSt".to_string()]).unwrap();
+        assert_eq!(resigned.chunks().len(), signed.chunks().len());
+        assert_eq!(types(&verify(&resigned, &signer.verifying_key()).unwrap().covered), ["IHDR", "IDAT", "IEND", "ruSt"]);
+    }
+
+    #[test]
+    fn test_signature_before_iend() {
+        let signer = Signer25519::generate();
+        let signed = sign(&Png::try_from(IMAGE).unwrap(), &signer, &[]).unwrap();
+        let bytes = signed.as_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let mut types = Vec::new();
+        while let Some(header) = reader.next_header().unwrap() {
+            types.push(header.chunk_type.to_string());
+        }
+        assert_eq!(types, ["IHDR", "IDAT", SIGNATURE_CHUNK_TYPE, "IEND", "ruSt"]);
+    }
+
+    #[test]
+    fn test_modifications_are_detected() {
+        let signer = Signer25519::generate();
+        let png = Png::try_from(IMAGE).unwrap();
+        let mut signed = sign(&png, &signer, &[]).unwrap();
+
+        // Uncovered chunks can change freely.
+        signed.append_chunk(Chunk::from_type_str("teSt", b"added later".to_vec()).unwrap());
+        assert!(verify(&signed, &signer.verifying_key()).is_ok());
+
+        let mut chunks = signed.chunks().to_vec();
+        let idat = chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "IDAT").unwrap();
+        let mut data = chunks[idat].data().to_vec();
+        data[100] ^= 1;
+        chunks[idat] = Chunk::new(chunks[idat].chunk_type(), data);
+        assert!(verify(&Png::from_chunks(chunks), &signer.verifying_key()).is_err());
+
+        assert!(verify(&signed, &Signer25519::generate().verifying_key()).is_err());
+        assert!(verify(&png, &signer.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_key_files() {
+        let signer = Signer25519::generate();
+        let file = signer.to_key_file();
+        assert_eq!(Signer25519::from_key_file(&file).unwrap().verifying_key(), signer.verifying_key());
+        assert_eq!(verifying_key_from_file(&file).unwrap(), signer.verifying_key());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::Result;
+
+/// Chunks that change how the stored samples map to displayed colors.
+pub const COLOR_MANAGEMENT_TYPES: [&str; 8] = ["gAMA", "cHRM", "sRGB", "iCCP", "sBIT", "cICP", "mDCv", "cLLi"];
+
+/// APNG chunks, which only make sense together: frames without acTL are
+/// ignored, and acTL without its frames breaks the animation.
+const ANIMATION_TYPES: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+/// Which ancillary chunks `strip` removes. Critical chunks and tRNS, which
+/// decides what is transparent, are always kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StripProfile {
+    /// Remove ancillary chunks that don't describe the image data: those
+    /// that are safe to copy (text, EXIF, physical size, ...), private
+    /// chunks, tIME, and iCCP, whose embedded profile can be larger than the
+    /// image. Other public chunks that are unsafe to copy, such as the rest
+    /// of color management and animation control, are kept.
+    Default,
+    /// Remove every ancillary chunk.
+    AllAncillary,
+    /// Remove every ancillary chunk except `COLOR_MANAGEMENT_TYPES`.
+    KeepColorManagement,
+    /// Remove every ancillary chunk not listed.
+    Allow(Vec<String>),
+    /// Remove only the listed chunk types. The animation chunks acTL, fcTL
+    /// and fdAT have to be listed together or not at all.
+    Deny(Vec<String>),
+}
+
+impl StripProfile {
+    /// Whether `chunk` survives stripping.
+    pub fn keeps(&self, chunk: &Chunk) -> bool {
+        let chunk_type = chunk.chunk_type();
+        let name = chunk_type.to_string();
+        if chunk_type.is_critical() || name == "tRNS" {
+            return true;
+        }
+        match self {
+            StripProfile::Default => !chunk_type.is_safe_to_copy() && chunk_type.is_public() && !["tIME", "iCCP"].contains(&name.as_str()),
+            StripProfile::AllAncillary => false,
+            StripProfile::KeepColorManagement => COLOR_MANAGEMENT_TYPES.contains(&name.as_str()),
+            StripProfile::Allow(types) => types.contains(&name),
+            StripProfile::Deny(types) => !types.contains(&name),
+        }
+    }
+}
+
+/// What `strip` took out.
+pub struct StripReport {
+    pub removed: Vec<Chunk>,
+}
+
+impl StripReport {
+    /// Bytes the removed chunks took up in the file, headers and CRCs included.
+    pub fn bytes_saved(&self) -> usize {
+        self.removed.iter().map(|chunk| chunk.length() as usize + 12).sum()
+    }
+}
+
+/// A copy of `png` without the chunks `profile` removes.
+pub fn strip(png: &Png, profile: &StripProfile) -> Result<(Png, StripReport)> {
+    if let StripProfile::Deny(types) = profile
+        && let Some(critical) = types.iter().find(|name| name.as_bytes().first().is_some_and(|b| b.is_ascii_uppercase())) {
+        return Err(format!("Refusing to strip critical {} chunks", critical).into());
+    }
+    if let StripProfile::Deny(types) = profile
+        && types.iter().any(|name| name == "tRNS") {
+        return Err("Refusing to strip tRNS, it changes which pixels are transparent".into());
+    }
+    if let StripProfile::Deny(types) = profile {
+        let denied = ANIMATION_TYPES.iter().filter(|name| types.iter().any(|t| t == *name)).count();
+        if denied != 0 && denied != ANIMATION_TYPES.len() {
+            return Err(format!("Refusing to strip only part of an animation, remove {} together", ANIMATION_TYPES.join(", ")).into());
+        }
+    }
+    let (kept, removed): (Vec<Chunk>, Vec<Chunk>) = png.chunks().iter().cloned().partition(|chunk| profile.keeps(chunk));
+    Ok((Png::from_chunks(kept), StripReport { removed }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str) -> Chunk {
+        Chunk::from_type_str(chunk_type, vec![0; 4]).unwrap()
+    }
+
+    fn testing_png() -> Png {
+        let types = ["IHDR", "gAMA", "iCCP", "tEXt", "eXIf", "tIME", "tRNS", "IDAT", "ruSt", "IEND"];
+        Png::from_chunks(types.iter().map(|t| chunk(t)).collect())
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_profiles() {
+        let png = testing_png();
+        let strip_types = |profile| types(&strip(&png, &profile).unwrap().0);
+        assert_eq!(strip_types(StripProfile::Default), ["IHDR", "gAMA", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(strip_types(StripProfile::AllAncillary), ["IHDR", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(strip_types(StripProfile::KeepColorManagement), ["IHDR", "gAMA", "iCCP", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(strip_types(StripProfile::Allow(vec!["tEXt".to_string()])), ["IHDR", "tEXt", "tRNS", "IDAT", "IEND"]);
+        assert_eq!(strip_types(StripProfile::Deny(vec!["ruSt".to_string(), "eXIf".to_string()])),
+            ["IHDR", "gAMA", "iCCP", "tEXt", "tIME", "tRNS", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_report() {
+        let (_, report) = strip(&testing_png(), &StripProfile::AllAncillary).unwrap();
+        assert_eq!(report.removed.len(), 6);
+        assert_eq!(report.bytes_saved(), 6 * 16);
+        assert!(strip(&testing_png(), &StripProfile::Deny(vec!["IDAT".to_string()])).is_err());
+        assert!(strip(&testing_png(), &StripProfile::Deny(vec!["tRNS".to_string()])).is_err());
+    }
+
+    #[test]
+    fn test_animation_chunks_go_together() {
+        let types_of = |names: &[&str]| names.iter().map(|t| t.to_string()).collect::<Vec<String>>();
+        let png = Png::from_chunks(["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"].iter().map(|t| chunk(t)).collect());
+        assert!(strip(&png, &StripProfile::Deny(types_of(&["fcTL"]))).is_err());
+        assert!(strip(&png, &StripProfile::Deny(types_of(&["acTL", "fdAT"]))).is_err());
+        let (stripped, _) = strip(&png, &StripProfile::Deny(types_of(&["fdAT", "acTL", "fcTL"]))).unwrap();
+        assert_eq!(types(&stripped), ["IHDR", "IDAT", "IEND"]);
+    }
+}